@@ -0,0 +1,464 @@
+/* Reader for the subset of SQL written by the `.dump` command of sqlite3 */
+
+/** Literal of an INSERT */
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    /** `X'...'` literal */
+    Blob(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DumpStatement {
+    /** Name of the table with the name and declared type of each column, table constraints are
+     * left out */
+    CreateTable {
+        name: String,
+        columns: Vec<(String, String)>,
+    },
+    /** Rows of an INSERT, `columns` is `None` when the statement lists no columns */
+    Insert {
+        table: String,
+        columns: Option<Vec<String>>,
+        rows: Vec<Vec<DumpValue>>,
+    },
+    /** Any other statement, named by its first keywords in upper case such as `CREATE INDEX` */
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DumpToken {
+    /** Keyword or bare identifier */
+    Word(String),
+    /** Identifier between double quotes, brackets or backticks */
+    Quoted(String),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+    Symbol(char),
+}
+
+/** Column constraints ending the declared type of a column */
+const CONSTRAINTS: [&str; 11] = [
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+fn error(line: usize, message: &str) -> String {
+    format!("Parse error: line {}: {}", line, message)
+}
+
+/** Split a script into tokens, each with the line it starts on */
+fn lex(script: &str) -> Result<Vec<(usize, DumpToken)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = script.chars().peekable();
+    let mut line = 1;
+
+    /* text up to `end`, where a doubled `end` stands for itself */
+    let quoted = |chars: &mut std::iter::Peekable<std::str::Chars>,
+                  line: &mut usize,
+                  end: char|
+     -> Result<String, String> {
+        let start = *line;
+        let mut text = String::new();
+        loop {
+            match chars.next() {
+                Some(c) if c == end && chars.peek() == Some(&end) && end != ']' => {
+                    chars.next();
+                    text.push(c);
+                }
+                Some(c) if c == end => return Ok(text),
+                Some(c) => {
+                    if c == '\n' {
+                        *line += 1;
+                    }
+                    text.push(c);
+                }
+                None => return Err(error(start, "unterminated quote")),
+            }
+        }
+    };
+
+    /* the character after the next one, peeked on a copy so nothing is consumed */
+    let second = |chars: &std::iter::Peekable<std::str::Chars>| {
+        let mut ahead = chars.clone();
+        ahead.next();
+        ahead.next()
+    };
+
+    while let Some(&c) = chars.peek() {
+        let start = line;
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            '-' if second(&chars) == Some('-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            '/' if second(&chars) == Some('*') => {
+                chars.next();
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            last = c;
+                        }
+                        None => return Err(error(start, "unterminated comment")),
+                    }
+                }
+            }
+            '\'' => {
+                chars.next();
+                let text = quoted(&mut chars, &mut line, '\'')?;
+                tokens.push((start, DumpToken::Text(text)));
+            }
+            '"' | '`' | '[' => {
+                chars.next();
+                let end = match c {
+                    '[' => ']',
+                    _ => c,
+                };
+                let name = quoted(&mut chars, &mut line, end)?;
+                tokens.push((start, DumpToken::Quoted(name)));
+            }
+            '0'..='9' | '.' => {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = (c == '+' || c == '-') && text.ends_with(['e', 'E']);
+                    if !c.is_ascii_alphanumeric() && c != '.' && !exponent_sign {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                let token = match text.parse() {
+                    Ok(integer) => DumpToken::Integer(integer),
+                    /* an integer out of range is kept as a real, like sqlite3 does */
+                    Err(_) => DumpToken::Real(
+                        text.parse()
+                            .map_err(|_| error(start, "invalid number literal"))?,
+                    ),
+                };
+                tokens.push((start, token));
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' && c != '$' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if word.eq_ignore_ascii_case("X") && chars.peek() == Some(&'\'') {
+                    chars.next();
+                    let hex = quoted(&mut chars, &mut line, '\'')?;
+                    tokens.push((start, DumpToken::Blob(decode_hex(&hex, start)?)));
+                } else {
+                    tokens.push((start, DumpToken::Word(word)));
+                }
+            }
+            _ => {
+                chars.next();
+                tokens.push((start, DumpToken::Symbol(c)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn decode_hex(hex: &str, line: usize) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(error(line, "invalid blob literal"));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+struct StatementParser<'a> {
+    tokens: &'a [(usize, DumpToken)],
+    ptr: usize,
+    line: usize,
+}
+
+impl StatementParser<'_> {
+    fn peek(&self) -> Option<&DumpToken> {
+        self.tokens.get(self.ptr).map(|(_, token)| token)
+    }
+    fn next(&mut self) -> Option<&DumpToken> {
+        self.ptr += 1;
+        self.tokens.get(self.ptr - 1).map(|(_, token)| token)
+    }
+    fn is_word(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(DumpToken::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+    /** Skip `keyword` if it is next */
+    fn accept(&mut self, keyword: &str) -> bool {
+        let found = self.is_word(keyword);
+        if found {
+            self.ptr += 1;
+        }
+        found
+    }
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.next() {
+            Some(DumpToken::Symbol(c)) if *c == symbol => Ok(()),
+            _ => Err(error(self.line, &format!("expected '{}'", symbol))),
+        }
+    }
+    fn name(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(DumpToken::Word(name) | DumpToken::Quoted(name) | DumpToken::Text(name)) => {
+                Ok(name.clone())
+            }
+            _ => Err(error(self.line, "expected a name")),
+        }
+    }
+    /** Name, possibly qualified by a schema name that is dropped */
+    fn table_name(&mut self) -> Result<String, String> {
+        let name = self.name()?;
+        if self.peek() == Some(&DumpToken::Symbol('.')) {
+            self.ptr += 1;
+            return self.name();
+        }
+        Ok(name)
+    }
+    /** Comma separated names between parentheses */
+    fn names(&mut self) -> Result<Vec<String>, String> {
+        self.expect('(')?;
+        let mut names = vec![self.name()?];
+        while self.peek() == Some(&DumpToken::Symbol(',')) {
+            self.ptr += 1;
+            names.push(self.name()?);
+        }
+        self.expect(')')?;
+        Ok(names)
+    }
+    /** Tokens up to the next top-level ',' or the ')' closing the current list */
+    fn list_item(&mut self) -> Result<&[(usize, DumpToken)], String> {
+        let start = self.ptr;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Some(DumpToken::Symbol('(')) => depth += 1,
+                Some(DumpToken::Symbol(')')) if depth == 0 => break,
+                Some(DumpToken::Symbol(')')) => depth -= 1,
+                Some(DumpToken::Symbol(',')) if depth == 0 => break,
+                Some(_) => {}
+                None => return Err(error(self.line, "expected ')'")),
+            }
+            self.ptr += 1;
+        }
+        Ok(&self.tokens[start..self.ptr])
+    }
+    fn create_table(&mut self) -> Result<DumpStatement, String> {
+        if self.accept("IF") && !(self.accept("NOT") && self.accept("EXISTS")) {
+            return Err(error(self.line, "expected IF NOT EXISTS"));
+        }
+        let name = self.table_name()?;
+        self.expect('(')?;
+        let mut columns = Vec::new();
+        loop {
+            let item = self.list_item()?;
+            let words: Vec<_> = item
+                .iter()
+                .map(|(_, token)| match token {
+                    DumpToken::Word(word) => Some(word.to_ascii_uppercase()),
+                    _ => None,
+                })
+                .collect();
+            let is_constraint = matches!(
+                words.first(),
+                Some(Some(word)) if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+                    .contains(&word.as_str())
+            );
+            if !is_constraint {
+                let column = match item.first() {
+                    Some((_, DumpToken::Word(name) | DumpToken::Quoted(name))) => name.clone(),
+                    _ => return Err(error(self.line, "expected a column name")),
+                };
+                /* the words of the type, a size such as VARCHAR(20) is dropped */
+                let declared: Vec<_> = words[1..]
+                    .iter()
+                    .map_while(|word| word.clone())
+                    .take_while(|word| !CONSTRAINTS.contains(&word.as_str()))
+                    .collect();
+                columns.push((column, declared.join(" ")));
+            }
+            match self.next() {
+                Some(DumpToken::Symbol(',')) => {}
+                _ => break,
+            }
+        }
+        Ok(DumpStatement::CreateTable { name, columns })
+    }
+    fn value(&mut self) -> Result<DumpValue, String> {
+        let negative = match self.peek() {
+            Some(DumpToken::Symbol(sign @ ('-' | '+'))) => {
+                let negative = *sign == '-';
+                self.ptr += 1;
+                Some(negative)
+            }
+            _ => None,
+        };
+        let line = self.line;
+        Ok(match (self.next(), negative) {
+            (Some(DumpToken::Integer(integer)), Some(true)) => DumpValue::Integer(-integer),
+            (Some(DumpToken::Integer(integer)), _) => DumpValue::Integer(*integer),
+            (Some(DumpToken::Real(real)), Some(true)) => DumpValue::Real(-real),
+            (Some(DumpToken::Real(real)), _) => DumpValue::Real(*real),
+            (Some(DumpToken::Text(text)), None) => DumpValue::Text(text.clone()),
+            (Some(DumpToken::Blob(blob)), None) => DumpValue::Blob(blob.clone()),
+            (Some(DumpToken::Word(word)), None) if word.eq_ignore_ascii_case("NULL") => {
+                DumpValue::Null
+            }
+            _ => return Err(error(line, "expected a literal")),
+        })
+    }
+    fn insert(&mut self) -> Result<DumpStatement, String> {
+        /* INSERT OR REPLACE and the like */
+        if self.accept("OR") {
+            self.name()?;
+        }
+        if !self.accept("INTO") {
+            return Err(error(self.line, "expected INTO"));
+        }
+        let table = self.table_name()?;
+        let columns = match self.peek() {
+            Some(DumpToken::Symbol('(')) => Some(self.names()?),
+            _ => None,
+        };
+        if !self.accept("VALUES") {
+            return Err(error(self.line, "expected VALUES"));
+        }
+        let mut rows = Vec::new();
+        loop {
+            self.expect('(')?;
+            let mut row = vec![self.value()?];
+            while self.peek() == Some(&DumpToken::Symbol(',')) {
+                self.ptr += 1;
+                row.push(self.value()?);
+            }
+            self.expect(')')?;
+            rows.push(row);
+            if self.peek() != Some(&DumpToken::Symbol(',')) {
+                break;
+            }
+            self.ptr += 1;
+        }
+        Ok(DumpStatement::Insert {
+            table,
+            columns,
+            rows,
+        })
+    }
+    fn statement(&mut self) -> Result<DumpStatement, String> {
+        let keyword = match self.next() {
+            Some(DumpToken::Word(word)) => word.to_ascii_uppercase(),
+            _ => return Err(error(self.line, "expected a statement")),
+        };
+        let statement = match keyword.as_str() {
+            "CREATE" if self.accept("TABLE") => self.create_table()?,
+            "CREATE" => {
+                let mut name = keyword;
+                while let Some(DumpToken::Word(word)) = self.peek() {
+                    name = name + " " + &word.to_ascii_uppercase();
+                    /* CREATE UNIQUE INDEX, CREATE TEMP TRIGGER */
+                    if !["UNIQUE", "TEMP", "TEMPORARY", "VIRTUAL"].contains(&&name[7..]) {
+                        break;
+                    }
+                    self.ptr += 1;
+                }
+                return Ok(DumpStatement::Other(name));
+            }
+            "INSERT" => self.insert()?,
+            _ => return Ok(DumpStatement::Other(keyword)),
+        };
+        if self.ptr != self.tokens.len() {
+            return Err(error(
+                self.line,
+                "unexpected token at the end of the statement",
+            ));
+        }
+        Ok(statement)
+    }
+}
+
+/** Parse a dump into its statements, each with the line it starts on */
+pub fn parse_dump(script: &str) -> Result<Vec<(usize, DumpStatement)>, String> {
+    let tokens = lex(script)?;
+    let mut statements = Vec::new();
+    for tokens in tokens.split(|(_, token)| *token == DumpToken::Symbol(';')) {
+        let Some(&(line, _)) = tokens.first() else {
+            continue;
+        };
+        let mut parser = StatementParser {
+            tokens,
+            ptr: 0,
+            line,
+        };
+        statements.push((line, parser.statement()?));
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_number_before_multibyte_text() {
+        let statements = parse_dump(
+            "INSERT INTO t VALUES(-1,'ééééé'); -- dernière ligne\n/* fin */ SELECT 1/'à';",
+        )
+        .unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            statements[0],
+            (
+                1,
+                DumpStatement::Insert {
+                    table: "t".to_owned(),
+                    columns: None,
+                    rows: vec![vec![
+                        DumpValue::Integer(-1),
+                        DumpValue::Text("ééééé".to_owned())
+                    ]],
+                }
+            )
+        );
+        assert_eq!(
+            statements[1],
+            (2, DumpStatement::Other("SELECT".to_owned()))
+        );
+    }
+}
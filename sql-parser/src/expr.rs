@@ -0,0 +1,540 @@
+use crate::function::Functions;
+use crate::limits::Limits;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Number(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Literal {
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "NULL",
+            Self::Number(_) => "Number",
+            Self::Float(_) => "Float",
+            Self::String(_) => "Text",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Concat => "||",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Column(String),
+    /** `*` as a whole projection item */
+    Wildcard,
+    Neg(Box<Expr>),
+    /** Call of a scalar function */
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+/** Renders the expression as SQL, which is also its generated output column name */
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Literal(Literal::Null) => f.write_str("NULL"),
+            Self::Literal(Literal::Number(number)) => write!(f, "{}", number),
+            Self::Literal(Literal::Float(float)) => write!(f, "{:?}", float),
+            Self::Literal(Literal::String(string)) => write!(f, "'{}'", string.replace('\'', "''")),
+            Self::Column(name) => f.write_str(name),
+            Self::Wildcard => f.write_str("*"),
+            Self::Neg(expr) => match **expr {
+                Self::Binary { .. } => write!(f, "-({})", expr),
+                _ => write!(f, "-{}", expr),
+            },
+            Self::Call { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            Self::Binary { op, left, right } => {
+                for (i, operand) in [left, right].into_iter().enumerate() {
+                    if i == 1 {
+                        write!(f, " {} ", op)?;
+                    }
+                    match **operand {
+                        Self::Binary { .. } => write!(f, "({})", operand)?,
+                        _ => write!(f, "{}", operand)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ExprToken {
+    Number(i64),
+    Float(f64),
+    String(String),
+    Ident(String),
+    Op(BinaryOp),
+    LeftParen,
+    RightParen,
+    Comma,
+    Equals,
+}
+
+/** Characters of the code being lexed with the byte offset of the next one */
+struct CodeChars<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    offset: usize,
+}
+
+impl CodeChars<'_> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        Some(c)
+    }
+}
+
+fn lex(code: &str) -> Result<Vec<ExprToken>, &'static str> {
+    Ok(lex_until(code, None)?.0)
+}
+
+/** Byte offset of the first `keyword` in `code` outside string literals, compared ignoring case
+ *
+ * The code before it is lexed as an expression, so a character that cannot start a token fails
+ * the search. */
+pub fn find_keyword(code: &str, keyword: &str) -> Result<Option<usize>, &'static str> {
+    Ok(lex_until(code, Some(keyword))?.1)
+}
+
+/** Lex `code` up to the identifier `stop`, return the tokens before it and its byte offset */
+fn lex_until(
+    code: &str,
+    stop: Option<&str>,
+) -> Result<(Vec<ExprToken>, Option<usize>), &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = CodeChars {
+        chars: code.chars().peekable(),
+        offset: 0,
+    };
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                chars.next();
+            }
+            '0'..='9' => {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() && c != '.' {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                if text.contains('.') {
+                    tokens.push(ExprToken::Float(
+                        text.parse()
+                            .map_err(|_| "Parse error: invalid number literal")?,
+                    ));
+                } else {
+                    tokens.push(ExprToken::Number(
+                        text.parse()
+                            .map_err(|_| "Parse error: number literal out of range")?,
+                    ));
+                }
+            }
+            'A'..='Z' | 'a'..='z' | '_' => {
+                let start = chars.offset;
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                if stop.is_some_and(|stop| ident.eq_ignore_ascii_case(stop)) {
+                    return Ok((tokens, Some(start)));
+                }
+                tokens.push(ExprToken::Ident(ident));
+            }
+            '\'' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        /* '' is an escaped quote */
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            string.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => string.push(c),
+                        None => return Err("Parse error: unterminated string literal"),
+                    }
+                }
+                tokens.push(ExprToken::String(string));
+            }
+            '|' => {
+                chars.next();
+                if chars.next() != Some('|') {
+                    return Err("Parse error: expected '||'");
+                }
+                tokens.push(ExprToken::Op(BinaryOp::Concat));
+            }
+            _ => {
+                tokens.push(match c {
+                    '+' => ExprToken::Op(BinaryOp::Add),
+                    '-' => ExprToken::Op(BinaryOp::Sub),
+                    '*' => ExprToken::Op(BinaryOp::Mul),
+                    '/' => ExprToken::Op(BinaryOp::Div),
+                    '%' => ExprToken::Op(BinaryOp::Rem),
+                    '(' => ExprToken::LeftParen,
+                    ')' => ExprToken::RightParen,
+                    ',' => ExprToken::Comma,
+                    '=' => ExprToken::Equals,
+                    _ => return Err("Parse error: unexpected character in expression"),
+                });
+                chars.next();
+            }
+        }
+    }
+    Ok((tokens, None))
+}
+
+struct Parser<'a> {
+    tokens: Vec<ExprToken>,
+    ptr: usize,
+    depth: usize,
+    limits: &'a Limits,
+}
+
+impl<'a> Parser<'a> {
+    fn new(code: &str, limits: &'a Limits) -> Result<Self, &'static str> {
+        Ok(Self {
+            tokens: lex(code)?,
+            ptr: 0,
+            depth: 0,
+            limits,
+        })
+    }
+    /** Enter a nested production */
+    fn enter(&mut self) -> Result<(), &'static str> {
+        self.depth += 1;
+        if self.depth > self.limits.max_expr_depth {
+            return Err("Parse error: expression nested too deeply");
+        }
+        Ok(())
+    }
+    fn peek(&self) -> Option<&ExprToken> {
+        self.tokens.get(self.ptr)
+    }
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+    /** expr := term (('+' | '-') term)* */
+    fn expr(&mut self) -> Result<Expr, &'static str> {
+        self.enter()?;
+        let mut left = self.term()?;
+        while let Some(&ExprToken::Op(op @ (BinaryOp::Add | BinaryOp::Sub))) = self.peek() {
+            self.ptr += 1;
+            left = Self::binary(op, left, self.term()?);
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+    /** term := concat (('*' | '/' | '%') concat)* */
+    fn term(&mut self) -> Result<Expr, &'static str> {
+        let mut left = self.concat()?;
+        while let Some(&ExprToken::Op(op @ (BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem))) =
+            self.peek()
+        {
+            self.ptr += 1;
+            left = Self::binary(op, left, self.concat()?);
+        }
+        Ok(left)
+    }
+    /** concat := unary ('||' unary)* */
+    fn concat(&mut self) -> Result<Expr, &'static str> {
+        let mut left = self.unary()?;
+        while let Some(&ExprToken::Op(BinaryOp::Concat)) = self.peek() {
+            self.ptr += 1;
+            left = Self::binary(BinaryOp::Concat, left, self.unary()?);
+        }
+        Ok(left)
+    }
+    /** unary := '-' unary | factor */
+    fn unary(&mut self) -> Result<Expr, &'static str> {
+        if let Some(&ExprToken::Op(BinaryOp::Sub)) = self.peek() {
+            self.ptr += 1;
+            self.enter()?;
+            let expr = Expr::Neg(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.factor()
+    }
+    /** factor := number | string | NULL | call | column | '(' expr ')' */
+    fn factor(&mut self) -> Result<Expr, &'static str> {
+        let token = self.tokens.get(self.ptr);
+        self.ptr += 1;
+        match token {
+            Some(ExprToken::Number(number)) => Ok(Expr::Literal(Literal::Number(*number))),
+            Some(ExprToken::Float(float)) => Ok(Expr::Literal(Literal::Float(*float))),
+            Some(ExprToken::String(string)) => Ok(Expr::Literal(Literal::String(string.clone()))),
+            Some(ExprToken::Ident(name)) if name.eq_ignore_ascii_case("NULL") => {
+                Ok(Expr::Literal(Literal::Null))
+            }
+            Some(ExprToken::Ident(name)) if self.peek() == Some(&ExprToken::LeftParen) => {
+                let name = name.clone();
+                self.ptr += 1;
+                self.call(name)
+            }
+            Some(ExprToken::Ident(name)) => Ok(Expr::Column(name.clone())),
+            Some(ExprToken::LeftParen) => {
+                let expr = self.expr()?;
+                if self.peek() != Some(&ExprToken::RightParen) {
+                    return Err("Parse error: expected ')'");
+                }
+                self.ptr += 1;
+                Ok(expr)
+            }
+            _ => Err("Parse error: expected expression"),
+        }
+    }
+    /** call := ident '(' [expr (',' expr)*] ')', with the name and '(' already consumed */
+    fn call(&mut self, name: String) -> Result<Expr, &'static str> {
+        let mut args = Vec::new();
+        if self.peek() != Some(&ExprToken::RightParen) {
+            args.push(self.expr()?);
+            while self.peek() == Some(&ExprToken::Comma) {
+                self.ptr += 1;
+                args.push(self.expr()?);
+            }
+        }
+        if self.peek() != Some(&ExprToken::RightParen) {
+            return Err("Parse error: expected ')' after function arguments");
+        }
+        self.ptr += 1;
+        Ok(Expr::Call { name, args })
+    }
+    /** item := '*' | expr */
+    fn item(&mut self) -> Result<Expr, &'static str> {
+        if self.peek() == Some(&ExprToken::Op(BinaryOp::Mul))
+            && matches!(self.tokens.get(self.ptr + 1), None | Some(ExprToken::Comma))
+        {
+            self.ptr += 1;
+            return Ok(Expr::Wildcard);
+        }
+        self.expr()
+    }
+    fn end(&self) -> Result<(), &'static str> {
+        if self.peek().is_some() {
+            return Err("Parse error: unexpected token after expression");
+        }
+        Ok(())
+    }
+    fn check_columns(&self, count: usize) -> Result<(), &'static str> {
+        if count > self.limits.max_columns {
+            return Err("Parse error: too many columns in statement");
+        }
+        Ok(())
+    }
+}
+
+/** Parse a comma separated list of projection items */
+pub fn parse_list(code: &str, limits: &Limits) -> Result<Vec<Expr>, &'static str> {
+    let mut parser = Parser::new(code, limits)?;
+    let mut exprs = vec![parser.item()?];
+    while parser.peek() == Some(&ExprToken::Comma) {
+        parser.ptr += 1;
+        parser.check_columns(exprs.len() + 1)?;
+        exprs.push(parser.item()?);
+    }
+    parser.end()?;
+    Ok(exprs)
+}
+
+/** Parse a comma separated list of `column = expr` assignments */
+pub fn parse_assignments(code: &str, limits: &Limits) -> Result<Vec<(String, Expr)>, &'static str> {
+    let mut parser = Parser::new(code, limits)?;
+    let mut assignments = Vec::new();
+    loop {
+        let column = match parser.tokens.get(parser.ptr) {
+            Some(ExprToken::Ident(name)) => name.clone(),
+            _ => return Err("Parse error: expected column name"),
+        };
+        parser.ptr += 1;
+        if parser.peek() != Some(&ExprToken::Equals) {
+            return Err("Parse error: expected '='");
+        }
+        parser.ptr += 1;
+        parser.check_columns(assignments.len() + 1)?;
+        assignments.push((column, parser.expr()?));
+
+        if parser.peek() != Some(&ExprToken::Comma) {
+            break;
+        }
+        parser.ptr += 1;
+    }
+    parser.end()?;
+    Ok(assignments)
+}
+
+/** Describe an operand in a type error, naming the column it came from */
+fn describe(expr: &Expr, value: &Literal) -> String {
+    match expr {
+        Expr::Column(name) => format!("column {} of type {}", name, value.type_name()),
+        _ => value.type_name().to_owned(),
+    }
+}
+
+/** Apply an arithmetic operator to two numeric values, promoting Number to Float when mixed
+ *
+ * Return `None` on integer overflow. */
+fn arithmetic(op: BinaryOp, left: Literal, right: Literal) -> Option<Literal> {
+    let float = |left: f64, right: f64| match op {
+        BinaryOp::Add => left + right,
+        BinaryOp::Sub => left - right,
+        BinaryOp::Mul => left * right,
+        BinaryOp::Div => left / right,
+        BinaryOp::Rem => left % right,
+        BinaryOp::Concat => unreachable!(),
+    };
+    match (left, right) {
+        (Literal::Number(left), Literal::Number(right)) => match op {
+            BinaryOp::Add => left.checked_add(right),
+            BinaryOp::Sub => left.checked_sub(right),
+            BinaryOp::Mul => left.checked_mul(right),
+            BinaryOp::Div => left.checked_div(right),
+            BinaryOp::Rem => left.checked_rem(right),
+            BinaryOp::Concat => unreachable!(),
+        }
+        .map(Literal::Number),
+        (Literal::Number(left), Literal::Float(right)) => {
+            Some(Literal::Float(float(left as f64, right)))
+        }
+        (Literal::Float(left), Literal::Number(right)) => {
+            Some(Literal::Float(float(left, right as f64)))
+        }
+        (Literal::Float(left), Literal::Float(right)) => Some(Literal::Float(float(left, right))),
+        _ => unreachable!(),
+    }
+}
+
+impl Expr {
+    /** Evaluate an expression that references no table */
+    pub fn eval(&self) -> Result<Literal, String> {
+        self.eval_with(&|name| Err(format!("Execution error: no such column {}", name)))
+    }
+    /** Evaluate an expression with the built-in functions, resolving column references through
+     * `column` */
+    pub fn eval_with<F>(&self, column: &F) -> Result<Literal, String>
+    where
+        F: Fn(&str) -> Result<Literal, String>,
+    {
+        self.eval_in(&Functions::default(), column)
+    }
+    /** Evaluate an expression, calling functions from `functions` and resolving column references
+     * through `column` */
+    pub fn eval_in<F>(&self, functions: &Functions, column: &F) -> Result<Literal, String>
+    where
+        F: Fn(&str) -> Result<Literal, String>,
+    {
+        match self {
+            Self::Literal(literal) => Ok(literal.clone()),
+            Self::Column(name) => column(name),
+            Self::Wildcard => Err("Execution error: '*' is not a value".to_owned()),
+            Self::Call { name, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_in(functions, column))
+                    .collect::<Result<Vec<_>, _>>()?;
+                functions.call(name, &args)
+            }
+            Self::Neg(expr) => match expr.eval_in(functions, column)? {
+                Literal::Null => Ok(Literal::Null),
+                Literal::Number(number) => number
+                    .checked_neg()
+                    .map(Literal::Number)
+                    .ok_or_else(|| "Execution error: integer overflow".to_owned()),
+                Literal::Float(float) => Ok(Literal::Float(-float)),
+                value => Err(format!(
+                    "Execution error: cannot apply unary '-' to {}",
+                    describe(expr, &value)
+                )),
+            },
+            Self::Binary { op, left, right } => {
+                let left_value = left.eval_in(functions, column)?;
+                let right_value = right.eval_in(functions, column)?;
+                match (op, &left_value, &right_value) {
+                    (_, Literal::Null, _) | (_, _, Literal::Null) => Ok(Literal::Null),
+                    (BinaryOp::Concat, Literal::String(l), Literal::String(r)) => {
+                        Ok(Literal::String(format!("{}{}", l, r)))
+                    }
+                    (BinaryOp::Concat, _, _)
+                    | (_, Literal::String(_), _)
+                    | (_, _, Literal::String(_)) => Err(format!(
+                        "Execution error: cannot apply '{}' to {} and {}",
+                        op,
+                        describe(left, &left_value),
+                        describe(right, &right_value)
+                    )),
+                    (BinaryOp::Div | BinaryOp::Rem, _, Literal::Number(0)) => {
+                        Err("Execution error: division by zero".to_owned())
+                    }
+                    (BinaryOp::Div | BinaryOp::Rem, _, Literal::Float(divisor))
+                        if *divisor == 0.0 =>
+                    {
+                        Err("Execution error: division by zero".to_owned())
+                    }
+                    _ => arithmetic(*op, left_value, right_value)
+                        .ok_or_else(|| "Execution error: integer overflow".to_owned()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::expr::Literal;
+use std::collections::HashMap;
+
+/** Implementation of a scalar function over its evaluated arguments
+ *
+ * An error is reported with the function name prepended, so it only needs to describe the
+ * problem, such as the position of a bad argument. */
+pub type ScalarFunction = Box<dyn Fn(&[Literal]) -> Result<Literal, String>>;
+
+struct Function {
+    /** Number of arguments, `None` for variadic */
+    arity: Option<usize>,
+    function: ScalarFunction,
+}
+
+/** Registry of scalar functions callable from expressions, keyed by case-insensitive name */
+pub struct Functions {
+    functions: HashMap<String, Function>,
+}
+
+impl Default for Functions {
+    /** Registry holding the built-in functions */
+    fn default() -> Self {
+        let mut functions = Self {
+            functions: HashMap::new(),
+        };
+        functions.create_function("LENGTH", Some(1), Box::new(length));
+        functions.create_function("UPPER", Some(1), Box::new(|args| ascii_case(args, true)));
+        functions.create_function("LOWER", Some(1), Box::new(|args| ascii_case(args, false)));
+        functions.create_function("ABS", Some(1), Box::new(abs));
+        functions.create_function("COALESCE", None, Box::new(coalesce));
+        functions.create_function("SUBSTR", None, Box::new(substr));
+        functions
+    }
+}
+
+impl Functions {
+    /** Register a scalar function, replacing any function of the same name
+     *
+     * `arity` is the number of arguments it takes, `None` accepts any number. */
+    pub fn create_function(&mut self, name: &str, arity: Option<usize>, function: ScalarFunction) {
+        self.functions
+            .insert(name.to_ascii_uppercase(), Function { arity, function });
+    }
+    /** Call a function by name */
+    pub fn call(&self, name: &str, args: &[Literal]) -> Result<Literal, String> {
+        let function = self
+            .functions
+            .get(&name.to_ascii_uppercase())
+            .ok_or_else(|| format!("Execution error: no such function {}", name))?;
+        if let Some(arity) = function.arity {
+            if args.len() != arity {
+                return Err(format!(
+                    "Execution error: {} takes {} arguments but {} were given",
+                    name.to_ascii_uppercase(),
+                    arity,
+                    args.len()
+                ));
+            }
+        }
+        (function.function)(args)
+            .map_err(|err| format!("Execution error: {}: {}", name.to_ascii_uppercase(), err))
+    }
+}
+
+fn type_error(position: usize, expected: &str, value: &Literal) -> String {
+    format!(
+        "argument {} must be {}, not {}",
+        position,
+        expected,
+        value.type_name()
+    )
+}
+
+/** Length of a text in characters */
+fn length(args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Null => Ok(Literal::Null),
+        Literal::String(string) => Ok(Literal::Number(string.chars().count() as i64)),
+        value => Err(type_error(1, "Text", value)),
+    }
+}
+
+/** Change the case of ASCII letters, other characters are kept as they are */
+fn ascii_case(args: &[Literal], upper: bool) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Null => Ok(Literal::Null),
+        Literal::String(string) if upper => Ok(Literal::String(string.to_ascii_uppercase())),
+        Literal::String(string) => Ok(Literal::String(string.to_ascii_lowercase())),
+        value => Err(type_error(1, "Text", value)),
+    }
+}
+
+fn abs(args: &[Literal]) -> Result<Literal, String> {
+    match &args[0] {
+        Literal::Null => Ok(Literal::Null),
+        Literal::Number(number) => number
+            .checked_abs()
+            .map(Literal::Number)
+            .ok_or_else(|| "integer overflow".to_owned()),
+        Literal::Float(float) => Ok(Literal::Float(float.abs())),
+        value => Err(type_error(1, "Number or Float", value)),
+    }
+}
+
+/** First argument that is not NULL */
+fn coalesce(args: &[Literal]) -> Result<Literal, String> {
+    Ok(args
+        .iter()
+        .find(|arg| **arg != Literal::Null)
+        .cloned()
+        .unwrap_or(Literal::Null))
+}
+
+/** SUBSTR(text, start[, length]) with a 1-based `start` in characters
+ *
+ * The result is the range of positions from `start` up to `start + length`, or to the end of the
+ * text without `length`. A negative `start` counts from the end of the text, -1 being the last
+ * character. As in SQLite, the range is clipped to the text afterwards, so the part of it before
+ * position 1 is dropped along with its length: `SUBSTR('abc', 0, 2)` is `'a'`, and an offset past
+ * the end gives an empty text. */
+fn substr(args: &[Literal]) -> Result<Literal, String> {
+    if !(2..=3).contains(&args.len()) {
+        return Err(format!(
+            "takes 2 or 3 arguments but {} were given",
+            args.len()
+        ));
+    }
+    if args.contains(&Literal::Null) {
+        return Ok(Literal::Null);
+    }
+    let string = match &args[0] {
+        Literal::String(string) => string,
+        value => return Err(type_error(1, "Text", value)),
+    };
+    let char_count = string.chars().count() as i64;
+    let start = match args[1] {
+        Literal::Number(start) if start >= 0 => start,
+        Literal::Number(start) => char_count.saturating_add(start).saturating_add(1),
+        ref value => return Err(type_error(2, "Number", value)),
+    };
+    let end = match args.get(2) {
+        None => char_count + 1,
+        Some(Literal::Number(length)) if *length >= 0 => start.saturating_add(*length),
+        Some(Literal::Number(_)) => return Err("argument 3 must not be negative".to_owned()),
+        Some(value) => return Err(type_error(3, "Number", value)),
+    };
+    /* clipped to positions 1 to char_count */
+    let start = start.max(1);
+    let end = end.min(char_count + 1);
+    Ok(Literal::String(
+        string
+            .chars()
+            .skip(start as usize - 1)
+            .take(end.saturating_sub(start).max(0) as usize)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substr_of(text: &str, start: i64, length: Option<i64>) -> Literal {
+        let mut args = vec![Literal::String(text.to_owned()), Literal::Number(start)];
+        args.extend(length.map(Literal::Number));
+        substr(&args).unwrap()
+    }
+
+    #[test]
+    fn substr_matches_sqlite() {
+        for (start, length, expected) in [
+            (1, Some(2), "ab"),
+            (2, None, "bc"),
+            (0, Some(2), "a"),
+            (0, None, "abc"),
+            (0, Some(1), ""),
+            (-1, None, "c"),
+            (-2, Some(1), "b"),
+            (-3, Some(2), "ab"),
+            (-5, Some(3), "a"),
+            (-5, Some(2), ""),
+            (-5, None, "abc"),
+            (3, Some(5), "c"),
+            (4, Some(1), ""),
+            (9, None, ""),
+            (2, Some(0), ""),
+            (i64::MIN, Some(i64::MAX), "ab"),
+            (i64::MIN, None, "abc"),
+            (1, Some(i64::MAX), "abc"),
+        ] {
+            assert_eq!(
+                substr_of("abc", start, length),
+                Literal::String(expected.to_owned()),
+                "SUBSTR('abc', {}, {:?})",
+                start,
+                length
+            );
+        }
+        assert_eq!(
+            substr_of("héllo", 2, Some(3)),
+            Literal::String("éll".to_owned())
+        );
+        assert!(substr(&[
+            Literal::String("abc".to_owned()),
+            Literal::Number(1),
+            Literal::Number(-1)
+        ])
+        .is_err());
+    }
+}
@@ -1 +1,5 @@
+pub mod dump;
+pub mod expr;
+pub mod function;
+pub mod limits;
 pub mod token;
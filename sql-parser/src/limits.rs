@@ -0,0 +1,21 @@
+/** Bounds checked while parsing, so absurd inputs fail with an error instead of exhausting the
+ * stack or memory */
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /** Maximum length of a statement in bytes */
+    pub max_statement_len: usize,
+    /** Maximum nesting depth of parentheses and unary operators in an expression */
+    pub max_expr_depth: usize,
+    /** Maximum number of projected columns or assignments in a statement */
+    pub max_columns: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_statement_len: 1024 * 1024,
+            max_expr_depth: 256,
+            max_columns: 2000,
+        }
+    }
+}
@@ -1,3 +1,7 @@
+use crate::expr::{self, Expr, Literal};
+use crate::function::Functions;
+use crate::limits::Limits;
+
 #[derive(Debug)]
 pub struct Where {
     pub key: String,
@@ -16,9 +20,39 @@ impl Where {
 #[derive(Debug)]
 pub enum Keywords {
     Select {
+        columns: Vec<Expr>,
         from: String,
         r#where: Option<Where>,
     },
+    Update {
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        r#where: Option<Where>,
+    },
+    /** SELECT without FROM, projecting a list of expressions */
+    SelectExprs(Vec<Expr>),
+}
+
+impl Keywords {
+    /** Execute a statement that does not touch storage
+     *
+     * Return the single synthesized row of a select without FROM. */
+    pub fn eval_row(&self) -> Option<Result<Vec<Literal>, String>> {
+        self.eval_row_in(&Functions::default())
+    }
+    /** Like `eval_row`, calling functions from `functions` */
+    pub fn eval_row_in(&self, functions: &Functions) -> Option<Result<Vec<Literal>, String>> {
+        let fail = |name: &str| Err(format!("Execution error: no such column {}", name));
+        match self {
+            Self::SelectExprs(exprs) => Some(
+                exprs
+                    .iter()
+                    .map(|expr| expr.eval_in(functions, &fail))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -27,14 +61,26 @@ pub enum Token {
     String(String),
 }
 
+/** Byte offset of a token split from `code` */
+fn offset_of(code: &str, token: &str) -> usize {
+    token.as_ptr() as usize - code.as_ptr() as usize
+}
+
 pub fn tokenize(code: &str) -> Result<Vec<Token>, &str> {
+    tokenize_with_limits(code, &Limits::default())
+}
+
+pub fn tokenize_with_limits<'a>(code: &'a str, limits: &Limits) -> Result<Vec<Token>, &'a str> {
+    if code.len() > limits.max_statement_len {
+        return Err("Parse error: statement too long");
+    }
     let mut tokens = Vec::new();
-    let mut tokens_str: Vec<&str> = code.split(' ').into_iter().collect();
+    let mut tokens_str: Vec<&str> = code.split(' ').collect();
 
     {
         let mut i = 0;
         while i < tokens_str.len() {
-            if tokens_str[i] == "" {
+            if tokens_str[i].is_empty() {
                 tokens_str.remove(i);
                 i -= 1;
             }
@@ -43,20 +89,111 @@ pub fn tokenize(code: &str) -> Result<Vec<Token>, &str> {
     }
 
     for (i, token) in tokens_str.iter().enumerate() {
-        if token.to_ascii_uppercase() == "SELECT" {
-            if i + 2 >= tokens_str.len() || tokens_str[i + 2].to_ascii_uppercase() != "FROM" {
+        if token.eq_ignore_ascii_case("SELECT") {
+            let start = offset_of(code, token) + token.len();
+            /* a FROM inside a string literal is part of the projection */
+            let Some(from) = expr::find_keyword(&code[start..], "FROM")? else {
+                /* no FROM, the rest of the statement is the projection */
+                tokens.push(Token::Keywords(Keywords::SelectExprs(expr::parse_list(
+                    &code[start..],
+                    limits,
+                )?)));
+                break;
+            };
+            let columns = expr::parse_list(&code[start..start + from], limits)?;
+            let rest: Vec<&str> = code[start + from + "FROM".len()..]
+                .split(' ')
+                .filter(|token| !token.is_empty())
+                .collect();
+            if rest.is_empty() {
                 return Err("Parse error: no tables specified");
             }
 
             let mut r#where = None;
-            if i + 4 < tokens_str.len() && tokens_str[i + 4].to_ascii_uppercase() == "WHERE" {
-                r#where = Some(Where::parse(tokens_str[i + 5]));
+            if rest.len() > 2 && rest[1].eq_ignore_ascii_case("WHERE") {
+                r#where = Some(Where::parse(rest[2]));
             }
             tokens.push(Token::Keywords(Keywords::Select {
-                from: tokens_str[i + 3].to_owned(),
+                columns,
+                from: rest[0].to_owned(),
+                r#where,
+            }));
+            /* the words after SELECT may come from string literals */
+            break;
+        } else if token.eq_ignore_ascii_case("UPDATE") {
+            if i + 2 >= tokens_str.len() || !tokens_str[i + 2].eq_ignore_ascii_case("SET") {
+                return Err("Parse error: expected SET");
+            }
+            let where_index = tokens_str[i + 3..]
+                .iter()
+                .position(|token| token.eq_ignore_ascii_case("WHERE"))
+                .map(|index| i + 3 + index);
+            let start = offset_of(code, tokens_str[i + 2]) + tokens_str[i + 2].len();
+            let end = match where_index {
+                Some(index) => offset_of(code, tokens_str[index]),
+                None => code.len(),
+            };
+
+            let mut r#where = None;
+            if let Some(index) = where_index {
+                if index + 1 >= tokens_str.len() {
+                    return Err("Parse error: expected condition after WHERE");
+                }
+                r#where = Some(Where::parse(tokens_str[index + 1]));
+            }
+            tokens.push(Token::Keywords(Keywords::Update {
+                table: tokens_str[i + 1].to_owned(),
+                assignments: expr::parse_assignments(&code[start..end], limits)?,
                 r#where,
             }));
         }
     }
     Ok(tokens)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(code: &str) -> (Vec<Expr>, String, Option<Where>) {
+        match tokenize(code).unwrap().pop() {
+            Some(Token::Keywords(Keywords::Select {
+                columns,
+                from,
+                r#where,
+            })) => (columns, from, r#where),
+            token => panic!("{:?}", token),
+        }
+    }
+
+    #[test]
+    fn from_inside_a_literal() {
+        let tokens = tokenize("SELECT 'a from b'").unwrap();
+        assert_eq!(tokens.len(), 1);
+        let Token::Keywords(keywords) = &tokens[0] else {
+            panic!("{:?}", tokens[0]);
+        };
+        assert_eq!(
+            keywords.eval_row().unwrap().unwrap(),
+            [Literal::String("a from b".to_owned())]
+        );
+
+        let (columns, from, r#where) = select("SELECT 'x FROM y', name FROM t WHERE id=1");
+        assert_eq!(columns.len(), 2);
+        assert_eq!(
+            columns[0].eval().unwrap(),
+            Literal::String("x FROM y".to_owned())
+        );
+        assert_eq!(from, "t");
+        let r#where = r#where.unwrap();
+        assert_eq!((r#where.key.as_str(), r#where.value.as_str()), ("id", "1"));
+
+        let (_, from, r#where) = select("select a  from  t");
+        assert_eq!(from, "t");
+        assert!(r#where.is_none());
+        assert_eq!(
+            tokenize("SELECT a FROM").unwrap_err(),
+            "Parse error: no tables specified"
+        );
+    }
+}
@@ -1,15 +1,38 @@
+use crate::codec::*;
 use crate::page::*;
 use std::io::{Read, Result as IOResult, Seek, Write};
+use std::ops::Range;
 
-const MAX_IDS: usize = PAGE_SIZE / (8 + 8) - 1;
 const UNIT_SIZE: usize = 8 + 8;
+/** Node type, count of ids and `record_count` */
+const HEADER_SIZE: usize = UNIT_SIZE;
+/** `next_rowid`, padded to a unit */
+const TAIL_SIZE: usize = UNIT_SIZE;
+const NEXT_ROWID_OFFSET: usize = PAGE_SIZE - TAIL_SIZE;
+/** Ids fitting between the header and the tail, a node holding this many is parted */
+const MAX_IDS: usize = (PAGE_SIZE - HEADER_SIZE - TAIL_SIZE) / UNIT_SIZE;
+/* the count of ids is a single byte */
+const _: () = assert!(MAX_IDS <= u8::MAX as usize);
+
+/** Offset of the id at `i`, followed by its pointer */
+fn unit_offset(i: usize) -> usize {
+    HEADER_SIZE + UNIT_SIZE * i
+}
 
-#[derive(Default)]
 pub struct BtreeNode {
     pub page_count: u64,
     pub ids: Vec<u64>,
     pub ptrs: Vec<u64>,
     pub node_type: u8,
+    /** Count of records of a table, only kept in the root node of a table
+     *
+     * Splits and merges keep the root on its page and rewrite it from the loaded node, so the
+     * count is carried along; other nodes leave it 0. */
+    pub record_count: u64,
+    /** Rowid following every rowid handed out so far, only kept in the root node of a table
+     *
+     * Carried along like `record_count`. */
+    pub next_rowid: u64,
 }
 
 impl BtreeNode {
@@ -20,62 +43,57 @@ impl BtreeNode {
     }
     pub fn new_node(node_type: u8) -> Self {
         Self {
+            page_count: 0,
+            ids: Vec::new(),
+            ptrs: Vec::new(),
             node_type,
-            ..Default::default()
+            record_count: 0,
+            next_rowid: 0,
         }
     }
     /** Load from bytes */
     pub fn load(page: &[u8; PAGE_SIZE]) -> Self {
         let mut node = Self::new_node(page[0]);
 
-        let id_count = page[1] as usize;
+        /* a corrupted count would read past the ids */
+        let id_count = (page[1] as usize).min(MAX_IDS);
+        node.record_count = read_u64(page, 8);
+        node.next_rowid = read_u64(page, NEXT_ROWID_OFFSET);
 
         for i in 0..id_count {
             node.push(
-                u64::from_be_bytes(
-                    page[UNIT_SIZE * (i + 1)..UNIT_SIZE * (i + 1) + 8]
-                        .try_into()
-                        .unwrap(),
-                ),
-                u64::from_be_bytes(
-                    page[UNIT_SIZE * (i + 1) + 8..UNIT_SIZE * (i + 1) + UNIT_SIZE]
-                        .try_into()
-                        .unwrap(),
-                ),
+                read_u64(page, unit_offset(i)),
+                read_u64(page, unit_offset(i) + 8),
             );
         }
         node
     }
     /** Dump to bytes */
     pub fn dump(&self) -> [u8; PAGE_SIZE] {
+        assert!(
+            unit_offset(self.len()) <= NEXT_ROWID_OFFSET,
+            "{} ids do not fit in a node",
+            self.len()
+        );
         let mut page = [0; PAGE_SIZE];
         page[0] = self.node_type;
         page[1] = self.len() as u8;
+        write_u64(&mut page, 8, self.record_count);
+        write_u64(&mut page, NEXT_ROWID_OFFSET, self.next_rowid);
         for (i, _) in self.ids.iter().enumerate() {
-            page[UNIT_SIZE * (i + 1)..UNIT_SIZE * (i + 1) + 8]
-                .copy_from_slice(&self.ids[i].to_be_bytes());
-            page[UNIT_SIZE * (i + 1) + 8..UNIT_SIZE * (i + 1) + UNIT_SIZE]
-                .copy_from_slice(&self.ptrs[i].to_be_bytes());
+            write_u64(&mut page, unit_offset(i), self.ids[i]);
+            write_u64(&mut page, unit_offset(i) + 8, self.ptrs[i]);
         }
         page
     }
-    /** Add an id into the node */
+    /** Add an id into the node, keeping ids in ascending order */
     fn add(&mut self, id: u64, ptr: u64) {
-        if self.ids.is_empty() {
-            self.push(id, ptr);
-        } else {
-            for (i, _) in self.ids.iter().enumerate() {
-                if i == 0 {
-                    self.insert(0, id, ptr);
-                    break;
-                } else if i < self.len() - 1 && id > self.ids[i] && id < self.ids[i + 1]
-                    || i == self.len() - 1
-                {
-                    self.insert(i + 1, id, ptr);
-                    break;
-                }
-            }
-        }
+        let index = self.ids.partition_point(|&i| i <= id);
+        self.insert(index, id, ptr);
+    }
+    /** Index of the child node covering `id` */
+    fn child_index(&self, id: u64) -> usize {
+        self.ids.partition_point(|&i| i <= id).saturating_sub(1)
     }
     /** Push an id into the current node
      *
@@ -153,100 +171,137 @@ impl BtreeNode {
             }
         } else {
             /* find child node to insert */
-            for i in 0..self.len() {
-                if i < self.len() - 1 && id > self.ids[i] && id < self.ids[i + 1]
-                    || i == self.len() - 1
-                {
-                    let child = mgr.get(device, self.ptrs[i]).unwrap();
-                    let mut child_node = Self::new(child.borrow().count, &child.borrow().data);
-                    /* if parted into tow sub trees */
-                    if let Some((id, page)) = child_node.insert_id_nontop(device, mgr, id, value)? {
-                        self.add(id, page);
-                        mgr.modify(device, self.page_count, &self.dump())?;
-                    }
+            let i = self.child_index(id);
+            let child = mgr.get(device, self.ptrs[i])?;
+            let mut child_node = Self::new(child.borrow().count, child.borrow().data());
+            let parted = child_node.insert_id_nontop(device, mgr, id, value)?;
+            /* the inserted id may be the new smallest id of the child */
+            self.ids[i] = *child_node.ids.first().unwrap();
+            /* if parted into tow sub trees */
+            if let Some((id, page)) = parted {
+                self.add(id, page);
+            }
+            mgr.modify(device, self.page_count, &self.dump())?;
 
-                    if self.len() >= MAX_IDS {
-                        return Ok(Some(self.part(device, mgr)?));
-                    }
-                }
+            if self.len() >= MAX_IDS {
+                return Ok(Some(self.part(device, mgr)?));
             }
         }
         Ok(None)
     }
+    /** Fill this empty node and new nodes below it with `entries`, sorted by id
+     *
+     * Every node but the last of each level holds as many ids as a node keeps without parting,
+     * so the tree has the fewest pages. This node stays on its page as the root. */
+    pub fn bulk_load<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        entries: &[(u64, u64)],
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut level = entries.to_vec();
+        let mut node_type = PAGE_TYPEID_BTREE_LEAF;
+        while level.len() >= MAX_IDS {
+            let mut parents = Vec::new();
+            let nodes = level.len().div_ceil(MAX_IDS - 1);
+            /* the nodes of a level are claimed at once while a single bitmap can hold them */
+            let first = if nodes < BITMAP_MANAGED_SIZE {
+                Some(mgr.alloc_contiguous(device, nodes, PageType::BtreePage)?)
+            } else {
+                None
+            };
+            for (i, chunk) in level.chunks(MAX_IDS - 1).enumerate() {
+                let mut node = Self::new_node(node_type);
+                for &(id, ptr) in chunk {
+                    node.push(id, ptr);
+                }
+                node.page_count = match first {
+                    Some(first) => first + i as u64,
+                    None => mgr.alloc(device, PageType::BtreePage)?.borrow().count,
+                };
+                mgr.modify(device, node.page_count, &node.dump())?;
+                parents.push((chunk[0].0, node.page_count));
+            }
+            level = parents;
+            node_type = PAGE_TYPEID_BTREE_INTERNAL;
+        }
+        self.clear();
+        self.node_type = node_type;
+        for (id, ptr) in level {
+            self.push(id, ptr);
+        }
+        mgr.modify(device, self.page_count, &self.dump())
+    }
+    /** Count the ids of this subtree by walking every leaf */
+    pub fn count_ids<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_leaf() {
+            return Ok(self.len() as u64);
+        }
+        let mut count = 0;
+        for &ptr in &self.ptrs {
+            count += Self::new(ptr, &mgr.get_data(device, ptr)?).count_ids(device, mgr)?;
+        }
+        Ok(count)
+    }
+    /** Release every page of this subtree, including the page of this node */
+    pub fn release<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_internal() {
+            for &ptr in &self.ptrs {
+                Self::new(ptr, &mgr.get_data(device, ptr)?).release(device, mgr)?;
+            }
+        }
+        mgr.release(device, self.page_count)
+    }
+    /** Release every node below this one, which is left on its page as an empty leaf */
+    pub fn release_children<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_internal() {
+            for &ptr in &self.ptrs {
+                Self::new(ptr, &mgr.get_data(device, ptr)?).release(device, mgr)?;
+            }
+        }
+        self.clear();
+        self.node_type = PAGE_TYPEID_BTREE_LEAF;
+        mgr.modify(device, self.page_count, &self.dump())
+    }
     /** Remove an id from B-Tree */
     pub fn remove_id<D>(&mut self, device: &mut D, mgr: &mut PageManage, id: u64) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        self.remove_id_nontop(device, mgr, id)?;
+        /* an internal root without children can no longer be descended, start over as a leaf */
+        if self.is_internal() && self.is_empty() {
+            self.node_type = PAGE_TYPEID_BTREE_LEAF;
+            mgr.modify(device, self.page_count, &self.dump())?;
+        }
+        Ok(())
+    }
+    /** Remove an id, merging or rebalancing children that become too small */
+    fn remove_id_nontop<D>(&mut self, device: &mut D, mgr: &mut PageManage, id: u64) -> IOResult<()>
     where
         D: Write + Read + Seek,
     {
         if self.is_internal() {
-            for i in 0..self.len() {
-                if i < self.len() - 1 && id >= self.ids[i] && id < self.ids[i + 1]
-                    || i == self.len() - 1
-                {
-                    let child_page = mgr.get(device, self.ptrs[i]).unwrap();
-                    let mut child_node =
-                        Self::new(child_page.borrow().count, &child_page.borrow().data);
-                    child_node.remove_id(device, mgr, id)?;
-                    /* when child_node is empty, self.len() must be 0 */
-                    if child_node.is_empty() {
-                        self.remove(i);
-                    } else if child_node.len() < MAX_IDS / 2 {
-                        if i > 0 {
-                            let previous_node_page = mgr.get(device, self.ptrs[i - 1]).unwrap();
-                            let mut previous_node = Self::new(
-                                previous_node_page.borrow().count,
-                                &previous_node_page.borrow().data,
-                            );
-                            /* merge this child node into previous node */
-                            if previous_node.len() + child_node.len() <= MAX_IDS {
-                                for child_i in 0..child_node.len() {
-                                    previous_node
-                                        .push(child_node.ids[child_i], child_node.ptrs[child_i]);
-                                }
-                                mgr.release(device, child_node.page_count);
-                                self.remove(i);
-                            } else {
-                                let id = previous_node.ids.pop().unwrap();
-                                let ptr = previous_node.ptrs.pop().unwrap();
-                                child_node.insert(0, id, ptr);
-                                child_page.borrow_mut().modify(&child_node.dump());
-                                self.ids[i] = id;
-                            }
-                            previous_node_page
-                                .borrow_mut()
-                                .modify(&previous_node.dump());
-                        } else if i < self.len() - 1 {
-                            let next_node_page = mgr.get(device, self.ptrs[i + 1]).unwrap();
-                            let mut next_node = Self::new(
-                                next_node_page.borrow().count,
-                                &next_node_page.borrow().data,
-                            );
-                            /* merge this child node into next node */
-                            if next_node.len() + child_node.len() <= MAX_IDS {
-                                for child_i in (0..child_node.len()).rev() {
-                                    next_node.insert(
-                                        0,
-                                        child_node.ids[child_i],
-                                        child_node.ptrs[child_i],
-                                    );
-                                }
-                                self.ids[i + 1] = *next_node.ids.first().unwrap();
-                                mgr.release(device, child_node.page_count);
-                                self.remove(i);
-                            } else {
-                                let id = *next_node.ids.first().unwrap();
-                                let ptr = *next_node.ptrs.first().unwrap();
-                                next_node.remove(0);
-                                child_node.push(id, ptr);
-                                child_page.borrow_mut().modify(&child_node.dump());
-                                self.ids[i + 1] = *next_node.ids.first().unwrap();
-                            }
-                            next_node_page.borrow_mut().modify(&next_node.dump());
-                        }
-                    }
-                    mgr.modify(device, self.page_count, &self.dump())?;
-                }
+            if self.is_empty() {
+                return Ok(());
             }
+            let i = self.child_index(id);
+            let mut child_node = Self::new(self.ptrs[i], &mgr.get_data(device, self.ptrs[i])?);
+            child_node.remove_id_nontop(device, mgr, id)?;
+            self.fix_child(device, mgr, i, child_node)?;
+            mgr.modify(device, self.page_count, &self.dump())?;
         } else {
             /* find and remove */
             for i in 0..self.len() {
@@ -259,80 +314,401 @@ impl BtreeNode {
         }
         Ok(())
     }
+    /** Repair child `i` after ids were removed from it
+     *
+     * An empty child is released. Otherwise its separator is updated, and a child with less than
+     * half of MAX_IDS is merged into a neighbor or takes ids from it until it has half. The parent
+     * itself is written by the caller. */
+    fn fix_child<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        i: usize,
+        mut child_node: Self,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if child_node.is_empty() {
+            mgr.release(device, child_node.page_count)?;
+            self.remove(i);
+            return Ok(());
+        }
+        /* the removed ids may have included the smallest id of the child */
+        self.ids[i] = *child_node.ids.first().unwrap();
+        if child_node.len() >= MAX_IDS / 2 {
+            return Ok(());
+        }
+        if i > 0 {
+            let mut previous_node =
+                Self::new(self.ptrs[i - 1], &mgr.get_data(device, self.ptrs[i - 1])?);
+            /* merge this child node into previous node */
+            if previous_node.len() + child_node.len() <= MAX_IDS {
+                for child_i in 0..child_node.len() {
+                    previous_node.push(child_node.ids[child_i], child_node.ptrs[child_i]);
+                }
+                mgr.release(device, child_node.page_count)?;
+                self.remove(i);
+            } else {
+                while child_node.len() < MAX_IDS / 2 && previous_node.len() > MAX_IDS / 2 {
+                    let id = previous_node.ids.pop().unwrap();
+                    let ptr = previous_node.ptrs.pop().unwrap();
+                    child_node.insert(0, id, ptr);
+                }
+                mgr.modify(device, child_node.page_count, &child_node.dump())?;
+                self.ids[i] = *child_node.ids.first().unwrap();
+            }
+            mgr.modify(device, previous_node.page_count, &previous_node.dump())?;
+        } else if i < self.len() - 1 {
+            let mut next_node =
+                Self::new(self.ptrs[i + 1], &mgr.get_data(device, self.ptrs[i + 1])?);
+            /* merge this child node into next node */
+            if next_node.len() + child_node.len() <= MAX_IDS {
+                for child_i in (0..child_node.len()).rev() {
+                    next_node.insert(0, child_node.ids[child_i], child_node.ptrs[child_i]);
+                }
+                self.ids[i + 1] = *next_node.ids.first().unwrap();
+                mgr.release(device, child_node.page_count)?;
+                self.remove(i);
+            } else {
+                while child_node.len() < MAX_IDS / 2 && next_node.len() > MAX_IDS / 2 {
+                    let id = *next_node.ids.first().unwrap();
+                    let ptr = *next_node.ptrs.first().unwrap();
+                    next_node.remove(0);
+                    child_node.push(id, ptr);
+                }
+                mgr.modify(device, child_node.page_count, &child_node.dump())?;
+                self.ids[i + 1] = *next_node.ids.first().unwrap();
+            }
+            mgr.modify(device, next_node.page_count, &next_node.dump())?;
+        }
+        Ok(())
+    }
+    /** Remove every id in `range` from B-Tree, return how many were removed
+     *
+     * Children entirely inside the range are released with their subtrees without being
+     * visited id by id, and each node on the way is repaired once instead of once per id. */
+    pub fn remove_range<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        range: &Range<u64>,
+    ) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let removed = self.remove_range_nontop(device, mgr, range)?;
+        /* like remove_id */
+        if self.is_internal() && self.is_empty() {
+            self.node_type = PAGE_TYPEID_BTREE_LEAF;
+            mgr.modify(device, self.page_count, &self.dump())?;
+        }
+        Ok(removed)
+    }
+    fn remove_range_nontop<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        range: &Range<u64>,
+    ) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        if range.is_empty() || self.is_empty() {
+            return Ok(0);
+        }
+        let mut removed = 0;
+        if self.is_leaf() {
+            for i in (0..self.len()).rev() {
+                if range.contains(&self.ids[i]) {
+                    self.remove(i);
+                    removed += 1;
+                }
+            }
+        } else {
+            let first = self.child_index(range.start);
+            let last = self.child_index(range.end - 1);
+            /* a child holds the ids from its separator up to the next one */
+            let mut partial = Vec::new();
+            for i in (first..=last).rev() {
+                let covered = range.start <= self.ids[i]
+                    && self.ids.get(i + 1).is_some_and(|&next| next <= range.end);
+                if covered {
+                    let child_node = Self::new(self.ptrs[i], &mgr.get_data(device, self.ptrs[i])?);
+                    removed += child_node.count_ids(device, mgr)?;
+                    child_node.release(device, mgr)?;
+                    self.remove(i);
+                    /* the children after it moved down */
+                    for j in &mut partial {
+                        *j -= 1;
+                    }
+                } else {
+                    partial.push(i);
+                }
+            }
+            /* every child is emptied before any is merged, so no id in the range moves into a
+             * child already done */
+            for &i in &partial {
+                let mut child_node = Self::new(self.ptrs[i], &mgr.get_data(device, self.ptrs[i])?);
+                removed += child_node.remove_range_nontop(device, mgr, range)?;
+            }
+            /* from the right, so fixing a child does not move the ones left of it, which are
+             * reloaded as fixing may have merged into them */
+            for &i in &partial {
+                let child_node = Self::new(self.ptrs[i], &mgr.get_data(device, self.ptrs[i])?);
+                self.fix_child(device, mgr, i, child_node)?;
+            }
+        }
+        if removed != 0 {
+            mgr.modify(device, self.page_count, &self.dump())?;
+        }
+        Ok(removed)
+    }
+    /** Replace the pointer of an id
+     *
+     * Return whether the id was found. */
+    pub fn update_id<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        id: u64,
+        value: u64,
+    ) -> IOResult<bool>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_internal() {
+            if self.is_empty() {
+                return Ok(false);
+            }
+            let page = mgr.get(device, self.ptrs[self.child_index(id)])?;
+            let mut child = Self::new(page.borrow().count, page.borrow().data());
+            return child.update_id(device, mgr, id, value);
+        }
+        match self.ids.iter().position(|&i| i == id) {
+            Some(i) => {
+                self.ptrs[i] = value;
+                mgr.modify(device, self.page_count, &self.dump())?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
     /** Find pointer by id */
-    pub fn find_id<D>(&self, device: &mut D, mgr: &mut PageManage, id: u64) -> Option<u64>
+    pub fn find_id<D>(&self, device: &mut D, mgr: &mut PageManage, id: u64) -> IOResult<Option<u64>>
     where
         D: Write + Read + Seek,
     {
         if self.is_internal() {
-            for i in 0..self.len() {
-                if i < self.len() - 1 && id >= self.ids[i] && id < self.ids[i + 1]
-                    || i == self.len() - 1
-                {
-                    let page = mgr.get(device, self.ptrs[i]).unwrap();
-                    let child = Self::new(page.borrow().count, &page.borrow().data);
-                    return child.find_id(device, mgr, id);
-                }
+            if self.is_empty() {
+                return Ok(None);
             }
+            let page = mgr.get(device, self.ptrs[self.child_index(id)])?;
+            let child = Self::new(page.borrow().count, page.borrow().data());
+            return child.find_id(device, mgr, id);
         } else {
             for i in 0..self.ids.len() {
                 if id == self.ids[i] {
-                    return Some(self.ptrs[i]);
+                    return Ok(Some(self.ptrs[i]));
+                }
+            }
+        }
+        Ok(None)
+    }
+    /** Find the smallest id not less than `id`
+     *
+     * Return:
+     * * the id
+     * * its pointer */
+    pub fn find_from<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        id: u64,
+    ) -> IOResult<Option<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_internal() {
+            /* a child covering `id` may only hold smaller ids, continue with the next one */
+            for i in self.child_index(id)..self.len() {
+                let page = mgr.get(device, self.ptrs[i])?;
+                let child = Self::new(page.borrow().count, page.borrow().data());
+                if let Some(found) = child.find_from(device, mgr, id)? {
+                    return Ok(Some(found));
+                }
+            }
+            return Ok(None);
+        }
+        let i = self.ids.partition_point(|&i| i < id);
+        Ok(self.ids.get(i).map(|&found| (found, self.ptrs[i])))
+    }
+    /** Every (id, pointer) pair, in ascending order
+     *
+     * Unlike any `find_range`, this includes the id `u64::MAX`. */
+    pub fn find_all<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Vec<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut found = Vec::new();
+        self.find_range(device, mgr, &(0..u64::MAX), &mut found)?;
+        if let Some(ptr) = self.find_id(device, mgr, u64::MAX)? {
+            found.push((u64::MAX, ptr));
+        }
+        Ok(found)
+    }
+    /** Append every (id, pointer) pair with an id in `range` to `found`, in ascending order */
+    pub fn find_range<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        range: &Range<u64>,
+        found: &mut Vec<(u64, u64)>,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_internal() {
+            for i in self.child_index(range.start)..self.len() {
+                if self.ids[i] >= range.end {
+                    break;
                 }
+                let page = mgr.get(device, self.ptrs[i])?;
+                let child = Self::new(page.borrow().count, page.borrow().data());
+                child.find_range(device, mgr, range, found)?;
+            }
+        } else {
+            let start = self.ids.partition_point(|&i| i < range.start);
+            for i in start..self.len() {
+                if self.ids[i] >= range.end {
+                    break;
+                }
+                found.push((self.ids[i], self.ptrs[i]));
             }
         }
-        None
+        Ok(())
     }
     /**
      * Return:
-     * * Unused id
-     * * useed id count (only a leaf node will returns this)
+     * * Unused id between the ids of this subtree
+     * * largest used id of this subtree
      */
     fn find_unused_nontop<D>(
         &self,
         device: &mut D,
         mgr: &mut PageManage,
-    ) -> (Option<u64>, Option<u64>)
+    ) -> IOResult<(Option<u64>, Option<u64>)>
     where
         D: Write + Read + Seek,
     {
         if self.is_internal() {
+            let mut last = None;
             for i in 0..self.len() {
-                let page = mgr.get(device, self.ptrs[i]).unwrap();
-                let child = Self::new(page.borrow().count, &page.borrow().data);
-                let result = child.find_unused_nontop(device, mgr);
+                let page = mgr.get(device, self.ptrs[i])?;
+                let child = Self::new(page.borrow().count, page.borrow().data());
+                let result = child.find_unused_nontop(device, mgr)?;
 
-                if let Some(id) = result.0 {
-                    return (Some(id), None);
+                if result.0.is_some() {
+                    return Ok(result);
                 } else if let Some(id) = result.1 {
-                    if i < self.len() - 1 && id + 1 < self.ids[i + 1] || i == self.len() - 1 {
-                        return (Some(id + 1), None);
+                    if i < self.len() - 1 && id + 1 < self.ids[i + 1] {
+                        return Ok((Some(id + 1), None));
                     }
+                    last = Some(id);
                 }
             }
-        } else if self.ids.len() > 1 {
-            for i in 0..self.ids.len() - 1 {
-                if self.ids[i] + 1 < self.ids[i + 1] {
-                    return (Some(self.ids[i] + 1), None);
+            Ok((None, last))
+        } else {
+            for i in 1..self.len() {
+                if self.ids[i - 1] + 1 < self.ids[i] {
+                    return Ok((Some(self.ids[i - 1] + 1), None));
                 }
             }
-            return (None, Some(*self.ids.last().unwrap()));
+            Ok((None, self.ids.last().copied()))
+        }
+    }
+    /** Smallest id and its pointer, found down the left edge of the tree */
+    pub fn min_id<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Option<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_leaf() {
+            return Ok(self.ids.first().map(|&id| (id, self.ptrs[0])));
+        }
+        /* an emptied child is passed over like `find_from` does */
+        for &ptr in &self.ptrs {
+            let child = Self::new(ptr, &mgr.get_data(device, ptr)?);
+            if let Some(found) = child.min_id(device, mgr)? {
+                return Ok(Some(found));
+            }
         }
-        (None, None)
+        Ok(None)
+    }
+    /** Largest id and its pointer, found down the right edge of the tree */
+    pub fn max_id<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Option<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.is_leaf() {
+            return Ok(self.ids.last().map(|&id| (id, self.ptrs[self.len() - 1])));
+        }
+        for &ptr in self.ptrs.iter().rev() {
+            let child = Self::new(ptr, &mgr.get_data(device, ptr)?);
+            if let Some(found) = child.max_id(device, mgr)? {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+    /** Find unused id
+     *
+     * Return the first gap between used ids, or the id following the largest one. Once the
+     * largest possible id is used, the id before the smallest one is returned. */
+    pub fn find_unused<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(match self.find_unused_nontop(device, mgr)? {
+            (Some(id), _) => id,
+            (None, Some(u64::MAX)) => {
+                /* no gap from the smallest id up */
+                let (first, _) = self.min_id(device, mgr)?.unwrap();
+                first.checked_sub(1).expect("every id is used")
+            }
+            (None, Some(last)) => last + 1,
+            (None, None) => 0,
+        })
     }
-    /** Find unused id */
-    pub fn find_unused<D>(&self, device: &mut D, mgr: &mut PageManage) -> u64
+    /** Rewrite ids to consecutive values starting at `next`, keeping their order
+     *
+     * Pointers are left untouched. Every (old id, new id) pair of a leaf is appended to
+     * `mapping`. */
+    pub fn renumber<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        next: &mut u64,
+        mapping: &mut Vec<(u64, u64)>,
+    ) -> IOResult<()>
     where
         D: Write + Read + Seek,
     {
-        let result = self.find_unused_nontop(device, mgr);
-        if let Some(id) = result.0 {
-            id
-        } else if let Some(id) = result.1 {
-            id
+        if self.is_internal() {
+            for i in 0..self.len() {
+                let page = mgr.get(device, self.ptrs[i])?;
+                let mut child = Self::new(page.borrow().count, page.borrow().data());
+                /* the separator is the smallest id of the child */
+                self.ids[i] = *next;
+                child.renumber(device, mgr, next, mapping)?;
+            }
         } else {
-            0
+            for id in self.ids.iter_mut() {
+                mapping.push((*id, *next));
+                *id = *next;
+                *next += 1;
+            }
         }
+        mgr.modify(device, self.page_count, &self.dump())
     }
     pub fn is_internal(&self) -> bool {
         self.node_type == PAGE_TYPEID_BTREE_INTERNAL
@@ -363,3 +739,225 @@ impl BtreeNode {
         self.ptrs.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /** Root on a page of its own, bulk loaded with `ids` pointing at ten times themselves */
+    fn tree(device: &mut Cursor<Vec<u8>>, mgr: &mut PageManage, ids: &[u64]) -> BtreeNode {
+        let page = mgr.alloc(device, PageType::BtreePage).unwrap();
+        let mut root = BtreeNode::new_node(PAGE_TYPEID_BTREE_LEAF);
+        root.page_count = page.borrow().count;
+        let entries: Vec<(u64, u64)> = ids.iter().map(|&id| (id, id * 10)).collect();
+        root.bulk_load(device, mgr, &entries).unwrap();
+        root
+    }
+
+    /** Check that ids ascend, every separator is the first id of its child and every leaf is
+     * at the same depth, return the depth */
+    fn check(device: &mut Cursor<Vec<u8>>, mgr: &mut PageManage, node: &BtreeNode) -> usize {
+        assert!(node.ids.windows(2).all(|w| w[0] < w[1]));
+        if node.is_leaf() {
+            assert!(node
+                .ids
+                .iter()
+                .zip(&node.ptrs)
+                .all(|(&id, &ptr)| ptr == id * 10));
+            return 1;
+        }
+        let mut depths = Vec::new();
+        for (&id, &ptr) in node.ids.iter().zip(&node.ptrs) {
+            let child = BtreeNode::new(ptr, &mgr.get_data(device, ptr).unwrap());
+            assert_eq!(child.ids.first(), Some(&id));
+            depths.push(check(device, mgr, &child));
+        }
+        assert!(depths.windows(2).all(|w| w[0] == w[1]));
+        depths[0] + 1
+    }
+
+    /** Ids of each child of a root above leaves */
+    fn leaves(
+        device: &mut Cursor<Vec<u8>>,
+        mgr: &mut PageManage,
+        root: &BtreeNode,
+    ) -> Vec<Vec<u64>> {
+        root.ptrs
+            .iter()
+            .map(|&ptr| BtreeNode::new(ptr, &mgr.get_data(device, ptr).unwrap()).ids)
+            .collect()
+    }
+
+    #[test]
+    fn remove_range_costs_less_than_removing_each_id() {
+        let ids: Vec<u64> = (0..70000).collect();
+        let range = 30000..40000;
+        let expected: Vec<(u64, u64)> = ids
+            .iter()
+            .filter(|id| !range.contains(*id))
+            .map(|&id| (id, id * 10))
+            .collect();
+
+        /* a small cache, so pages are loaded and written again as the removal goes */
+        let mut by_range = Cursor::new(Vec::new());
+        let mut range_mgr = PageManage::new(8);
+        let mut root = tree(&mut by_range, &mut range_mgr, &ids);
+        assert_eq!(check(&mut by_range, &mut range_mgr, &root), 3);
+        range_mgr.sync_all(&mut by_range).unwrap();
+        let before = range_mgr.stats();
+        assert_eq!(
+            root.remove_range(&mut by_range, &mut range_mgr, &range)
+                .unwrap(),
+            10000
+        );
+        range_mgr.sync_all(&mut by_range).unwrap();
+        let range_stats = range_mgr.stats();
+        assert_eq!(check(&mut by_range, &mut range_mgr, &root), 3);
+        assert_eq!(
+            root.find_all(&mut by_range, &mut range_mgr).unwrap(),
+            expected
+        );
+
+        let mut by_id = Cursor::new(Vec::new());
+        let mut id_mgr = PageManage::new(8);
+        let mut id_root = tree(&mut by_id, &mut id_mgr, &ids);
+        id_mgr.sync_all(&mut by_id).unwrap();
+        let id_before = id_mgr.stats();
+        for id in range.clone() {
+            id_root.remove_id(&mut by_id, &mut id_mgr, id).unwrap();
+        }
+        id_mgr.sync_all(&mut by_id).unwrap();
+        let id_stats = id_mgr.stats();
+        assert_eq!(check(&mut by_id, &mut id_mgr, &id_root), 3);
+        assert_eq!(id_root.find_all(&mut by_id, &mut id_mgr).unwrap(), expected);
+
+        /* key by key rewrites the path down once per id, while the range rewrites each node once;
+         * both load and write each leaf about once through the cache, key by key also its
+         * neighbors as it rebalances */
+        let range_modifies = range_stats.modifies - before.modifies;
+        let id_modifies = id_stats.modifies - id_before.modifies;
+        assert!(id_modifies >= 3 * 10000);
+        assert!(
+            range_modifies * 100 < id_modifies,
+            "{range_modifies} {id_modifies}"
+        );
+        let range_reads = range_stats.reads - before.reads;
+        let id_reads = id_stats.reads - id_before.reads;
+        assert!(range_reads < id_reads, "{range_reads} {id_reads}");
+        let range_writes = range_stats.writes - before.writes;
+        let id_writes = id_stats.writes - id_before.writes;
+        assert!(range_writes < id_writes, "{range_writes} {id_writes}");
+    }
+
+    /** Three leaves of `MAX_IDS - 1` ids, 0, 10, 20 and so on */
+    fn three_leaves(device: &mut Cursor<Vec<u8>>, mgr: &mut PageManage) -> BtreeNode {
+        let ids: Vec<u64> = (0..3 * (MAX_IDS as u64 - 1)).map(|i| i * 10).collect();
+        let root = tree(device, mgr, &ids);
+        assert_eq!(
+            leaves(device, mgr, &root)
+                .iter()
+                .map(Vec::len)
+                .collect::<Vec<_>>(),
+            [MAX_IDS - 1; 3]
+        );
+        root
+    }
+
+    #[test]
+    fn fix_child_borrows_from_the_previous_node() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let mut root = three_leaves(&mut device, &mut mgr);
+        let second = root.ids[1];
+        /* leaves the middle child one short of half after its two smallest ids */
+        let removed = (MAX_IDS - 1 - (MAX_IDS / 2 - 1)) as u64;
+        let range = second + 20..second + 20 + removed * 10;
+        assert_eq!(
+            root.remove_range(&mut device, &mut mgr, &range).unwrap(),
+            removed
+        );
+
+        let lens: Vec<usize> = leaves(&mut device, &mut mgr, &root)
+            .iter()
+            .map(Vec::len)
+            .collect();
+        assert_eq!(lens, [MAX_IDS - 2, MAX_IDS / 2, MAX_IDS - 1]);
+        assert_eq!(root.ids[1], second - 10);
+        check(&mut device, &mut mgr, &root);
+    }
+
+    #[test]
+    fn fix_child_merges_into_the_previous_node() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let mut root = three_leaves(&mut device, &mut mgr);
+        let (second, third) = (root.ids[1], root.ids[2]);
+        /* only the smallest id of the middle child is left, which fits into the first child */
+        let range = second + 10..third;
+        assert_eq!(
+            root.remove_range(&mut device, &mut mgr, &range).unwrap(),
+            MAX_IDS as u64 - 2
+        );
+
+        let leaves = leaves(&mut device, &mut mgr, &root);
+        assert_eq!(leaves.len(), 2);
+        assert_eq!(
+            (leaves[0].len(), leaves[0].last()),
+            (MAX_IDS, Some(&second))
+        );
+        assert_eq!(root.ids, [0, third]);
+        check(&mut device, &mut mgr, &root);
+    }
+
+    #[test]
+    fn fix_child_borrows_from_the_next_node() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let mut root = three_leaves(&mut device, &mut mgr);
+        let second = root.ids[1];
+        /* the first child has no previous node to turn to */
+        let removed = (MAX_IDS - 1 - (MAX_IDS / 2 - 4)) as u64;
+        assert_eq!(
+            root.remove_range(&mut device, &mut mgr, &(0..removed * 10))
+                .unwrap(),
+            removed
+        );
+
+        let leaves = leaves(&mut device, &mut mgr, &root);
+        let lens: Vec<usize> = leaves.iter().map(Vec::len).collect();
+        assert_eq!(lens, [MAX_IDS / 2, MAX_IDS - 5, MAX_IDS - 1]);
+        assert_eq!(leaves[0].first(), Some(&(removed * 10)));
+        assert_eq!(root.ids[..2], [removed * 10, second + 40]);
+        check(&mut device, &mut mgr, &root);
+    }
+
+    #[test]
+    fn fix_child_merges_into_the_next_node() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let mut root = three_leaves(&mut device, &mut mgr);
+        let (second, third) = (root.ids[1], root.ids[2]);
+        let first_page = root.ptrs[0];
+        /* only the largest id of the first child is left, which fits into the second child */
+        let last = second - 10;
+        assert_eq!(
+            root.remove_range(&mut device, &mut mgr, &(0..last))
+                .unwrap(),
+            MAX_IDS as u64 - 2
+        );
+
+        let leaves = leaves(&mut device, &mut mgr, &root);
+        assert_eq!(leaves.len(), 2);
+        assert_eq!((leaves[0].len(), leaves[0].first()), (MAX_IDS, Some(&last)));
+        assert_eq!(root.ids, [last, third]);
+        check(&mut device, &mut mgr, &root);
+        /* a released child page is handed out again */
+        let reused = mgr
+            .alloc(&mut device, PageType::BtreePage)
+            .unwrap()
+            .borrow()
+            .count;
+        assert_eq!(reused, first_page);
+    }
+}
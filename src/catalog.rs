@@ -0,0 +1,652 @@
+use crate::codec::*;
+use crate::page::*;
+use crate::table::{Index, RecordLayout, RowidMode, Value, ValueType};
+use std::fmt;
+use std::io::{Result as IOResult, *};
+
+/** Page holding the catalog, the first page allocated in a new file */
+pub const CATALOG_PAGE: u64 = 1;
+
+/** Version of the catalog and table formats, bumped on every incompatible change */
+pub const CATALOG_VERSION: u8 = 14;
+
+/** First version storing timestamps with their sign bit flipped, older timestamp columns are refused */
+pub const TIMESTAMP_ORDER_VERSION: u8 = 12;
+
+/** First version recording the free-space map, a map is built when an older file is opened */
+pub const FREE_MAP_VERSION: u8 = 13;
+
+/** First version recording the autovacuum flag and the page count of the last checkpoint */
+pub const AUTOVACUUM_VERSION: u8 = 14;
+
+/** Oldest version still read, later versions only append fields to the header and each table */
+const OLDEST_CATALOG_VERSION: u8 = 8;
+
+#[derive(Debug)]
+pub enum CatalogError {
+    /** The catalog page holds something else, the file predates the catalog or is not a database */
+    NotCatalog,
+    /** The catalog was written by an incompatible version */
+    UnsupportedVersion(u8),
+    /** No table has this name */
+    TableNotFound(String),
+    /** A table with this name already exists */
+    TableExists(String),
+    /** The schemas do not fit in the catalog page */
+    Full,
+    /** The file was written with pages of another size than `PAGE_SIZE` */
+    PageSizeMismatch(usize),
+    /** The table has a timestamp column written before `TIMESTAMP_ORDER_VERSION` */
+    OldTimestamps(String),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotCatalog => write!(f, "page {} is not a catalog page", CATALOG_PAGE),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported catalog version {}", version)
+            }
+            Self::TableNotFound(name) => write!(f, "no table named {}", name),
+            Self::TableExists(name) => write!(f, "table {} already exists", name),
+            Self::Full => f.write_str("catalog page is full"),
+            Self::PageSizeMismatch(page_size) => write!(
+                f,
+                "the file has {}-byte pages but this build uses {}-byte pages",
+                page_size, PAGE_SIZE
+            ),
+            Self::OldTimestamps(name) => write!(
+                f,
+                "table {} stores timestamps in the encoding before catalog version {}",
+                name, TIMESTAMP_ORDER_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CatalogError {}
+
+impl From<CatalogError> for Error {
+    fn from(err: CatalogError) -> Self {
+        let kind = match err {
+            CatalogError::NotCatalog
+            | CatalogError::UnsupportedVersion(_)
+            | CatalogError::PageSizeMismatch(_)
+            | CatalogError::OldTimestamps(_) => ErrorKind::InvalidData,
+            CatalogError::TableNotFound(_) => ErrorKind::NotFound,
+            CatalogError::TableExists(_) => ErrorKind::AlreadyExists,
+            CatalogError::Full => ErrorKind::InvalidInput,
+        };
+        Error::new(kind, err)
+    }
+}
+
+/** Stored schema of a table */
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    pub name: String,
+    pub root_page: u64,
+    pub value_types: Vec<ValueType>,
+    /** Column names, empty when the columns are unnamed */
+    pub columns: Vec<String>,
+    pub indexes: Vec<Index>,
+    pub rowid_mode: RowidMode,
+    pub layout: RecordLayout,
+    /** Default value of each column */
+    pub defaults: Vec<Option<Value>>,
+    /** Whether each record carries a checksum */
+    pub checksum: bool,
+}
+
+#[derive(Debug)]
+/**
+ * # Data structure:
+ *
+ * |Start|End |Description|
+ * |-----|----|-----------|
+ * |0    |1   |Page type  |
+ * |1    |2   |Version    |
+ * |2    |4   |Count of tables|
+ * |4    |8   |Page size, at this offset in every version|
+ * |8    |9   |1 if frozen, 0 otherwise|
+ * |9    |17  |Directory page of the free-space map, since version 13, 0 if none|
+ * |17   |18  |1 if autovacuum is on, since version 14, 0 otherwise|
+ * |18   |26  |Count of pages at the last checkpoint, since version 14, 0 if none|
+ * |26   |4096|Tables, from 17 before version 14 and from 9 before version 13|
+ *
+ * ## Table
+ *
+ * |Start|End    |Description|
+ * |-----|-------|-----------|
+ * |0    |2      |Length of name (n)|
+ * |2    |2+n    |Name       |
+ * |2+n  |10+n   |Root B-tree page|
+ * |10+n |12+n   |Count of columns (c)|
+ * |12+n |12+n+c |Value type of each column|
+ * |12+n+c|14+n+c|Count of column names, 0 or c|
+ * |14+n+c|...   |Column names|
+ * |...  |+2    |Count of indexes|
+ * |...  |...   |Indexes    |
+ * |...  |+1    |Rowid mode |
+ * |...  |+1    |Record layout, since version 9, chained before|
+ * |...  |...   |Default of each column, since version 10|
+ * |...  |+1    |1 if records carry a checksum, since version 11, 0 before|
+ *
+ * ## Column name
+ *
+ * |Start|End    |Description|
+ * |-----|-------|-----------|
+ * |0    |2      |Length of name (n)|
+ * |2    |2+n    |Name       |
+ *
+ * ## Index
+ *
+ * |Start|End    |Description|
+ * |-----|-------|-----------|
+ * |0    |2      |Column     |
+ * |2    |3      |1 if unique, 0 otherwise|
+ * |3    |11     |Root B-tree page|
+ *
+ * ## Default
+ *
+ * |Start|End    |Description|
+ * |-----|-------|-----------|
+ * |0    |1      |1 if the column has a default, 0 otherwise and nothing follows|
+ * |1    |3      |Length of the value (n)|
+ * |3    |3+n    |Value      |
+ */
+pub struct Catalog {
+    pub entries: Vec<CatalogEntry>,
+    /** Page size the file was written with, the catalog is always saved with `PAGE_SIZE` */
+    pub page_size: usize,
+    /** Written once by `Database::freeze_to`, every change to the file is refused */
+    pub frozen: bool,
+    /** Directory page of the free-space map, see `PageManage::create_free_map` */
+    pub free_map: Option<u64>,
+    /** Turns `PageManage::autovacuum` on whenever the file is opened, see
+     * `Database::set_autovacuum` */
+    pub autovacuum: bool,
+    /** Count of pages of the file recorded by the last `Database::checkpoint`, 0 before one */
+    pub page_count: u64,
+}
+
+impl Catalog {
+    /** Load the catalog, creating an empty one in a new file
+     *
+     * A file written with another page size is refused, unless the page manager is read-only and
+     * the file can still be read. The page manager of a frozen file is made immutable, and any
+     * other one uses the free-space map of the file, built first if the file has none. The
+     * autovacuum flag of the catalog turns on autovacuum of the page manager. */
+    pub fn open<D>(device: &mut D, mgr: &mut PageManage) -> IOResult<Self>
+    where
+        D: Write + Read + Seek,
+    {
+        if !mgr.is_used(device, CATALOG_PAGE)? {
+            /* the lowest unused page is always allocated first */
+            let page = mgr.alloc(device, PageType::General)?;
+            if page.borrow().count != CATALOG_PAGE {
+                return Err(CatalogError::NotCatalog.into());
+            }
+            let catalog = Self {
+                entries: Vec::new(),
+                page_size: PAGE_SIZE,
+                frozen: false,
+                free_map: Some(mgr.create_free_map(device)?),
+                autovacuum: false,
+                page_count: 0,
+            };
+            catalog.save(device, mgr)?;
+            return Ok(catalog);
+        }
+        let mut catalog = match mgr
+            .get_data(device, CATALOG_PAGE)
+            .and_then(|data| Self::load(&data))
+        {
+            Ok(catalog) => catalog,
+            /* the catalog page is elsewhere with another page size */
+            Err(err) => {
+                return Err(match Self::probe_page_size(device)? {
+                    Some(page_size) => CatalogError::PageSizeMismatch(page_size).into(),
+                    None => err,
+                })
+            }
+        };
+        if catalog.page_size != PAGE_SIZE && !mgr.read_only {
+            return Err(CatalogError::PageSizeMismatch(catalog.page_size).into());
+        }
+        mgr.immutable |= catalog.frozen;
+        mgr.autovacuum |= catalog.autovacuum;
+        match catalog.free_map {
+            Some(dir) => mgr.free_map = Some(dir),
+            None if !mgr.read_only && !mgr.immutable => {
+                catalog.free_map = Some(mgr.create_free_map(device)?);
+                mgr.rebuild_free_map(device)?;
+                catalog.save(device, mgr)?;
+            }
+            None => {}
+        }
+        Ok(catalog)
+    }
+    /** Look for the catalog page of a file written with another page size
+     *
+     * Return the page size recorded in a catalog page found at the start of page 1 for a page
+     * size from 512 to 65536 bytes. */
+    fn probe_page_size<D>(device: &mut D) -> IOResult<Option<usize>>
+    where
+        D: Read + Seek,
+    {
+        for page_size in (9..=16).map(|shift| 1 << shift) {
+            if page_size == PAGE_SIZE {
+                continue;
+            }
+            let mut header = [0; 8];
+            device.seek(SeekFrom::Start(page_size as u64))?;
+            match device.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => continue,
+                Err(err) => return Err(err),
+            }
+            if header[0] == PAGE_TYPEID_CATALOG && read_u32(&header, 4) as usize == page_size {
+                return Ok(Some(page_size));
+            }
+        }
+        Ok(None)
+    }
+    /** Load from bytes */
+    pub fn load(page: &[u8; PAGE_SIZE]) -> IOResult<Self> {
+        if page[0] != PAGE_TYPEID_CATALOG {
+            return Err(CatalogError::NotCatalog.into());
+        }
+        if !(OLDEST_CATALOG_VERSION..=CATALOG_VERSION).contains(&page[1]) {
+            return Err(CatalogError::UnsupportedVersion(page[1]).into());
+        }
+        let has_layout = page[1] >= 9;
+        let has_defaults = page[1] >= 10;
+        let has_checksum = page[1] >= 11;
+        let corrupted = || Error::new(ErrorKind::InvalidData, "catalog page is corrupted");
+        /* length-prefixed bytes, followed by at least `after` more bytes */
+        let read_bytes = |ptr: &mut usize, after: usize| {
+            if *ptr + 2 > PAGE_SIZE {
+                return Err(corrupted());
+            }
+            let len = read_u16(page, *ptr) as usize;
+            *ptr += 2;
+            if *ptr + len + after > PAGE_SIZE {
+                return Err(corrupted());
+            }
+            *ptr += len;
+            Ok(&page[*ptr - len..*ptr])
+        };
+        let read_name = |ptr: &mut usize, after: usize| {
+            String::from_utf8(read_bytes(ptr, after)?.to_vec()).map_err(|_| corrupted())
+        };
+        let mut catalog = Self {
+            entries: Vec::new(),
+            page_size: read_u32(page, 4) as usize,
+            frozen: page[8] != 0,
+            free_map: None,
+            autovacuum: false,
+            page_count: 0,
+        };
+        let mut ptr = 9;
+        if page[1] >= FREE_MAP_VERSION {
+            catalog.free_map = Some(read_u64(page, 9)).filter(|&dir| dir != 0);
+            ptr = 17;
+        }
+        if page[1] >= AUTOVACUUM_VERSION {
+            catalog.autovacuum = page[17] != 0;
+            catalog.page_count = read_u64(page, 18);
+            ptr = 26;
+        }
+        for _ in 0..read_u16(page, 2) {
+            let name = read_name(&mut ptr, 10)?;
+            let root_page = read_u64(page, ptr);
+            ptr += 8;
+            let column_count = read_u16(page, ptr) as usize;
+            ptr += 2;
+            if ptr + column_count + 2 > PAGE_SIZE {
+                return Err(corrupted());
+            }
+            let value_types: Vec<_> = page[ptr..ptr + column_count]
+                .iter()
+                .map(|&id| ValueType::from_id(id).ok_or_else(corrupted))
+                .collect::<IOResult<_>>()?;
+            if page[1] < TIMESTAMP_ORDER_VERSION && value_types.contains(&ValueType::Timestamp) {
+                return Err(CatalogError::OldTimestamps(name).into());
+            }
+            ptr += column_count;
+            let name_count = read_u16(page, ptr) as usize;
+            ptr += 2;
+            if name_count != 0 && name_count != column_count {
+                return Err(corrupted());
+            }
+            let columns = (0..name_count)
+                .map(|_| read_name(&mut ptr, 0))
+                .collect::<IOResult<_>>()?;
+            if ptr + 2 > PAGE_SIZE {
+                return Err(corrupted());
+            }
+            let index_count = read_u16(page, ptr) as usize;
+            ptr += 2;
+            if ptr + index_count * 11 + 1 + has_layout as usize > PAGE_SIZE {
+                return Err(corrupted());
+            }
+            let mut indexes = Vec::new();
+            for _ in 0..index_count {
+                let column = read_u16(page, ptr) as usize;
+                if column >= column_count {
+                    return Err(corrupted());
+                }
+                indexes.push(Index {
+                    column,
+                    unique: page[ptr + 2] != 0,
+                    root_page: read_u64(page, ptr + 3),
+                });
+                ptr += 11;
+            }
+            let rowid_mode = RowidMode::from_id(page[ptr]).ok_or_else(corrupted)?;
+            ptr += 1;
+            let layout = if has_layout {
+                ptr += 1;
+                RecordLayout::from_id(page[ptr - 1]).ok_or_else(corrupted)?
+            } else {
+                RecordLayout::Chained
+            };
+            let mut defaults = vec![None; column_count];
+            if has_defaults {
+                for (default, value_type) in defaults.iter_mut().zip(&value_types) {
+                    if ptr + 1 > PAGE_SIZE {
+                        return Err(corrupted());
+                    }
+                    ptr += 1;
+                    if page[ptr - 1] != 0 {
+                        *default = Some(Value::new(value_type.clone(), read_bytes(&mut ptr, 0)?));
+                    }
+                }
+            }
+            let checksum = if has_checksum {
+                if ptr + 1 > PAGE_SIZE {
+                    return Err(corrupted());
+                }
+                ptr += 1;
+                page[ptr - 1] != 0
+            } else {
+                false
+            };
+            catalog.entries.push(CatalogEntry {
+                name,
+                root_page,
+                value_types,
+                columns,
+                indexes,
+                rowid_mode,
+                layout,
+                defaults,
+                checksum,
+            });
+        }
+        Ok(catalog)
+    }
+    /** Dump to bytes */
+    pub fn dump(&self) -> IOResult<[u8; PAGE_SIZE]> {
+        let mut page = [0; PAGE_SIZE];
+        page[0] = PAGE_TYPEID_CATALOG;
+        page[1] = CATALOG_VERSION;
+        write_u16(&mut page, 2, self.entries.len() as u16);
+        write_u32(&mut page, 4, PAGE_SIZE as u32);
+        page[8] = self.frozen as u8;
+        write_u64(&mut page, 9, self.free_map.unwrap_or(0));
+        page[17] = self.autovacuum as u8;
+        write_u64(&mut page, 18, self.page_count);
+        let mut ptr = 26;
+        for entry in &self.entries {
+            let size = 2 + entry.name.len() + 8 + 2 + entry.value_types.len() + 2;
+            let size = size + entry.columns.iter().map(|c| 2 + c.len()).sum::<usize>();
+            let size = size + 2 + entry.indexes.len() * 11 + 3;
+            let size = size
+                + entry
+                    .defaults
+                    .iter()
+                    .map(|default| default.as_ref().map_or(1, |value| 3 + value.data.len()))
+                    .sum::<usize>();
+            if ptr + size > PAGE_SIZE {
+                return Err(CatalogError::Full.into());
+            }
+            write_u16(&mut page, ptr, entry.name.len() as u16);
+            ptr += 2;
+            page[ptr..ptr + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+            ptr += entry.name.len();
+            write_u64(&mut page, ptr, entry.root_page);
+            ptr += 8;
+            write_u16(&mut page, ptr, entry.value_types.len() as u16);
+            ptr += 2;
+            for value_type in &entry.value_types {
+                page[ptr] = value_type.id();
+                ptr += 1;
+            }
+            write_u16(&mut page, ptr, entry.columns.len() as u16);
+            ptr += 2;
+            for column in &entry.columns {
+                write_u16(&mut page, ptr, column.len() as u16);
+                ptr += 2;
+                page[ptr..ptr + column.len()].copy_from_slice(column.as_bytes());
+                ptr += column.len();
+            }
+            write_u16(&mut page, ptr, entry.indexes.len() as u16);
+            ptr += 2;
+            for index in &entry.indexes {
+                write_u16(&mut page, ptr, index.column as u16);
+                page[ptr + 2] = index.unique as u8;
+                write_u64(&mut page, ptr + 3, index.root_page);
+                ptr += 11;
+            }
+            page[ptr] = entry.rowid_mode.id();
+            page[ptr + 1] = entry.layout.id();
+            ptr += 2;
+            for default in &entry.defaults {
+                page[ptr] = default.is_some() as u8;
+                ptr += 1;
+                if let Some(value) = default {
+                    write_u16(&mut page, ptr, value.data.len() as u16);
+                    ptr += 2;
+                    page[ptr..ptr + value.data.len()].copy_from_slice(&value.data);
+                    ptr += value.data.len();
+                }
+            }
+            page[ptr] = entry.checksum as u8;
+            ptr += 1;
+        }
+        Ok(page)
+    }
+    /** Write the catalog to its page */
+    pub fn save<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let page = self.dump()?;
+        mgr.modify(device, CATALOG_PAGE, &page)
+    }
+    pub fn find(&self, name: &str) -> Option<&CatalogEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+    /** Add a table schema, replacing the one with the same name */
+    pub fn set(&mut self, entry: CatalogEntry) {
+        match self.entries.iter_mut().find(|e| e.name == entry.name) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+    /** Remove a table schema by name */
+    pub fn remove(&mut self, name: &str) -> Option<CatalogEntry> {
+        let index = self.entries.iter().position(|entry| entry.name == name)?;
+        Some(self.entries.remove(index))
+    }
+}
+
+/** Schema of one column, as compared by `Catalog::diff` */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    /** `None` for an unnamed column */
+    pub name: Option<String>,
+    pub value_type: ValueType,
+}
+
+impl fmt::Display for ColumnSchema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} {:?}", name, self.value_type),
+            None => write!(f, "{:?}", self.value_type),
+        }
+    }
+}
+
+/** A column that differs, columns are matched by position since values are stored in order */
+#[derive(Debug, PartialEq)]
+pub struct ColumnDiff {
+    pub column: usize,
+    /** `None` if the table has no such column on this side */
+    pub left: Option<ColumnSchema>,
+    pub right: Option<ColumnSchema>,
+}
+
+/** An index that differs */
+#[derive(Debug, PartialEq)]
+pub struct IndexDiff {
+    pub column: usize,
+    /** Whether the index is unique, `None` if the column has no index on this side */
+    pub left: Option<bool>,
+    pub right: Option<bool>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TableDiff {
+    pub name: String,
+    pub columns: Vec<ColumnDiff>,
+    pub indexes: Vec<IndexDiff>,
+}
+
+/** Differences between the schemas of two catalogs, built by `Catalog::diff`
+ *
+ * Displayed in the style of a unified diff: `-` lines are from the left catalog and `+` lines
+ * from the right one. */
+#[derive(Debug, Default, PartialEq)]
+pub struct SchemaDiff {
+    pub only_left: Vec<String>,
+    pub only_right: Vec<String>,
+    /** Tables on both sides whose schemas differ */
+    pub tables: Vec<TableDiff>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty() && self.tables.is_empty()
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for name in &self.only_left {
+            writeln!(f, "- table {}", name)?;
+        }
+        for name in &self.only_right {
+            writeln!(f, "+ table {}", name)?;
+        }
+        for table in &self.tables {
+            writeln!(f, "  table {}", table.name)?;
+            for diff in &table.columns {
+                if let Some(left) = &diff.left {
+                    writeln!(f, "-   column {}: {}", diff.column, left)?;
+                }
+                if let Some(right) = &diff.right {
+                    writeln!(f, "+   column {}: {}", diff.column, right)?;
+                }
+            }
+            for diff in &table.indexes {
+                for (sign, unique) in [("-", diff.left), ("+", diff.right)] {
+                    match unique {
+                        Some(true) => {
+                            writeln!(f, "{}   unique index on column {}", sign, diff.column)?
+                        }
+                        Some(false) => writeln!(f, "{}   index on column {}", sign, diff.column)?,
+                        None => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CatalogEntry {
+    fn column_schema(&self, column: usize) -> Option<ColumnSchema> {
+        Some(ColumnSchema {
+            name: self.columns.get(column).cloned(),
+            value_type: self.value_types.get(column)?.clone(),
+        })
+    }
+    /** Differences with the schema of the same table in another catalog, `None` if equal */
+    fn diff(&self, other: &Self) -> Option<TableDiff> {
+        let column_count = self.value_types.len().max(other.value_types.len());
+        let columns: Vec<_> = (0..column_count)
+            .map(|column| ColumnDiff {
+                column,
+                left: self.column_schema(column),
+                right: other.column_schema(column),
+            })
+            .filter(|diff| diff.left != diff.right)
+            .collect();
+
+        let unique_of = |entry: &Self, column| {
+            entry
+                .indexes
+                .iter()
+                .find(|index| index.column == column)
+                .map(|index| index.unique)
+        };
+        let mut indexed: Vec<_> = self
+            .indexes
+            .iter()
+            .chain(&other.indexes)
+            .map(|index| index.column)
+            .collect();
+        indexed.sort_unstable();
+        indexed.dedup();
+        let indexes: Vec<_> = indexed
+            .into_iter()
+            .map(|column| IndexDiff {
+                column,
+                left: unique_of(self, column),
+                right: unique_of(other, column),
+            })
+            .filter(|diff| diff.left != diff.right)
+            .collect();
+
+        if columns.is_empty() && indexes.is_empty() {
+            return None;
+        }
+        Some(TableDiff {
+            name: self.name.clone(),
+            columns,
+            indexes,
+        })
+    }
+}
+
+impl Catalog {
+    /** Compare table schemas with another catalog, root pages are not compared */
+    pub fn diff(&self, other: &Self) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        for entry in &self.entries {
+            match other.find(&entry.name) {
+                Some(other_entry) => diff.tables.extend(entry.diff(other_entry)),
+                None => diff.only_left.push(entry.name.clone()),
+            }
+        }
+        for entry in &other.entries {
+            if self.find(&entry.name).is_none() {
+                diff.only_right.push(entry.name.clone());
+            }
+        }
+        diff
+    }
+}
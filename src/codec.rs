@@ -0,0 +1,108 @@
+/** Flag bit of a 15-bit length field */
+const LEN15_FLAG: u16 = 1 << 15;
+
+pub fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+pub fn write_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+pub fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+    buf[offset..offset + 8].copy_from_slice(&value.to_be_bytes());
+}
+
+/** Read a 2-byte field holding a flag in the highest bit and a 15-bit length
+ *
+ * Return:
+ * * flag
+ * * length */
+pub fn read_len15(buf: &[u8], offset: usize) -> (bool, usize) {
+    let raw = read_u16(buf, offset);
+    (raw & LEN15_FLAG != 0, (raw & !LEN15_FLAG) as usize)
+}
+
+/** Write a 2-byte field holding a flag in the highest bit and a 15-bit length */
+pub fn write_len15(buf: &mut [u8], offset: usize, flag: bool, len: usize) {
+    assert!(
+        len < LEN15_FLAG as usize,
+        "length {} does not fit in 15 bits",
+        len
+    );
+    let mut raw = len as u16;
+    if flag {
+        raw |= LEN15_FLAG;
+    }
+    write_u16(buf, offset, raw);
+}
+
+/** Remainders of the reflected CRC-32 polynomial for every byte */
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/** CRC-32 of `data` as zlib computes it, continuing from the CRC of the data before, 0 at first */
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn len15_round_trip() {
+        for (flag, len, raw) in [
+            (false, 0, [0x00, 0x00]),
+            (false, 1, [0x00, 0x01]),
+            (false, 0x7FFF, [0x7F, 0xFF]),
+            (true, 0, [0x80, 0x00]),
+            (true, 1, [0x80, 0x01]),
+            (true, 0x7FFF, [0xFF, 0xFF]),
+        ] {
+            let mut buf = [0xAA; 4];
+            write_len15(&mut buf, 1, flag, len);
+            assert_eq!(buf, [0xAA, raw[0], raw[1], 0xAA]);
+            assert_eq!(read_len15(&buf, 1), (flag, len));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit in 15 bits")]
+    fn len15_too_long() {
+        write_len15(&mut [0; 2], 0, false, 0x8000);
+    }
+}
@@ -0,0 +1,858 @@
+#[cfg(feature = "low-level")]
+use crate::btree::BtreeNode;
+use crate::catalog::*;
+#[cfg(feature = "low-level")]
+use crate::codec::*;
+use crate::page::*;
+use crate::table::*;
+use sql_parser::function::{Functions, ScalarFunction};
+use std::fs::OpenOptions;
+use std::io::{Result as IOResult, *};
+use std::path::Path;
+
+/** Decoded type of a page with a summary of its content */
+#[cfg(feature = "low-level")]
+#[derive(Debug, PartialEq)]
+pub enum PageKind {
+    /** Count of pages marked as used, including the bitmap page itself */
+    Bitmap {
+        used: usize,
+    },
+    BtreeInternal {
+        keys: usize,
+    },
+    BtreeLeaf {
+        keys: usize,
+    },
+    /** Count of entry slots, tombstones included, and of live entries */
+    Content {
+        entries: usize,
+        live: usize,
+    },
+    Overflow {
+        next: Option<u64>,
+    },
+    Catalog {
+        tables: usize,
+    },
+    /** Count of free-space map pages listed */
+    FreeMapDir {
+        maps: usize,
+    },
+    /** Count of pages recorded as content pages */
+    FreeMap {
+        content: usize,
+    },
+    /** Type byte of a page whose content does not decode as its type */
+    Corrupt(u8),
+    /** Type byte of a page that is none of the above, 0 for a free or never-written page */
+    Unknown(u8),
+}
+
+#[cfg(feature = "low-level")]
+#[derive(Debug)]
+pub struct PageInfo {
+    pub count: u64,
+    pub kind: PageKind,
+    /** Marked as used in its bitmap */
+    pub used: bool,
+}
+
+#[cfg(feature = "low-level")]
+impl PageKind {
+    /** Decode through the checked loaders where a type has one, checking every length against
+     * the page size */
+    fn decode(count: u64, data: &[u8; PAGE_SIZE]) -> Self {
+        if bitmap_count_of(count) == count {
+            let used = data.iter().map(|byte| byte.count_ones() as usize).sum();
+            return Self::Bitmap { used };
+        }
+        match data[0] {
+            PAGE_TYPEID_BTREE_INTERNAL => Self::BtreeInternal {
+                keys: BtreeNode::load(data).len(),
+            },
+            PAGE_TYPEID_BTREE_LEAF => Self::BtreeLeaf {
+                keys: BtreeNode::load(data).len(),
+            },
+            PAGE_TYPEID_CONTENT => match ContentPage::load(count, data) {
+                Ok(page) => Self::Content {
+                    entries: page.entries.len(),
+                    live: page.entries.iter().filter(|entry| !entry.deleted).count(),
+                },
+                Err(_) => Self::Corrupt(data[0]),
+            },
+            PAGE_TYPEID_OVERFLOW => {
+                let (overflowed, size) = read_len15(data, 1);
+                if (if overflowed { 11 } else { 3 }) + size > PAGE_SIZE {
+                    return Self::Corrupt(data[0]);
+                }
+                Self::Overflow {
+                    next: overflowed.then(|| read_u64(data, 3)),
+                }
+            }
+            PAGE_TYPEID_CATALOG => match Catalog::load(data) {
+                Ok(catalog) => Self::Catalog {
+                    tables: catalog.entries.len(),
+                },
+                Err(_) => Self::Corrupt(data[0]),
+            },
+            PAGE_TYPEID_FREE_MAP_DIR => Self::FreeMapDir {
+                maps: (0..FREE_MAP_DIR_SLOTS)
+                    .filter(|slot| read_u64(data, 8 + 8 * slot) != 0)
+                    .count(),
+            },
+            PAGE_TYPEID_FREE_MAP => Self::FreeMap {
+                content: data[8..].iter().filter(|&&byte| byte != 0).count(),
+            },
+            page_type => Self::Unknown(page_type),
+        }
+    }
+}
+
+/** Iterator over every page of a database in ascending order, from `Database::pages`
+ *
+ * Pages are read without being added to the cache, so iterating does not evict hot pages. */
+#[cfg(feature = "low-level")]
+pub struct Pages<'a, D> {
+    db: &'a mut Database<D>,
+    next: u64,
+    end: u64,
+}
+
+#[cfg(feature = "low-level")]
+impl<D> Iterator for Pages<'_, D>
+where
+    D: Write + Read + Seek,
+{
+    type Item = IOResult<PageInfo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let count = self.next;
+        self.next += 1;
+        let info = self
+            .db
+            .mgr
+            .get_data_bypass(&mut self.db.device, count)
+            .and_then(|data| {
+                Ok(PageInfo {
+                    count,
+                    kind: PageKind::decode(count, &data),
+                    used: self.db.mgr.is_used(&mut self.db.device, count)?,
+                })
+            });
+        if info.is_err() {
+            self.next = self.end;
+        }
+        Some(info)
+    }
+}
+
+/** Database file holding any number of named tables
+ *
+ * Tables share content pages, it is safe since every record is reached from the B-tree of its
+ * own table and never by scanning content pages. */
+pub struct Database<D> {
+    pub device: D,
+    pub mgr: PageManage,
+    /** Scalar functions callable from expressions evaluated against this database, the
+     * built-in ones and those added with `create_function` */
+    pub functions: Functions,
+}
+
+impl<D> Database<D>
+where
+    D: Write + Read + Seek,
+{
+    /** Open a database, reserving the catalog page in a new file */
+    pub fn open(mut device: D, mut mgr: PageManage) -> IOResult<Self> {
+        Catalog::open(&mut device, &mut mgr)?;
+        Ok(Self {
+            device,
+            mgr,
+            functions: Functions::default(),
+        })
+    }
+    /** Open an existing database and refuse every change to it
+     *
+     * A file saved with another page size than `PAGE_SIZE` can be opened this way to salvage what
+     * is still readable. */
+    pub fn open_read_only(device: D, mut mgr: PageManage) -> IOResult<Self> {
+        mgr.read_only = true;
+        Self::open(device, mgr)
+    }
+    /** Register a scalar function, see `Functions::create_function`
+     *
+     * Functions live in memory only and have to be registered again on every open. */
+    pub fn create_function(&mut self, name: &str, arity: Option<usize>, function: ScalarFunction) {
+        self.functions.create_function(name, arity, function);
+    }
+    /** Install instrumentation callbacks, replacing any installed before, see `Hooks`
+     *
+     * Like functions, hooks live in memory only. */
+    pub fn set_hooks(&mut self, hooks: Box<dyn Hooks>) {
+        self.mgr.set_hooks(hooks);
+    }
+    /** Create an empty table named `name` */
+    pub fn create_table(&mut self, name: &str, value_types: Vec<ValueType>) -> IOResult<Table> {
+        if Catalog::open(&mut self.device, &mut self.mgr)?
+            .find(name)
+            .is_some()
+        {
+            return Err(CatalogError::TableExists(name.to_owned()).into());
+        }
+        let table = Table::create(&mut self.device, &mut self.mgr, value_types)?;
+        table.save_schema(&mut self.device, &mut self.mgr, name)?;
+        Ok(table)
+    }
+    /** Create an empty table named `name` with named columns */
+    pub fn create_table_with_columns(
+        &mut self,
+        name: &str,
+        columns: Vec<(String, ValueType)>,
+    ) -> IOResult<Table> {
+        let (names, value_types): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+        /* validate before anything is allocated */
+        Table::check_columns(&names, value_types.len())?;
+        let mut created = self.create_table(name, value_types)?;
+        created.columns = names;
+        created.save_schema(&mut self.device, &mut self.mgr, name)?;
+        Ok(created)
+    }
+    /** Open the table named `name` */
+    pub fn table(&mut self, name: &str) -> IOResult<Table> {
+        Table::open(&mut self.device, &mut self.mgr, name)
+    }
+    /** Names of every table, in creation order */
+    pub fn table_names(&mut self) -> IOResult<Vec<String>> {
+        let catalog = Catalog::open(&mut self.device, &mut self.mgr)?;
+        Ok(catalog
+            .entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect())
+    }
+    /** Compare the table schemas with another database, only the catalogs are read */
+    pub fn schema_diff<E>(&mut self, other: &mut Database<E>) -> IOResult<SchemaDiff>
+    where
+        E: Write + Read + Seek,
+    {
+        let catalog = Catalog::open(&mut self.device, &mut self.mgr)?;
+        let other_catalog = Catalog::open(&mut other.device, &mut other.mgr)?;
+        Ok(catalog.diff(&other_catalog))
+    }
+    /** Compare the index of `column` of the table named `name` with its records */
+    pub fn verify_index(&mut self, name: &str, column: usize) -> IOResult<Vec<IndexMismatch>> {
+        self.table(name)?
+            .verify_index(&mut self.device, &mut self.mgr, column)
+    }
+    /** Rebuild the index of `column` of the table named `name` from its records */
+    pub fn reindex(&mut self, name: &str, column: usize) -> IOResult<()> {
+        self.table(name)?
+            .reindex(&mut self.device, &mut self.mgr, column)
+    }
+    /** Append a column to the table named `name` and save its schema, see `Table::add_column` */
+    pub fn add_column(
+        &mut self,
+        name: &str,
+        column: Option<String>,
+        value_type: ValueType,
+        default: Option<Value>,
+    ) -> IOResult<()> {
+        self.table(name)?.add_column(
+            &mut self.device,
+            &mut self.mgr,
+            name,
+            column,
+            value_type,
+            default,
+        )
+    }
+    /** Measure the storage of the table named `name` */
+    pub fn table_stats(&mut self, name: &str) -> IOResult<TableStats> {
+        self.table(name)?.stats(&mut self.device, &mut self.mgr)
+    }
+    /** Rewrite every record of the table named `name` with `layout` and save its schema */
+    pub fn set_layout(&mut self, name: &str, layout: RecordLayout) -> IOResult<()> {
+        let mut table = self.table(name)?;
+        table.set_layout(&mut self.device, &mut self.mgr, layout)?;
+        table.save_schema(&mut self.device, &mut self.mgr, name)
+    }
+    /** Add a checksum to every record of the table named `name` or drop it, and save its schema */
+    pub fn set_checksum(&mut self, name: &str, checksum: bool) -> IOResult<()> {
+        let mut table = self.table(name)?;
+        table.set_checksum(&mut self.device, &mut self.mgr, checksum)?;
+        table.save_schema(&mut self.device, &mut self.mgr, name)
+    }
+    /** Delete every record of the table named `name`, return how many were deleted */
+    pub fn truncate_table(&mut self, name: &str) -> IOResult<u64> {
+        self.table(name)?.truncate(&mut self.device, &mut self.mgr)
+    }
+    /** Delete every record of the table named `name`, release its B-tree and forget its schema */
+    pub fn drop_table(&mut self, name: &str) -> IOResult<()> {
+        let mut table = self.table(name)?;
+        table.truncate(&mut self.device, &mut self.mgr)?;
+        /* truncating leaves only the roots */
+        self.mgr.release(&mut self.device, table.root_page)?;
+        for index in &table.indexes {
+            self.mgr.release(&mut self.device, index.root_page)?;
+        }
+
+        let mut catalog = Catalog::open(&mut self.device, &mut self.mgr)?;
+        catalog.remove(name);
+        catalog.save(&mut self.device, &mut self.mgr)
+    }
+    /** Write a compact copy of the database into a new file at `path` and freeze it
+     *
+     * Every table is copied with `Table::copy_packed`. Whenever the copy is opened afterwards,
+     * any change to it fails with `PageError::Immutable`. */
+    pub fn freeze_to(&mut self, path: &Path) -> IOResult<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        let mut frozen = Database::open(file, PageManage::new(DEFAULT_CACHE_SIZE))?;
+        for entry in Catalog::open(&mut self.device, &mut self.mgr)?.entries {
+            let table = self.table(&entry.name)?;
+            table
+                .copy_packed(
+                    &mut self.device,
+                    &mut self.mgr,
+                    &mut frozen.device,
+                    &mut frozen.mgr,
+                )?
+                .save_schema(&mut frozen.device, &mut frozen.mgr, &entry.name)?;
+        }
+        frozen.checkpoint()?;
+        let mut catalog = Catalog::open(&mut frozen.device, &mut frozen.mgr)?;
+        catalog.frozen = true;
+        catalog.save(&mut frozen.device, &mut frozen.mgr)?;
+        frozen.mgr.sync_all(&mut frozen.device)?;
+        frozen.device.sync_all()
+    }
+    /** Turn cutting the free pages at the end of the file off on every `checkpoint` on or off
+     *
+     * The setting is saved in the catalog, so every later open of the file has it. */
+    pub fn set_autovacuum(&mut self, autovacuum: bool) -> IOResult<()> {
+        let mut catalog = Catalog::open(&mut self.device, &mut self.mgr)?;
+        catalog.autovacuum = autovacuum;
+        catalog.save(&mut self.device, &mut self.mgr)?;
+        self.mgr.autovacuum = autovacuum;
+        Ok(())
+    }
+    /** Sync every page, then cut the free pages at the end of the file off with autovacuum, see
+     * `PageManage::checkpoint`
+     *
+     * The count of pages the file is left with is recorded in the catalog and synced before the
+     * file is cut. Return the number of pages cut off. */
+    pub fn checkpoint(&mut self) -> IOResult<u64>
+    where
+        D: Truncate,
+    {
+        self.mgr.sync_all(&mut self.device)?;
+        let page_count = if self.mgr.autovacuum {
+            self.mgr.used_len(&mut self.device)?
+        } else {
+            self.device.seek(SeekFrom::End(0))? / PAGE_SIZE as u64
+        };
+        let mut catalog = Catalog::open(&mut self.device, &mut self.mgr)?;
+        if catalog.page_count != page_count {
+            catalog.page_count = page_count;
+            catalog.save(&mut self.device, &mut self.mgr)?;
+        }
+        self.mgr.checkpoint(&mut self.device)
+    }
+    /** Iterate every page of the file, including pages only allocated in the cache so far */
+    #[cfg(feature = "low-level")]
+    pub fn pages(&mut self) -> IOResult<Pages<'_, D>> {
+        let device_pages = self.device.seek(SeekFrom::End(0))? / PAGE_SIZE as u64;
+        let cached_end = self.mgr.cached_pages().last().map_or(0, |count| count + 1);
+        Ok(Pages {
+            end: device_pages.max(cached_end),
+            next: 0,
+            db: self,
+        })
+    }
+    /** Read the raw bytes of an allocated page, see `PageManage::raw_read_page` */
+    #[cfg(feature = "low-level")]
+    pub fn raw_read_page(&mut self, count: u64) -> IOResult<[u8; PAGE_SIZE]> {
+        self.mgr.raw_read_page(&mut self.device, count)
+    }
+    /** Overwrite the raw bytes of a page, see `PageManage::raw_write_page`
+     *
+     * Misuse corrupts the file, and nothing but checking its pages afterwards tells. */
+    #[cfg(feature = "low-level")]
+    pub fn raw_write_page(
+        &mut self,
+        count: u64,
+        data: &[u8; PAGE_SIZE],
+        force: bool,
+    ) -> IOResult<()> {
+        self.mgr
+            .raw_write_page(&mut self.device, count, data, force)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sql_parser::expr::{parse_list, Literal};
+    use sql_parser::limits::Limits;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /** Hooks adding up every event into a `PageStats` shared with the test */
+    struct Counting(Rc<RefCell<PageStats>>);
+
+    impl Hooks for Counting {
+        fn on_page_read(&mut self, _count: u64) {
+            self.0.borrow_mut().reads += 1;
+        }
+        fn on_page_write(&mut self, _count: u64) {
+            self.0.borrow_mut().writes += 1;
+        }
+        fn on_alloc(&mut self, _count: u64) {
+            self.0.borrow_mut().allocs += 1;
+        }
+        fn on_query(&mut self, _table: u64, rows: u64, _duration: Duration) {
+            let mut stats = self.0.borrow_mut();
+            stats.queries += 1;
+            stats.rows += rows;
+        }
+        fn on_insert(&mut self, _table: u64, bytes: u64) {
+            let mut stats = self.0.borrow_mut();
+            stats.inserts += 1;
+            stats.bytes += bytes;
+        }
+    }
+
+    struct Panicking;
+
+    impl Hooks for Panicking {
+        fn on_query(&mut self, _table: u64, _rows: u64, _duration: Duration) {
+            panic!("hook failure");
+        }
+    }
+
+    #[test]
+    fn hooks_see_what_page_stats_count() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::new(4)).unwrap();
+        let counted = Rc::new(RefCell::new(PageStats::default()));
+        db.set_hooks(Box::new(Counting(counted.clone())));
+        let before = db.mgr.stats();
+
+        let mut table = db
+            .create_table("items", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 0, false)
+            .unwrap();
+        for i in 0..300u64 {
+            let rec = Record::builder()
+                .number(i % 7)
+                .bytes(&vec![b'x'; i as usize])
+                .build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        table.query(&mut db.device, &mut db.mgr, 5).unwrap();
+        table
+            .query_range(&mut db.device, &mut db.mgr, 10..20)
+            .unwrap();
+        let found = table
+            .find(&mut db.device, &mut db.mgr, 0, &Value::from_u64(3))
+            .unwrap()
+            .collect(&mut db.device, &mut db.mgr)
+            .unwrap();
+        assert_eq!(found.len(), 43);
+        db.mgr.sync_all(&mut db.device).unwrap();
+
+        let after = db.mgr.stats();
+        let counted = *counted.borrow();
+        assert_eq!(counted.reads, after.reads - before.reads);
+        assert_eq!(counted.writes, after.writes - before.writes);
+        assert_eq!(counted.allocs, after.allocs - before.allocs);
+        assert_eq!(counted.queries, 3);
+        assert_eq!(counted.rows, 1 + 10 + 43);
+        assert_eq!(counted.inserts, 300);
+        assert_eq!(counted.bytes, 300 * 8 + (0..300).sum::<u64>());
+        assert_eq!(
+            (
+                counted.queries,
+                counted.rows,
+                counted.inserts,
+                counted.bytes
+            ),
+            (after.queries, after.rows, after.inserts, after.bytes)
+        );
+        assert!(counted.reads > 0 && counted.writes > 0 && counted.allocs > 0);
+
+        /* a hook that panics is dropped, and the database goes on */
+        db.set_hooks(Box::new(Panicking));
+        table.query(&mut db.device, &mut db.mgr, 6).unwrap();
+        table.query(&mut db.device, &mut db.mgr, 7).unwrap();
+        assert_eq!(db.mgr.stats().queries, after.queries + 2);
+    }
+
+    /** Catalog page as written before `TIMESTAMP_ORDER_VERSION`, without the free-space map and
+     * the autovacuum fields */
+    fn downgrade(mut page: [u8; PAGE_SIZE]) -> [u8; PAGE_SIZE] {
+        page[1] = TIMESTAMP_ORDER_VERSION - 1;
+        page.copy_within(26.., 9);
+        page
+    }
+
+    #[test]
+    fn old_timestamps_are_refused() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        db.create_table("plain", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        let page = downgrade(db.mgr.get_data(&mut db.device, CATALOG_PAGE).unwrap());
+        assert_eq!(Catalog::load(&page).unwrap().entries.len(), 1);
+
+        db.create_table("events", vec![ValueType::Timestamp])
+            .unwrap();
+        let page = downgrade(db.mgr.get_data(&mut db.device, CATALOG_PAGE).unwrap());
+        let err = Catalog::load(&page).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = err
+            .into_inner()
+            .unwrap()
+            .downcast::<CatalogError>()
+            .unwrap();
+        assert!(matches!(*err, CatalogError::OldTimestamps(name) if name == "events"));
+    }
+
+    #[test]
+    fn free_map_is_built_for_an_older_file() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let mut table = db.create_table("plain", vec![ValueType::Bytes]).unwrap();
+        for len in [100, 3800, 500] {
+            let rec = Record::builder().bytes(&vec![1; len]).build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        let page = downgrade(db.mgr.get_data(&mut db.device, CATALOG_PAGE).unwrap());
+        db.mgr.modify(&mut db.device, CATALOG_PAGE, &page).unwrap();
+        db.mgr.sync_all(&mut db.device).unwrap();
+
+        let mut db = Database::open(db.device, PageManage::default()).unwrap();
+        let dir = db.mgr.free_map.unwrap();
+        let page = db.mgr.get_data(&mut db.device, CATALOG_PAGE).unwrap();
+        assert_eq!(page[1], CATALOG_VERSION);
+        assert_eq!(Catalog::load(&page).unwrap().free_map, Some(dir));
+        /* the last record did not fit next to the first two, its page is the one with room */
+        let table = db.table("plain").unwrap();
+        let last = table.query(&mut db.device, &mut db.mgr, 2).unwrap();
+        let found = db.mgr.find_free_space(&mut db.device, 0, 1000).unwrap();
+        assert_eq!(found, Some(last.location[0].0));
+    }
+
+    #[test]
+    fn autovacuum_gives_the_pages_of_dropped_tables_back() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let mut kept = db.create_table("kept", vec![ValueType::Bytes]).unwrap();
+        for i in 0..20u8 {
+            let rec = Record::builder().bytes(&[i; 300]).build();
+            kept.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        let page_count = |db: &mut Database<Cursor<Vec<u8>>>| {
+            let catalog = Catalog::open(&mut db.device, &mut db.mgr).unwrap();
+            (catalog.page_count, db.device.get_ref().len() / PAGE_SIZE)
+        };
+        let fill = |db: &mut Database<Cursor<Vec<u8>>>, name: &str| {
+            let mut table = db.create_table(name, vec![ValueType::Bytes]).unwrap();
+            for i in 0..30u8 {
+                let rec = Record::builder().bytes(&vec![i; 10000]).build();
+                table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+            }
+        };
+        assert_eq!(db.checkpoint().unwrap(), 0);
+        let (recorded, small) = page_count(&mut db);
+        assert_eq!(recorded as usize, small);
+        fill(&mut db, "large");
+        fill(&mut db, "larger");
+
+        /* off by default, the file keeps its length */
+        db.drop_table("larger").unwrap();
+        assert_eq!(db.checkpoint().unwrap(), 0);
+        let (recorded, full) = page_count(&mut db);
+        assert_eq!(recorded as usize, full);
+
+        db.set_autovacuum(true).unwrap();
+        db.drop_table("large").unwrap();
+        let cut = db.checkpoint().unwrap() as usize;
+        let (recorded, len) = page_count(&mut db);
+        assert!(cut > 0 && len == full - cut && len <= small + 1);
+        assert_eq!(recorded as usize, len);
+
+        /* reopened, the flag is read from the catalog */
+        let mut db = Database::open(db.device, PageManage::default()).unwrap();
+        assert!(db.mgr.autovacuum);
+        let kept = db.table("kept").unwrap();
+        for rowid in [0, 19] {
+            let rec = kept.query(&mut db.device, &mut db.mgr, rowid).unwrap();
+            assert_eq!(rec.bytes(0).unwrap(), [rowid as u8; 300]);
+        }
+        /* the file grows back over the cut pages */
+        fill(&mut db, "again");
+        db.checkpoint().unwrap();
+        let (recorded, grown) = page_count(&mut db);
+        assert!(recorded as usize == grown && grown > len);
+        let mut db = Database::open(db.device, PageManage::default()).unwrap();
+        let again = db.table("again").unwrap();
+        let rec = again.query(&mut db.device, &mut db.mgr, 29).unwrap();
+        assert_eq!(rec.bytes(0).unwrap(), [29; 10000]);
+        db.set_autovacuum(false).unwrap();
+        db.checkpoint().unwrap();
+        let db = Database::open(db.device, PageManage::default()).unwrap();
+        assert!(!db.mgr.autovacuum);
+    }
+
+    #[cfg(feature = "low-level")]
+    #[test]
+    fn raw_pages_replicate_a_database() {
+        let mut source = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let mut table = source
+            .create_table("items", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        table
+            .create_index(&mut source.device, &mut source.mgr, 0, false)
+            .unwrap();
+        table
+            .save_schema(&mut source.device, &mut source.mgr, "items")
+            .unwrap();
+        for i in 0..400u64 {
+            /* a few values need overflow pages */
+            let len = if i % 100 == 0 { 10000 } else { 30 };
+            let rec = Record::builder()
+                .number(i % 9)
+                .bytes(&vec![i as u8; len])
+                .build();
+            table
+                .insert(&mut source.device, &mut source.mgr, rec)
+                .unwrap();
+        }
+        source
+            .create_table("empty", vec![ValueType::Bytes])
+            .unwrap();
+
+        /* unsynced changes are read from the cache */
+        let mut replica = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let used: Vec<u64> = source
+            .pages()
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|info| info.used)
+            .map(|info| info.count)
+            .collect();
+        let unused = (0..).find(|count| !used.contains(count)).unwrap();
+        let err = source.raw_read_page(unused).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        for &count in &used {
+            let data = source.raw_read_page(count).unwrap();
+            replica.raw_write_page(count, &data, true).unwrap();
+        }
+        replica.mgr.sync_all(&mut replica.device).unwrap();
+
+        let mut replica = Database::open(replica.device, PageManage::default()).unwrap();
+        assert_eq!(replica.table_names().unwrap(), ["items", "empty"]);
+        let copy = replica.table("items").unwrap();
+        for rowid in [0, 1, 100, 399] {
+            let ours = table
+                .query(&mut source.device, &mut source.mgr, rowid)
+                .unwrap();
+            let theirs = copy
+                .query(&mut replica.device, &mut replica.mgr, rowid)
+                .unwrap();
+            assert_eq!(ours.bytes(1).unwrap(), theirs.bytes(1).unwrap());
+        }
+        let found = copy
+            .find(
+                &mut replica.device,
+                &mut replica.mgr,
+                0,
+                &Value::from_u64(4),
+            )
+            .unwrap()
+            .collect(&mut replica.device, &mut replica.mgr)
+            .unwrap();
+        assert_eq!(found.len(), 44);
+        assert!(copy
+            .verify_index(&mut replica.device, &mut replica.mgr, 0)
+            .unwrap()
+            .is_empty());
+
+        /* a page the bitmap does not mark as used takes `force` */
+        let data = replica.raw_read_page(CATALOG_PAGE).unwrap();
+        let err = replica.raw_write_page(unused, &data, false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[cfg(feature = "low-level")]
+    #[test]
+    fn pages_summarize_content_pages_as_tables_load_them() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let mut table = db.create_table("items", vec![ValueType::Bytes]).unwrap();
+        for i in 0..10u8 {
+            let rec = Record::builder().bytes(&[i; 100]).build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        table.delete(&mut db.device, &mut db.mgr, 3).unwrap();
+        let content = |db: &mut Database<Cursor<Vec<u8>>>| {
+            db.pages()
+                .unwrap()
+                .map(Result::unwrap)
+                .find(|info| matches!(info.kind, PageKind::Content { .. } | PageKind::Corrupt(_)))
+                .unwrap()
+        };
+        let info = content(&mut db);
+        assert_eq!(
+            info.kind,
+            PageKind::Content {
+                entries: 10,
+                live: 9
+            }
+        );
+
+        /* the last entry runs past the end of the page */
+        let mut data = db.raw_read_page(info.count).unwrap();
+        let last = ContentPage::load(info.count, &data).unwrap().total_size() - 102;
+        write_len15(&mut data, last, false, PAGE_SIZE);
+        db.raw_write_page(info.count, &data, false).unwrap();
+        assert_eq!(
+            content(&mut db).kind,
+            PageKind::Corrupt(PAGE_TYPEID_CONTENT)
+        );
+        let err = table.query(&mut db.device, &mut db.mgr, 9).unwrap_err();
+        assert!(matches!(
+            PageError::of(&err),
+            Some(PageError::Corrupted { page, offset, .. }) if *page == info.count && *offset == last
+        ));
+    }
+
+    #[test]
+    fn frozen_copy_is_compact_and_immutable() {
+        let dir = std::env::temp_dir().join(format!("31database-freeze-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (path, frozen_path) = (dir.join("source.db"), dir.join("frozen.db"));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        let mut db = Database::open(file, PageManage::default()).unwrap();
+        let mut table = db
+            .create_table("items", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 0, false)
+            .unwrap();
+        table
+            .save_schema(&mut db.device, &mut db.mgr, "items")
+            .unwrap();
+        for i in 0..3000u64 {
+            let len = if i % 500 == 0 {
+                9000
+            } else {
+                (i % 60) as usize
+            };
+            let rec = Record::builder()
+                .number(i % 11)
+                .bytes(&vec![i as u8; len])
+                .build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        /* leaves holes for the copy to close */
+        for rowid in (0..3000).step_by(3) {
+            table.delete(&mut db.device, &mut db.mgr, rowid).unwrap();
+        }
+        db.create_table("empty", vec![ValueType::Bytes]).unwrap();
+        db.mgr.sync_all(&mut db.device).unwrap();
+        db.freeze_to(&frozen_path).unwrap();
+        let err = db.freeze_to(&frozen_path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let len_of = |path: &Path| std::fs::metadata(path).unwrap().len();
+        assert!(len_of(&frozen_path) < len_of(&path));
+
+        /* read-only without asking, from a file handle that cannot write */
+        let file = std::fs::File::open(&frozen_path).unwrap();
+        let mut frozen = Database::open(file, PageManage::default()).unwrap();
+        assert!(frozen.mgr.immutable);
+        assert_eq!(frozen.table_names().unwrap(), ["items", "empty"]);
+        let mut copy = frozen.table("items").unwrap();
+        assert_eq!(copy.len(&mut frozen.device, &mut frozen.mgr).unwrap(), 2000);
+        for rowid in 0..3000 {
+            let ours = table.query(&mut db.device, &mut db.mgr, rowid);
+            let theirs = copy.query(&mut frozen.device, &mut frozen.mgr, rowid);
+            match (ours, theirs) {
+                (Ok(ours), Ok(theirs)) => {
+                    assert_eq!(ours.u64(0).unwrap(), theirs.u64(0).unwrap());
+                    assert_eq!(ours.bytes(1).unwrap(), theirs.bytes(1).unwrap());
+                }
+                (Err(ours), Err(theirs)) => assert_eq!(ours.kind(), theirs.kind()),
+                _ => panic!("rowid {} differs", rowid),
+            }
+        }
+        for number in [0, 5, 10] {
+            let value = Value::from_u64(number);
+            let ours = table.find(&mut db.device, &mut db.mgr, 0, &value).unwrap();
+            let ours = ours.collect(&mut db.device, &mut db.mgr).unwrap();
+            let theirs = copy
+                .find(&mut frozen.device, &mut frozen.mgr, 0, &value)
+                .unwrap();
+            let theirs = theirs.collect(&mut frozen.device, &mut frozen.mgr).unwrap();
+            let rowids = |found: &[Record]| found.iter().map(|rec| rec.rowid).collect::<Vec<_>>();
+            assert_eq!(rowids(&ours), rowids(&theirs));
+        }
+
+        let immutable = |err: Error| matches!(PageError::of(&err), Some(PageError::Immutable));
+        let rec = Record::builder().number(1).bytes(b"x").build();
+        assert!(immutable(
+            copy.insert(&mut frozen.device, &mut frozen.mgr, rec)
+                .unwrap_err()
+        ));
+        assert!(immutable(
+            copy.delete(&mut frozen.device, &mut frozen.mgr, 1)
+                .unwrap_err()
+        ));
+        assert!(immutable(
+            frozen
+                .create_table("more", vec![ValueType::Bytes])
+                .err()
+                .unwrap()
+        ));
+        assert!(immutable(frozen.drop_table("empty").unwrap_err()));
+        drop((db, frozen));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn create_function_is_callable_from_expressions() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        db.create_function(
+            "twice",
+            Some(1),
+            Box::new(|args| match &args[0] {
+                Literal::Number(number) => Ok(Literal::Number(number * 2)),
+                _ => Err("argument 1 must be Number".to_owned()),
+            }),
+        );
+        let exprs = parse_list("TWICE(size) + 1, length('abc')", &Limits::default()).unwrap();
+        let column = |name: &str| match name {
+            "size" => Ok(Literal::Number(20)),
+            _ => Err(format!("no such column {}", name)),
+        };
+        let values: Vec<_> = exprs
+            .iter()
+            .map(|expr| expr.eval_in(&db.functions, &column).unwrap())
+            .collect();
+        assert_eq!(values, [Literal::Number(41), Literal::Number(3)]);
+        let err = exprs[0].eval_with(&column).unwrap_err();
+        assert!(err.contains("no such function"), "{}", err);
+    }
+}
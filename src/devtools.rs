@@ -0,0 +1,281 @@
+use crate::database::Database;
+use crate::page::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::io::{Result as IOResult, *};
+
+/** Bytes shown around the first difference of a changed page */
+const CONTEXT_SIZE: usize = 8;
+
+struct PageImage {
+    hash: u64,
+    data: [u8; PAGE_SIZE],
+}
+
+/** Bytes of every allocated page of a device */
+#[derive(Default)]
+pub struct PageSnapshot {
+    pages: BTreeMap<u64, PageImage>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PageDiff {
+    pub count: u64,
+    /** First differing offset, `None` if the page is allocated on one side only */
+    pub offset: Option<usize>,
+    /** Hex bytes from `offset` before the change */
+    pub before: String,
+    /** Hex bytes from `offset` after the change */
+    pub after: String,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl PageSnapshot {
+    /** Read every page marked as used in the bitmaps */
+    pub fn capture<D>(device: &mut D) -> IOResult<Self>
+    where
+        D: Read + Seek,
+    {
+        let mut snapshot = Self::default();
+        let mut bitmap_count = 0;
+        while let Ok(page) = Page::load(device, bitmap_count) {
+            let bitmap = BitmapPage::from_page(page);
+            for i in 0..BITMAP_MANAGED_SIZE as u64 {
+                if !bitmap.get_used(i) {
+                    continue;
+                }
+                let count = bitmap_count + i;
+                let data = match Page::load(device, count) {
+                    Ok(page) => *page.data(),
+                    /* allocated but never synced */
+                    Err(err) if err.kind() == ErrorKind::UnexpectedEof => continue,
+                    Err(err) => return Err(err),
+                };
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+                snapshot.pages.insert(
+                    count,
+                    PageImage {
+                        hash: hasher.finish(),
+                        data,
+                    },
+                );
+            }
+            bitmap_count += BITMAP_MANAGED_SIZE as u64 + 1;
+        }
+        Ok(snapshot)
+    }
+    /** List pages that differ between two snapshots, in ascending order
+     *
+     * Pages are only compared byte by byte when their hashes differ. */
+    pub fn diff(before: &Self, after: &Self) -> Vec<PageDiff> {
+        let mut diffs = Vec::new();
+        for (count, image) in &before.pages {
+            match after.pages.get(count) {
+                Some(other) if other.hash == image.hash => {}
+                Some(other) => {
+                    if let Some(offset) = (0..PAGE_SIZE).find(|&i| image.data[i] != other.data[i]) {
+                        let end = (offset + CONTEXT_SIZE).min(PAGE_SIZE);
+                        diffs.push(PageDiff {
+                            count: *count,
+                            offset: Some(offset),
+                            before: hex(&image.data[offset..end]),
+                            after: hex(&other.data[offset..end]),
+                        });
+                    }
+                }
+                None => diffs.push(PageDiff {
+                    count: *count,
+                    offset: None,
+                    before: hex(&image.data[..CONTEXT_SIZE]),
+                    after: String::new(),
+                }),
+            }
+        }
+        for (count, image) in &after.pages {
+            if !before.pages.contains_key(count) {
+                diffs.push(PageDiff {
+                    count: *count,
+                    offset: None,
+                    before: String::new(),
+                    after: hex(&image.data[..CONTEXT_SIZE]),
+                });
+            }
+        }
+        diffs.sort_by_key(|diff| diff.count);
+        diffs
+    }
+    /** Counts of the captured pages */
+    pub fn pages(&self) -> Vec<u64> {
+        self.pages.keys().copied().collect()
+    }
+}
+
+/** Write made through a `WriteLog`, in the order it reached the device */
+#[derive(Clone, Debug)]
+pub struct WriteEvent {
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/** Device recording every write, to rebuild the file a crash leaves after any of them
+ *
+ * A crash can stop the page manager after any write, so replaying every prefix of the log gives
+ * every file it can leave behind. Reads and seeks go to the wrapped device unchanged. */
+pub struct WriteLog<D> {
+    device: D,
+    /** Content of the device when it was wrapped */
+    base: Vec<u8>,
+    pub events: Vec<WriteEvent>,
+}
+
+impl<D> WriteLog<D>
+where
+    D: Write + Read + Seek,
+{
+    /** Wrap a device, its current content is where every replay starts */
+    pub fn new(mut device: D) -> IOResult<Self> {
+        let mut base = Vec::new();
+        device.seek(SeekFrom::Start(0))?;
+        device.read_to_end(&mut base)?;
+        device.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            device,
+            base,
+            events: Vec::new(),
+        })
+    }
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+    /** Content of the device after only the first `count` writes */
+    pub fn replay(&self, count: usize) -> Cursor<Vec<u8>> {
+        let mut data = self.base.clone();
+        for event in &self.events[..count] {
+            let start = event.offset as usize;
+            let end = start + event.data.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[start..end].copy_from_slice(&event.data);
+        }
+        Cursor::new(data)
+    }
+    /** Open the file left after every `step` writes, and after all of them, and pass it to `check`
+     *
+     * `check` is given the count of writes replayed. The first error, from opening or from
+     * `check`, stops the walk and is returned with that count. */
+    pub fn check_prefixes<F>(&self, step: usize, mut check: F) -> IOResult<()>
+    where
+        F: FnMut(usize, Database<Cursor<Vec<u8>>>) -> IOResult<()>,
+    {
+        let counts = (0..self.events.len()).step_by(step.max(1));
+        for count in counts.chain([self.events.len()]) {
+            Database::open(self.replay(count), PageManage::default())
+                .and_then(|db| check(count, db))
+                .map_err(|err| {
+                    Error::new(err.kind(), format!("after {} writes: {}", count, err))
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl<D> Write for WriteLog<D>
+where
+    D: Write + Seek,
+{
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let offset = self.device.stream_position()?;
+        let written = self.device.write(buf)?;
+        self.events.push(WriteEvent {
+            offset,
+            data: buf[..written].to_vec(),
+        });
+        Ok(written)
+    }
+    fn flush(&mut self) -> IOResult<()> {
+        self.device.flush()
+    }
+}
+
+impl<D> Read for WriteLog<D>
+where
+    D: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        self.device.read(buf)
+    }
+}
+
+impl<D> Seek for WriteLog<D>
+where
+    D: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.device.seek(pos)
+    }
+}
+
+/** Device whose writes fail once `budget` of them went through, like a disk running full
+ *
+ * A failed write leaves the device untouched. Raising `budget` again, or setting it to `None`,
+ * lets writes through once more, so a retry can be checked. Reads and seeks always succeed. */
+pub struct FullDevice<D> {
+    device: D,
+    /** Writes still allowed, `None` for no limit */
+    pub budget: Option<usize>,
+}
+
+impl<D> FullDevice<D> {
+    pub fn new(device: D, budget: Option<usize>) -> Self {
+        Self { device, budget }
+    }
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+}
+
+impl<D> Write for FullDevice<D>
+where
+    D: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        match &mut self.budget {
+            Some(0) => Err(Error::new(
+                ErrorKind::StorageFull,
+                "no space left on device",
+            )),
+            Some(budget) => {
+                *budget -= 1;
+                self.device.write(buf)
+            }
+            None => self.device.write(buf),
+        }
+    }
+    fn flush(&mut self) -> IOResult<()> {
+        self.device.flush()
+    }
+}
+
+impl<D> Read for FullDevice<D>
+where
+    D: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        self.device.read(buf)
+    }
+}
+
+impl<D> Seek for FullDevice<D>
+where
+    D: Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+        self.device.seek(pos)
+    }
+}
@@ -0,0 +1,298 @@
+/* Best-effort import of the SQL scripts written by the `.dump` command of sqlite3 */
+
+use crate::database::Database;
+use crate::table::*;
+use sql_parser::dump::{parse_dump, DumpStatement, DumpValue};
+use std::io::{Result as IOResult, *};
+
+/** Storage of a column, from the affinity of its declared type as sqlite3 decides it */
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Affinity {
+    /** Stored as a number holding the bits of the i64 */
+    Integer,
+    /** Stored as bytes holding the big-endian bits of the f64 */
+    Real,
+    /** Stored as UTF-8 bytes, numbers are written as decimal text */
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    fn of(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        if declared.contains("INT") {
+            Self::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|t| declared.contains(t))
+        {
+            Self::Text
+        } else if declared.is_empty() || declared.contains("BLOB") {
+            Self::Blob
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|t| declared.contains(t))
+        {
+            Self::Real
+        } else {
+            /* NUMERIC keeps whatever it is given, text is the only storage that always fits */
+            Self::Text
+        }
+    }
+    fn value_type(&self) -> ValueType {
+        match self {
+            Self::Integer => ValueType::Number,
+            _ => ValueType::Bytes,
+        }
+    }
+    fn convert(&self, value: &DumpValue) -> std::result::Result<Value, String> {
+        match (self, value) {
+            (_, DumpValue::Null) => Err("NULL has no storage".to_owned()),
+            (Self::Integer, DumpValue::Integer(integer)) => Ok(Value::from_u64(*integer as u64)),
+            (Self::Integer, DumpValue::Real(real)) if real.fract() == 0.0 && real.abs() < 9e18 => {
+                Ok(Value::from_u64(*real as i64 as u64))
+            }
+            (Self::Real, DumpValue::Integer(integer)) => {
+                Ok(Value::from_bytes(&(*integer as f64).to_be_bytes()))
+            }
+            (Self::Real, DumpValue::Real(real)) => Ok(Value::from_bytes(&real.to_be_bytes())),
+            (Self::Text | Self::Blob, DumpValue::Integer(integer)) => {
+                Ok(Value::from_text(&integer.to_string()))
+            }
+            (Self::Text | Self::Blob, DumpValue::Real(real)) => {
+                Ok(Value::from_text(&format!("{:?}", real)))
+            }
+            (Self::Text | Self::Blob, DumpValue::Text(text)) => Ok(Value::from_text(text)),
+            (Self::Text | Self::Blob, DumpValue::Blob(blob)) => Ok(Value::from_bytes(blob)),
+            (affinity, value) => Err(format!("{:?} in a {:?} column", value, affinity)),
+        }
+    }
+}
+
+/** Outcome of `import_sql` */
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /** Name and count of imported records of each created table, in creation order */
+    pub tables: Vec<(String, u64)>,
+    /** Line of the statement and what was skipped */
+    pub warnings: Vec<(usize, String)>,
+}
+
+struct ImportedTable {
+    name: String,
+    table: Table,
+    columns: Vec<String>,
+    affinities: Vec<Affinity>,
+    records: Vec<Record>,
+}
+
+/** Create the tables and insert the rows of a dump written by sqlite3
+ *
+ * Only CREATE TABLE and INSERT statements are imported, any other statement is skipped with a
+ * warning, and so is each row holding a NULL or a value its column cannot store. A parse error
+ * fails the import before anything is created. The rows of a table are inserted with a single
+ * `insert_many` once the whole script is read, then the pages are synced. */
+pub fn import_sql<D>(db: &mut Database<D>, script: &str) -> IOResult<ImportReport>
+where
+    D: Write + Read + Seek,
+{
+    let statements = parse_dump(script).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    let mut report = ImportReport::default();
+    let mut tables: Vec<ImportedTable> = Vec::new();
+    for (line, statement) in statements {
+        match statement {
+            DumpStatement::CreateTable { name, columns } => {
+                let affinities: Vec<_> = columns
+                    .iter()
+                    .map(|(_, declared)| Affinity::of(declared))
+                    .collect();
+                let table = db.create_table_with_columns(
+                    &name,
+                    columns
+                        .iter()
+                        .zip(&affinities)
+                        .map(|((column, _), affinity)| (column.clone(), affinity.value_type()))
+                        .collect(),
+                )?;
+                tables.push(ImportedTable {
+                    name,
+                    table,
+                    columns: columns.into_iter().map(|(column, _)| column).collect(),
+                    affinities,
+                    records: Vec::new(),
+                });
+            }
+            DumpStatement::Insert {
+                table,
+                columns,
+                rows,
+            } => {
+                let Some(imported) = tables.iter_mut().find(|imported| imported.name == table)
+                else {
+                    report
+                        .warnings
+                        .push((line, format!("INSERT into unknown table {} skipped", table)));
+                    continue;
+                };
+                /* position in the row of the value of each column */
+                let positions = match &columns {
+                    None => (0..imported.columns.len()).map(Some).collect(),
+                    Some(names) => {
+                        if let Some(unknown) =
+                            names.iter().find(|name| !imported.columns.contains(name))
+                        {
+                            report.warnings.push((
+                                line,
+                                format!("INSERT into {} skipped: no column {}", table, unknown),
+                            ));
+                            continue;
+                        }
+                        imported
+                            .columns
+                            .iter()
+                            .map(|column| names.iter().position(|name| name == column))
+                            .collect::<Vec<_>>()
+                    }
+                };
+                let expected = columns.as_ref().map_or(imported.columns.len(), Vec::len);
+                for (count, row) in rows.iter().enumerate() {
+                    let values = if row.len() != expected {
+                        Err(format!("{} values for {} columns", row.len(), expected))
+                    } else {
+                        positions
+                            .iter()
+                            .zip(&imported.columns)
+                            .zip(&imported.affinities)
+                            .map(|((position, column), affinity)| match position {
+                                Some(position) => affinity.convert(&row[*position]),
+                                None => Err(format!("no value for column {}", column)),
+                            })
+                            .collect()
+                    };
+                    match values {
+                        Ok(values) => imported.records.push(Record {
+                            values,
+                            ..Default::default()
+                        }),
+                        Err(err) => report.warnings.push((
+                            line,
+                            format!(
+                                "row {} of INSERT into {} skipped: {}",
+                                count + 1,
+                                table,
+                                err
+                            ),
+                        )),
+                    }
+                }
+            }
+            DumpStatement::Other(keyword) => report
+                .warnings
+                .push((line, format!("{} statement skipped", keyword))),
+        }
+    }
+    for mut imported in tables {
+        let rowids = imported
+            .table
+            .insert_many(&mut db.device, &mut db.mgr, imported.records)?;
+        db.mgr.sync_all(&mut db.device)?;
+        report.tables.push((imported.name, rowids.len() as u64));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::PageManage;
+    use std::fmt::Write as _;
+
+    /** Dump of two tables as sqlite3 writes it, with `users` users and three orders each */
+    fn fixture(users: u64) -> String {
+        let mut dump = String::from("PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n");
+        dump += "CREATE TABLE users(id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB);\n";
+        for id in 1..=users {
+            writeln!(
+                dump,
+                "INSERT INTO users VALUES({},'user ''{}''',{:?},X'{:02x}{:02X}');",
+                id,
+                id,
+                (id as f64 - 100.0) / 4.0,
+                id as u8,
+                (id * 7) as u8
+            )
+            .unwrap();
+        }
+        dump += "CREATE TABLE IF NOT EXISTS \"orders\"(id INTEGER, user_id INTEGER, note TEXT);\n";
+        for id in 1..=users * 3 {
+            let note = if id == 5 {
+                "NULL".to_owned()
+            } else {
+                format!("'order {}; line\nbreak'", id)
+            };
+            writeln!(
+                dump,
+                "INSERT INTO orders VALUES({},{},{});",
+                id,
+                (id - 1) / 3 + 1,
+                note
+            )
+            .unwrap();
+        }
+        dump += "CREATE INDEX orders_user ON orders(user_id);\nCOMMIT;\n";
+        dump
+    }
+
+    #[test]
+    fn fixture_dump_imports_with_counts_and_values() {
+        let mut db = Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap();
+        let report = import_sql(&mut db, &fixture(1500)).unwrap();
+        assert_eq!(
+            report.tables,
+            [("users".to_owned(), 1500), ("orders".to_owned(), 4499)]
+        );
+        let warnings: Vec<(usize, &str)> = report
+            .warnings
+            .iter()
+            .map(|(line, warning)| (*line, warning.as_str()))
+            .collect();
+        /* every order but the fifth, the NULL one, takes two lines */
+        assert_eq!(
+            warnings,
+            [
+                (1, "PRAGMA statement skipped"),
+                (2, "BEGIN statement skipped"),
+                (
+                    1505 + 4 * 2,
+                    "row 1 of INSERT into orders skipped: NULL has no storage"
+                ),
+                (1505 + 4500 * 2 - 1, "CREATE INDEX statement skipped"),
+                (1505 + 4500 * 2, "COMMIT statement skipped"),
+            ]
+        );
+
+        let users = db.table("users").unwrap();
+        assert_eq!(users.len(&mut db.device, &mut db.mgr).unwrap(), 1500);
+        for (rowid, id) in [(0, 1u64), (99, 100), (1499, 1500)] {
+            let rec = users.query(&mut db.device, &mut db.mgr, rowid).unwrap();
+            assert_eq!(rec.u64(0).unwrap(), id);
+            assert_eq!(rec.text(1).unwrap(), format!("user '{}'", id));
+            let score = f64::from_be_bytes(rec.bytes(2).unwrap().try_into().unwrap());
+            assert_eq!(score, (id as f64 - 100.0) / 4.0);
+            assert_eq!(rec.bytes(3).unwrap(), [id as u8, (id * 7) as u8]);
+        }
+
+        let orders = db.table("orders").unwrap();
+        assert_eq!(orders.len(&mut db.device, &mut db.mgr).unwrap(), 4499);
+        let rec = orders.query(&mut db.device, &mut db.mgr, 4).unwrap();
+        assert_eq!(rec.u64(0).unwrap(), 6);
+        assert_eq!(rec.text(2).unwrap(), "order 6; line\nbreak");
+        let of_user = orders
+            .find(&mut db.device, &mut db.mgr, 1, &Value::from_u64(1500))
+            .unwrap()
+            .collect(&mut db.device, &mut db.mgr)
+            .unwrap();
+        let ids: Vec<u64> = of_user.iter().map(|rec| rec.u64(0).unwrap()).collect();
+        assert_eq!(ids, [4498, 4499, 4500]);
+    }
+}
@@ -1,41 +1,389 @@
 #[allow(dead_code)]
 mod btree;
 #[allow(dead_code)]
+mod catalog;
+#[allow(dead_code)]
+mod codec;
+#[allow(dead_code)]
+mod database;
+#[cfg(feature = "devtools")]
+#[allow(dead_code)]
+mod devtools;
+#[allow(dead_code)]
+mod import;
+#[allow(dead_code)]
 mod page;
 #[allow(dead_code)]
 mod table;
+#[allow(dead_code)]
+mod timestamp;
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: 31database [--db <path>] [--create-dirs | --read-only]
+       31database diff <a.db> <b.db>
+       31database [--db <path>] import-sql <file>";
+
+struct Options {
+    db: PathBuf,
+    create_dirs: bool,
+    /** Open an existing file with `Database::open_read_only` and list the records of the demo
+     * table instead of adding one */
+    read_only: bool,
+    /** Compare the schemas of two database files instead of running the demo */
+    diff: Option<(PathBuf, PathBuf)>,
+    /** Import a dump written by sqlite3 into the database instead of running the demo */
+    import_sql: Option<PathBuf>,
+}
+
+/** Parse command line arguments
+ *
+ * Arguments are handled as `OsString` so non UTF-8 paths are passed through untouched. */
+fn parse_args<I>(mut args: I) -> Result<Options, String>
+where
+    I: Iterator<Item = OsString>,
+{
+    let mut options = Options {
+        db: PathBuf::from("31.db"),
+        create_dirs: false,
+        read_only: false,
+        diff: None,
+        import_sql: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--db") => match args.next() {
+                Some(path) => options.db = PathBuf::from(path),
+                None => return Err("--db requires a path".to_owned()),
+            },
+            Some("--create-dirs") => options.create_dirs = true,
+            Some("--read-only") => options.read_only = true,
+            Some("diff") if options.diff.is_none() => match (args.next(), args.next()) {
+                (Some(a), Some(b)) => options.diff = Some((PathBuf::from(a), PathBuf::from(b))),
+                _ => return Err("diff requires two paths".to_owned()),
+            },
+            Some("import-sql") if options.import_sql.is_none() => match args.next() {
+                Some(path) => options.import_sql = Some(PathBuf::from(path)),
+                None => return Err("import-sql requires a path".to_owned()),
+            },
+            _ => return Err(format!("unknown argument {:?}", arg)),
+        }
+    }
+    if options.read_only && options.create_dirs {
+        return Err("--read-only opens an existing file, --create-dirs cannot apply".to_owned());
+    }
+    if options.read_only && options.import_sql.is_some() {
+        return Err("import-sql cannot write to a database opened with --read-only".to_owned());
+    }
+    Ok(options)
+}
+
+/** Compare the schemas of two database files, opened read-only */
+fn schema_diff(a: &Path, b: &Path) -> std::io::Result<catalog::SchemaDiff> {
+    let mut db_a = database::Database::open_read_only(
+        std::fs::File::open(a)?,
+        page::PageManage::new(page::DEFAULT_CACHE_SIZE),
+    )?;
+    let mut db_b = database::Database::open_read_only(
+        std::fs::File::open(b)?,
+        page::PageManage::new(page::DEFAULT_CACHE_SIZE),
+    )?;
+    db_a.schema_diff(&mut db_b)
+}
+
+/** Exit status of `diff`, like diff(1): 1 when the schemas differ, 2 on trouble, so scripts can
+ * gate on it */
+fn diff_status(diff: &std::io::Result<catalog::SchemaDiff>) -> i32 {
+    match diff {
+        Ok(diff) if diff.is_empty() => 0,
+        Ok(_) => 1,
+        Err(_) => 2,
+    }
+}
 
 fn main() -> std::io::Result<()> {
+    let options = match parse_args(std::env::args_os().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n{}", msg, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some((a, b)) = &options.diff {
+        let diff = schema_diff(a, b);
+        match &diff {
+            Ok(diff) => print!("{}", diff),
+            Err(err) => eprintln!("{}", err),
+        }
+        std::process::exit(diff_status(&diff));
+    }
+
+    if options.read_only {
+        let mut db = database::Database::open_read_only(
+            std::fs::File::open(&options.db)?,
+            page::PageManage::new(page::DEFAULT_CACHE_SIZE),
+        )?;
+        let table = db.table("example")?;
+        let mut scan = table.scan();
+        while let Some(rec) = scan.next(&mut db.device, &mut db.mgr) {
+            let rec = rec?;
+            println!("{} {}", rec.text(0)?, rec.text(1)?);
+        }
+        return Ok(());
+    }
+
+    if options.create_dirs {
+        if let Some(parent) = options.db.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
     let mut f = std::fs::File::options()
         .create(true)
+        .truncate(false)
         .write(true)
         .read(true)
-        .open("31.db")?;
-
-    let mut node = btree::BtreeNode::new_node(page::PAGE_TYPEID_BTREE_LEAF);
-    let mut mgr = page::PageManage::default();
-    mgr.cache_size = 1024;
-    let root_page = mgr.alloc(&mut f, page::PageType::BtreePage)?;
-    node.page_count = root_page.borrow().count;
+        .open(&options.db)?;
 
-    let mut table = table::Table {
-        root_node: node,
-        ..Default::default()
+    let mut mgr = page::PageManage::new(page::DEFAULT_CACHE_SIZE);
+    mgr.check_tail(&mut f)?;
+    let mut db = database::Database::open(f, mgr)?;
+    if let Some(path) = &options.import_sql {
+        let report = import::import_sql(&mut db, &std::fs::read_to_string(path)?)?;
+        for (line, warning) in &report.warnings {
+            eprintln!("line {}: {}", line, warning);
+        }
+        for (name, count) in &report.tables {
+            println!("{}: {} records", name, count);
+        }
+        return Ok(());
+    }
+    let mut table = match db.table("example") {
+        Ok(table) => table,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => db.create_table(
+            "example",
+            vec![table::ValueType::Bytes, table::ValueType::Bytes],
+        )?,
+        Err(err) => return Err(err),
     };
 
-    let mut rec = table::Record::default();
+    let rec = table::Record::builder().text("sss").text("sss").build();
 
-    rec.values
-        .push(table::Value::new(table::ValueType::Bytes, b"sss"));
-    rec.values
-        .push(table::Value::new(table::ValueType::Bytes, b"sss"));
-    table.value_types.push(table::ValueType::Bytes);
-    table.value_types.push(table::ValueType::Bytes);
-
-    let rowid = table.insert(&mut f, &mut mgr, rec.clone())?;
-    let v = table.query(&mut f, &mut mgr, rowid)?;
-    println!("{:?}", v.values);
-    mgr.sync_all(&mut f)?;
+    let rowid = table
+        .insert(&mut db.device, &mut db.mgr, rec.clone())?
+        .rowid;
+    let v = table.query(&mut db.device, &mut db.mgr, rowid)?;
+    println!("{} {}", v.text(0)?, v.text(1)?);
+    /* no record has the next rowid yet */
+    match table.query(&mut db.device, &mut db.mgr, rowid + 1) {
+        Ok(v) => println!("{:?}", v.values),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => println!("{}", err),
+        Err(err) => return Err(err),
+    }
+    db.checkpoint()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use catalog::{ColumnDiff, ColumnSchema, IndexDiff, SchemaDiff, TableDiff};
+    use std::io::Cursor;
+    use table::ValueType;
+
+    type MemoryDatabase = database::Database<Cursor<Vec<u8>>>;
+
+    fn memory_db() -> MemoryDatabase {
+        database::Database::open(Cursor::new(Vec::new()), page::PageManage::default()).unwrap()
+    }
+
+    fn create(db: &mut MemoryDatabase, name: &str, columns: &[(&str, ValueType)]) {
+        db.create_table_with_columns(
+            name,
+            columns
+                .iter()
+                .map(|(column, value_type)| (column.to_string(), value_type.clone()))
+                .collect(),
+        )
+        .unwrap();
+    }
+
+    fn index(db: &mut MemoryDatabase, name: &str, column: usize, unique: bool) {
+        let mut table = db.table(name).unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, column, unique)
+            .unwrap();
+        table
+            .save_schema(&mut db.device, &mut db.mgr, name)
+            .unwrap();
+    }
+
+    fn schema(name: &str, value_type: ValueType) -> Option<ColumnSchema> {
+        Some(ColumnSchema {
+            name: Some(name.to_owned()),
+            value_type,
+        })
+    }
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn parse_args_of_each_option() {
+        let options = args(&[]).unwrap();
+        assert_eq!(options.db, PathBuf::from("31.db"));
+        assert!(!options.create_dirs && !options.read_only);
+        assert!(options.diff.is_none() && options.import_sql.is_none());
+
+        let options = args(&["--db", "some dir/a.db", "--read-only"]).unwrap();
+        assert_eq!(options.db, PathBuf::from("some dir/a.db"));
+        assert!(options.read_only);
+        let options = args(&["--create-dirs", "import-sql", "dump.sql"]).unwrap();
+        assert!(options.create_dirs);
+        assert_eq!(options.import_sql, Some(PathBuf::from("dump.sql")));
+        let options = args(&["diff", "a.db", "b.db"]).unwrap();
+        assert_eq!(
+            options.diff,
+            Some((PathBuf::from("a.db"), PathBuf::from("b.db")))
+        );
+
+        for bad in [
+            &["--db"][..],
+            &["diff", "a.db"],
+            &["import-sql"],
+            &["--verbose"],
+            &["--read-only", "--create-dirs"],
+            &["--read-only", "import-sql", "dump.sql"],
+        ] {
+            assert!(args(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_args_keeps_non_utf8_paths() {
+        use std::os::unix::ffi::OsStringExt;
+        let path = OsString::from_vec(b"caf\xe9.db".to_vec());
+        let options = parse_args([OsString::from("--db"), path.clone()].into_iter()).unwrap();
+        assert_eq!(options.db.as_os_str(), path);
+    }
+
+    #[test]
+    fn schema_diff_of_each_dimension() {
+        let mut a = memory_db();
+        let mut b = memory_db();
+        for db in [&mut a, &mut b] {
+            create(db, "same", &[("id", ValueType::Number)]);
+            index(db, "same", 0, true);
+        }
+        let mut same = memory_db();
+        create(&mut same, "same", &[("id", ValueType::Number)]);
+        index(&mut same, "same", 0, true);
+        let diff = a.schema_diff(&mut same);
+        assert!(diff.as_ref().unwrap().is_empty());
+        assert_eq!(diff_status(&diff), 0);
+
+        create(&mut a, "gone", &[("id", ValueType::Number)]);
+        create(&mut b, "new", &[("id", ValueType::Number)]);
+        create(
+            &mut a,
+            "changed",
+            &[
+                ("renamed", ValueType::Bytes),
+                ("retyped", ValueType::Number),
+                ("dropped", ValueType::Bytes),
+                ("indexed", ValueType::Bytes),
+                ("unique", ValueType::Bytes),
+            ],
+        );
+        create(
+            &mut b,
+            "changed",
+            &[
+                ("name", ValueType::Bytes),
+                ("retyped", ValueType::Timestamp),
+                ("dropped", ValueType::Bytes),
+                ("indexed", ValueType::Bytes),
+                ("unique", ValueType::Bytes),
+            ],
+        );
+        index(&mut a, "changed", 3, false);
+        index(&mut a, "changed", 4, false);
+        index(&mut b, "changed", 4, true);
+        let diff = a.schema_diff(&mut b);
+        assert_eq!(diff_status(&diff), 1);
+        let diff = diff.unwrap();
+        assert_eq!(
+            diff,
+            SchemaDiff {
+                only_left: vec!["gone".to_owned()],
+                only_right: vec!["new".to_owned()],
+                tables: vec![TableDiff {
+                    name: "changed".to_owned(),
+                    columns: vec![
+                        ColumnDiff {
+                            column: 0,
+                            left: schema("renamed", ValueType::Bytes),
+                            right: schema("name", ValueType::Bytes),
+                        },
+                        ColumnDiff {
+                            column: 1,
+                            left: schema("retyped", ValueType::Number),
+                            right: schema("retyped", ValueType::Timestamp),
+                        },
+                    ],
+                    indexes: vec![
+                        IndexDiff {
+                            column: 3,
+                            left: Some(false),
+                            right: None,
+                        },
+                        IndexDiff {
+                            column: 4,
+                            left: Some(false),
+                            right: Some(true),
+                        },
+                    ],
+                }],
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "- table gone\n\
+             + table new\n  \
+             table changed\n\
+             -   column 0: renamed Bytes\n\
+             +   column 0: name Bytes\n\
+             -   column 1: retyped Number\n\
+             +   column 1: retyped Timestamp\n\
+             -   index on column 3\n\
+             -   index on column 4\n\
+             +   unique index on column 4\n"
+        );
+
+        /* a column only on one side */
+        b.add_column(
+            "changed",
+            Some("extra".to_owned()),
+            ValueType::Number,
+            Some(table::Value::from_u64(0)),
+        )
+        .unwrap();
+        let diff = a.schema_diff(&mut b).unwrap();
+        assert_eq!(
+            diff.tables[0].columns[2],
+            ColumnDiff {
+                column: 5,
+                left: None,
+                right: schema("extra", ValueType::Number),
+            }
+        );
+
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert_eq!(diff_status(&Err(missing)), 2);
+    }
+}
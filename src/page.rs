@@ -1,20 +1,155 @@
+use crate::codec::*;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
 use std::io::{Result as IOResult, *};
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
+use std::time::Duration;
 
 pub const PAGE_SIZE: usize = 4096;
 
-const BITMAP_MANAGED_SIZE: usize = PAGE_SIZE * 8;
+pub const BITMAP_MANAGED_SIZE: usize = PAGE_SIZE * 8;
+
+/** Pages kept in memory by a page manager unless configured otherwise */
+pub const DEFAULT_CACHE_SIZE: usize = 256;
 
 pub const PAGE_TYPEID_BTREE_INTERNAL: u8 = 1;
 pub const PAGE_TYPEID_BTREE_LEAF: u8 = 2;
 pub const PAGE_TYPEID_CONTENT: u8 = 3;
 pub const PAGE_TYPEID_OVERFLOW: u8 = 4;
+pub const PAGE_TYPEID_CATALOG: u8 = 5;
+pub const PAGE_TYPEID_FREE_MAP_DIR: u8 = 6;
+pub const PAGE_TYPEID_FREE_MAP: u8 = 7;
+
+/** Pages whose free space one free-space map page records */
+pub const FREE_MAP_MANAGED_SIZE: usize = PAGE_SIZE - 8;
+/** Free-space map pages listed by the directory page, later pages are not mapped */
+pub const FREE_MAP_DIR_SLOTS: usize = (PAGE_SIZE - 8) / 8;
+/** Bytes of free space counted by one step of a free-space map byte */
+pub const FREE_MAP_GRANULE: usize = 32;
+
+/** Most entries a content page holds, bounded by the 1-byte entry count and location offset */
+pub const CONTENTPAGE_MAX_ENTRIES: usize = u8::MAX as usize;
+
+/** Length field of a removed content entry, no real length has every bit set */
+pub const CONTENTENTRY_TOMBSTONE: u16 = u16::MAX;
 
 const OVERFLOWPAGE_AVAILABLE_SIZE: usize = PAGE_SIZE - 3;
 const OVERFLOWED_OVERFLOWPAGE_AVAILABLE_SIZE: usize = PAGE_SIZE - 3 - 8;
 
+#[derive(Debug)]
+pub enum PageError {
+    /** The page lies beyond the end of the device */
+    OutOfRange(u64),
+    /** The device ends with a partially written page that is marked as used */
+    TornTail(u64),
+    /** The page is not marked as used in the allocation bitmap */
+    Unallocated(u64),
+    /** The page manager is read-only */
+    ReadOnly,
+    /** The file is frozen and can never be changed */
+    Immutable,
+    /** The page does not hold what the page pointing to it expects
+     *
+     * `page_type` is the type byte found on the page, `structure` what was being decoded and
+     * `offset` the byte of the page where decoding failed. */
+    Corrupted {
+        page: u64,
+        page_type: u8,
+        structure: &'static str,
+        offset: usize,
+    },
+    /** Writing the page failed after `written` pages were written by the same sync */
+    SyncFailed {
+        page: u64,
+        written: usize,
+        source: Error,
+    },
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfRange(count) => write!(f, "page {} is beyond the end of the device", count),
+            Self::TornTail(count) => write!(
+                f,
+                "page {} at the end of the file is partially written, run recovery",
+                count
+            ),
+            Self::Unallocated(count) => write!(f, "page {} is not allocated", count),
+            Self::ReadOnly => write!(f, "the database is opened read-only"),
+            Self::Immutable => write!(f, "the database is frozen and cannot be changed"),
+            Self::Corrupted {
+                page,
+                page_type,
+                structure,
+                offset,
+            } => write!(
+                f,
+                "page {}: bad {} at offset {} of a page of type {}",
+                page, structure, offset, page_type
+            ),
+            Self::SyncFailed {
+                page,
+                written,
+                source,
+            } => write!(
+                f,
+                "writing page {} failed after {} pages were written: {}",
+                page, written, source
+            ),
+        }
+    }
+}
+
+impl PageError {
+    /** The page error carried by an I/O error, if it is one */
+    pub fn of(err: &Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+    /** Count of the page the error is about, if it is about one */
+    pub fn page(&self) -> Option<u64> {
+        match self {
+            Self::OutOfRange(page)
+            | Self::TornTail(page)
+            | Self::Unallocated(page)
+            | Self::Corrupted { page, .. }
+            | Self::SyncFailed { page, .. } => Some(*page),
+            Self::ReadOnly | Self::Immutable => None,
+        }
+    }
+}
+
+impl std::error::Error for PageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::SyncFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<PageError> for Error {
+    fn from(err: PageError) -> Self {
+        let kind = match &err {
+            PageError::OutOfRange(_) => ErrorKind::UnexpectedEof,
+            PageError::TornTail(_) | PageError::Corrupted { .. } => ErrorKind::InvalidData,
+            PageError::Unallocated(_) => ErrorKind::InvalidInput,
+            PageError::ReadOnly | PageError::Immutable => ErrorKind::PermissionDenied,
+            /* the caller can tell a full disk from any other failure */
+            PageError::SyncFailed { source, .. } => source.kind(),
+        };
+        Error::new(kind, err)
+    }
+}
+
+/** Count of the bitmap page managing a page */
+pub fn bitmap_count_of(page_count: u64) -> u64 {
+    page_count / (BITMAP_MANAGED_SIZE as u64 + 1) * (BITMAP_MANAGED_SIZE as u64 + 1)
+}
+
 #[derive(Clone, Copy)]
 pub enum PageType {
     General,
@@ -28,8 +163,9 @@ pub enum PageType {
 pub struct Page {
     pub page_type: PageType,
     pub count: u64,
-    pub syncd: bool,
-    pub data: [u8; PAGE_SIZE],
+    /** Private so that every write goes through `modify` or `data_mut` and marks the page dirty */
+    syncd: bool,
+    data: [u8; PAGE_SIZE],
 }
 
 impl Page {
@@ -47,7 +183,11 @@ impl Page {
         R: Read + Seek,
     {
         let mut data = [0; PAGE_SIZE];
-        reader.seek(SeekFrom::Start(count * PAGE_SIZE as u64))?;
+        /* no device is that large */
+        let position = count
+            .checked_mul(PAGE_SIZE as u64)
+            .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+        reader.seek(SeekFrom::Start(position))?;
         reader.read_exact(&mut data)?;
 
         Ok(Self {
@@ -73,45 +213,84 @@ impl Page {
         self.data = *data;
         self.syncd = false;
     }
+    pub fn data(&self) -> &[u8; PAGE_SIZE] {
+        &self.data
+    }
+    /** Mutable page data, the page is marked dirty */
+    pub fn data_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
+        self.syncd = false;
+        &mut self.data
+    }
+    /** Whether the page has changes not synced to disk */
+    pub fn is_dirty(&self) -> bool {
+        !self.syncd
+    }
 }
 
 pub struct BitmapPage {
     page: Page,
 }
 
+/** Whether bit `index` of a bitmap is set */
+fn bitmap_get(data: &[u8], index: usize) -> bool {
+    data[index / 8] >> (7 - index % 8) & 1 != 0
+}
+
+fn bitmap_set(data: &mut [u8], index: usize, used: bool) {
+    if used {
+        data[index / 8] |= 1 << (7 - index % 8);
+    } else {
+        data[index / 8] &= !(1 << (7 - index % 8));
+    }
+}
+
+/** Index of the last set bit of a bitmap */
+fn bitmap_last_used(data: &[u8]) -> Option<usize> {
+    let byte = data.iter().rposition(|byte| *byte != 0)?;
+    Some(byte * 8 + 7 - data[byte].trailing_zeros() as usize)
+}
+
+/** Find the first run of `len` unset bits, return the index of its first bit */
+fn bitmap_find_run(data: &[u8], len: usize) -> Option<usize> {
+    let mut start = 0;
+    let mut index = 0;
+    while index < data.len() * 8 {
+        /* skip full bytes while no run is open */
+        if index == start && index % 8 == 0 && data[index / 8] == 255 {
+            index += 8;
+            start = index;
+            continue;
+        }
+        if bitmap_get(data, index) {
+            start = index + 1;
+        } else if index + 1 - start == len {
+            return Some(start);
+        }
+        index += 1;
+    }
+    None
+}
+
 impl BitmapPage {
     pub fn new(count: u64) -> Self {
         Self {
             page: Page::new(count, PageType::BitmapPage),
         }
     }
+    pub fn from_page(page: Page) -> Self {
+        Self { page }
+    }
     pub fn get_used(&self, count: u64) -> bool {
-        let byte = count as usize / 8;
-        let bit = count as usize % 8;
-        self.page.data[byte] >> (7 - bit) << 7 != 0
+        bitmap_get(&self.page.data, count as usize)
     }
     pub fn set_used(&mut self, count: u64) {
-        let byte = count as usize / 8;
-        let bit = count as usize % 8;
-        self.page.data[byte] |= 1 << (7 - bit);
+        bitmap_set(&mut self.page.data, count as usize, true);
     }
     pub fn set_unused(&mut self, count: u64) {
-        let byte = count as usize / 8;
-        let bit = count as usize % 8;
-        self.page.data[byte] &= !(1 << (7 - bit));
+        bitmap_set(&mut self.page.data, count as usize, false);
     }
     pub fn find_unused(&self) -> Option<u64> {
-        for (i, byte) in self.page.data.iter().enumerate() {
-            if *byte != 255 {
-                for j in 0..8 {
-                    let position = (i * 8 + j) as u64;
-                    if !self.get_used(position) {
-                        return Some(position);
-                    }
-                }
-            }
-        }
-        None
+        bitmap_find_run(&self.page.data, 1).map(|index| index as u64)
     }
 }
 
@@ -119,6 +298,8 @@ impl BitmapPage {
 pub struct ContentEntry {
     pub data: Vec<u8>,
     pub overflow_page: Option<u64>,
+    /** Removed entry kept as a tombstone so later offsets stay valid */
+    pub deleted: bool,
 }
 
 impl ContentEntry {
@@ -133,43 +314,101 @@ impl ContentEntry {
             entry.data = data[..PAGE_SIZE - 12].to_owned();
             let mut data = &data[PAGE_SIZE - 12..];
 
-            let mut last_count = None;
-            let mut last_page: Option<OverflowPage> = None;
+            /* page by page, so the chain fills scattered free pages and has no length limit */
             let mut overflow_page_count = mgr.alloc(device, PageType::OverflowPage)?.borrow().count;
             entry.overflow_page = Some(overflow_page_count);
             loop {
                 let mut overflow_page = OverflowPage::default();
-                if last_page.is_some() {
-                    let mut last = last_page.clone().unwrap();
-                    last.next = Some(overflow_page_count);
-                    last_page = Some(last);
-                }
-
                 overflow_page.put_data(data);
-                if let Some(count) = last_count {
-                    if let Some(page) = &last_page {
-                        mgr.modify(device, count, &page.dump())?;
-                    }
-                }
-
-                last_count = Some(overflow_page_count);
-                last_page = Some(overflow_page.clone());
                 data = &data[overflow_page.data.len()..];
-
-                if data.is_empty() {
-                    mgr.modify(device, overflow_page_count, &overflow_page.dump())?;
-                    break;
+                let next = if data.is_empty() {
+                    None
+                } else {
+                    Some(mgr.alloc(device, PageType::OverflowPage)?.borrow().count)
+                };
+                overflow_page.next = next;
+                mgr.modify(device, overflow_page_count, &overflow_page.dump())?;
+                match next {
+                    Some(next) => overflow_page_count = next,
+                    None => break,
                 }
-                overflow_page_count = mgr.alloc(device, PageType::OverflowPage)?.borrow().count;
             }
         } else {
             entry.data = data.to_owned();
         }
         Ok(entry)
     }
+    /** Read the whole data of this entry, following its overflow pages */
+    pub fn read_data<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Vec<u8>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut data = self.data.clone();
+        let mut next = self.overflow_page;
+        while let Some(count) = next {
+            let page = OverflowPage::read(device, mgr, count)?;
+            data.extend(page.data);
+            next = page.next;
+        }
+        Ok(data)
+    }
+    /** Read `len` bytes from `start` of the data of this entry
+     *
+     * Overflow pages are followed only until the range is covered. A range past the end of the
+     * data is cut short. */
+    pub fn read_range<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        start: usize,
+        len: usize,
+    ) -> IOResult<Vec<u8>>
+    where
+        D: Write + Read + Seek,
+    {
+        let end = start.saturating_add(len);
+        let mut range = Vec::new();
+        /* offset of the current piece within the whole data */
+        let mut offset = 0;
+        let mut piece = self.data.clone();
+        let mut next = self.overflow_page;
+        loop {
+            let piece_end = offset + piece.len();
+            if start < piece_end {
+                range.extend(&piece[start.max(offset) - offset..end.min(piece_end) - offset]);
+            }
+            if end <= piece_end {
+                break;
+            }
+            match next {
+                Some(count) => {
+                    let page = OverflowPage::read(device, mgr, count)?;
+                    offset = piece_end;
+                    piece = page.data;
+                    next = page.next;
+                }
+                None => break,
+            }
+        }
+        Ok(range)
+    }
+    /** Release the overflow pages of this entry */
+    pub fn release_overflow<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut next = self.overflow_page;
+        while let Some(count) = next {
+            next = OverflowPage::read(device, mgr, count)?.next;
+            mgr.release(device, count)?;
+        }
+        Ok(())
+    }
     /** Summary used size (not including overflowed part) */
     pub fn total_size(&self) -> usize {
-        if self.overflow_page.is_none() {
+        if self.deleted {
+            2
+        } else if self.overflow_page.is_none() {
             2 + self.data.len()
         } else {
             2 + 8 + self.data.len()
@@ -207,33 +446,67 @@ impl ContentEntry {
  * |-----|---|-----------|
  * |0    |2  |Lenth      |
  * |2    |10 |Overflow page|
+ *
+ * Removed entry:
+ *
+ * |Start|End|Description|
+ * |-----|---|-----------|
+ * |0    |2  |0xFFFF     |
  */
 pub struct ContentPage {
     pub entries: Vec<ContentEntry>,
 }
 
 impl ContentPage {
-    /** Load from bytes */
-    pub fn load(page_data: &[u8; PAGE_SIZE]) -> Self {
+    /** Load the content page `page_count` from bytes
+     *
+     * Fails with `PageError::Corrupted` at the offset of the first field that is not a content
+     * page type or runs past the end of the page. */
+    pub fn load(
+        page_count: u64,
+        page_data: &[u8; PAGE_SIZE],
+    ) -> std::result::Result<Self, PageError> {
+        let corrupted = |structure, offset| PageError::Corrupted {
+            page: page_count,
+            page_type: page_data[0],
+            structure,
+            offset,
+        };
+        if page_data[0] != PAGE_TYPEID_CONTENT {
+            return Err(corrupted("page type", 0));
+        }
         let mut page = Self::default();
         let entries_len = page_data[1] as usize;
         let mut ptr = 2;
         for _ in 0..entries_len {
             let mut entry = ContentEntry::default();
-            let mut size = u16::from_be_bytes(page_data[ptr..ptr + 2].try_into().unwrap());
+            if ptr + 2 > PAGE_SIZE {
+                return Err(corrupted("entry length", ptr));
+            }
+            if read_u16(page_data, ptr) == CONTENTENTRY_TOMBSTONE {
+                entry.deleted = true;
+                ptr += 2;
+                page.entries.push(entry);
+                continue;
+            }
+            let (overflowed, size) = read_len15(page_data, ptr);
+            let len_ptr = ptr;
             ptr += 2;
-            if size >> 15 == 1 {
-                size &= !0 << 1 >> 1;
-                entry.overflow_page = Some(u64::from_be_bytes(
-                    page_data[ptr..ptr + 8].try_into().unwrap(),
-                ));
+            if overflowed {
+                if ptr + 8 > PAGE_SIZE {
+                    return Err(corrupted("overflow page", ptr));
+                }
+                entry.overflow_page = Some(read_u64(page_data, ptr));
                 ptr += 8;
             }
-            entry.data = page_data[ptr..ptr + size as usize].to_vec();
-            ptr += size as usize;
+            if ptr + size > PAGE_SIZE {
+                return Err(corrupted("entry length", len_ptr));
+            }
+            entry.data = page_data[ptr..ptr + size].to_vec();
+            ptr += size;
             page.entries.push(entry);
         }
-        page
+        Ok(page)
     }
     /** Dump to bytes */
     pub fn dump(&self) -> [u8; PAGE_SIZE] {
@@ -242,31 +515,64 @@ impl ContentPage {
         page_data[1] = self.entries.len() as u8;
         let mut ptr = 2;
         for entry in &self.entries {
-            let mut size = entry.data.len() as u16;
-            if let Some(overflow_page) = entry.overflow_page {
-                size |= 1 << 15;
-                page_data[ptr..ptr + 2].copy_from_slice(&size.to_be_bytes());
+            if entry.deleted {
+                write_u16(&mut page_data, ptr, CONTENTENTRY_TOMBSTONE);
                 ptr += 2;
-                page_data[ptr..ptr + 8].copy_from_slice(&overflow_page.to_be_bytes());
+                continue;
+            }
+            write_len15(
+                &mut page_data,
+                ptr,
+                entry.overflow_page.is_some(),
+                entry.data.len(),
+            );
+            ptr += 2;
+            if let Some(overflow_page) = entry.overflow_page {
+                write_u64(&mut page_data, ptr, overflow_page);
                 ptr += 8;
-            } else {
-                page_data[ptr..ptr + 2].copy_from_slice(&size.to_be_bytes());
-                ptr += 2;
             }
             page_data[ptr..ptr + entry.data.len()].copy_from_slice(&entry.data);
             ptr += entry.data.len();
         }
         page_data
     }
-    /** Push a content entry */
-    pub fn push(&mut self, entry: ContentEntry) -> std::result::Result<(), ()> {
-        if self.total_size() + entry.total_size() <= PAGE_SIZE {
+    /** Push a content entry, reusing the slot of a removed entry if one fits
+     *
+     * Return the slot of the entry. Fails when the entry does not fit or its slot could not be
+     * addressed by a location. */
+    pub fn push(&mut self, entry: ContentEntry) -> std::result::Result<usize, ()> {
+        if let Some(slot) = self.entries.iter().position(|entry| entry.deleted) {
+            if self.total_size() - self.entries[slot].total_size() + entry.total_size() <= PAGE_SIZE
+            {
+                self.entries[slot] = entry;
+                return Ok(slot);
+            }
+        }
+        if self.entries.len() < CONTENTPAGE_MAX_ENTRIES
+            && self.total_size() + entry.total_size() <= PAGE_SIZE
+        {
             self.entries.push(entry);
-            Ok(())
+            Ok(self.entries.len() - 1)
         } else {
             Err(())
         }
     }
+    /** Remove an entry, leaving a tombstone so the other offsets stay valid
+     *
+     * Trailing tombstones are dropped since nothing refers to them. */
+    pub fn remove(&mut self, offset: usize) {
+        self.entries[offset] = ContentEntry {
+            deleted: true,
+            ..Default::default()
+        };
+        while self.entries.last().is_some_and(|entry| entry.deleted) {
+            self.entries.pop();
+        }
+    }
+    /** Whether the page holds no live entry */
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| entry.deleted)
+    }
     /** Summary used size */
     pub fn total_size(&self) -> usize {
         let mut size = 2;
@@ -284,16 +590,37 @@ pub struct OverflowPage {
 }
 
 impl OverflowPage {
+    /** Load the overflow page `count` of a chain, checking that it is one */
+    pub fn read<D>(device: &mut D, mgr: &mut PageManage, count: u64) -> IOResult<Self>
+    where
+        D: Write + Read + Seek,
+    {
+        let data = mgr.get_data(device, count)?;
+        let (overflowed, size) = read_len15(&data, 1);
+        let start = if overflowed { 11 } else { 3 };
+        let corrupted = |structure, offset| PageError::Corrupted {
+            page: count,
+            page_type: data[0],
+            structure,
+            offset,
+        };
+        if data[0] != PAGE_TYPEID_OVERFLOW {
+            return Err(corrupted("page type", 0).into());
+        }
+        if start + size > PAGE_SIZE {
+            return Err(corrupted("overflow length", 1).into());
+        }
+        Ok(Self::load(&data))
+    }
     /** Load from bytes */
     pub fn load(data: &[u8; PAGE_SIZE]) -> Self {
         let mut page = Self::default();
-        let size = u16::from_be_bytes(data[1..3].try_into().unwrap());
-        if size >> 15 == 1 {
-            let size = size << 1 >> 1;
-            page.data = data[11..11 + size as usize].to_owned();
-            page.next = Some(u64::from_be_bytes(data[3..11].try_into().unwrap()));
+        let (overflowed, size) = read_len15(data, 1);
+        if overflowed {
+            page.data = data[11..11 + size].to_owned();
+            page.next = Some(read_u64(data, 3));
         } else {
-            page.data = data[3..3 + size as usize].to_owned();
+            page.data = data[3..3 + size].to_owned();
         }
 
         page
@@ -302,17 +629,25 @@ impl OverflowPage {
     pub fn dump(&self) -> [u8; PAGE_SIZE] {
         let mut data = [0; PAGE_SIZE];
         data[0] = PAGE_TYPEID_OVERFLOW;
+        write_len15(&mut data, 1, self.next.is_some(), self.data.len()); // write size
         if let Some(next) = self.next {
-            data[1..3].copy_from_slice(&(self.data.len() as u16 | (1 << 15)).to_be_bytes()); // write size
-            data[3..11].copy_from_slice(&next.to_be_bytes()); // write the next overflow page
+            write_u64(&mut data, 3, next); // write the next overflow page
             data[11..11 + self.data.len()].copy_from_slice(&self.data);
         } else {
-            data[1..3].copy_from_slice(&(self.data.len() as u16).to_be_bytes()); // write size
             data[3..3 + self.data.len()].copy_from_slice(&self.data);
         }
 
         data
     }
+    /** Count of chained overflow pages needed to hold `len` bytes */
+    pub fn pages_for(mut len: usize) -> usize {
+        let mut pages = 1;
+        while len > OVERFLOWPAGE_AVAILABLE_SIZE {
+            len -= OVERFLOWED_OVERFLOWPAGE_AVAILABLE_SIZE;
+            pages += 1;
+        }
+        pages
+    }
     pub fn put_data(&mut self, data: &[u8]) {
         /* overflowed */
         if data.len() > OVERFLOWPAGE_AVAILABLE_SIZE {
@@ -323,35 +658,218 @@ impl OverflowPage {
     }
 }
 
-#[derive(Default)]
+/** Free-space map byte of a page, 0 if it is not a content page
+ *
+ * A content page is recorded as 1 plus its room for a new entry in whole granules, so a page
+ * recorded as `b` has room for an entry of `(b - 1) * FREE_MAP_GRANULE` bytes. A page with every
+ * slot in use has no room whatever its free bytes. */
+pub fn free_space_byte(data: &[u8; PAGE_SIZE]) -> u8 {
+    if data[0] != PAGE_TYPEID_CONTENT {
+        return 0;
+    }
+    let entries = data[1] as usize;
+    let mut ptr = 2;
+    let mut tombstone = false;
+    for _ in 0..entries {
+        if ptr + 2 > PAGE_SIZE {
+            return 1;
+        }
+        if read_u16(data, ptr) == CONTENTENTRY_TOMBSTONE {
+            tombstone = true;
+            ptr += 2;
+            continue;
+        }
+        let (overflowed, size) = read_len15(data, ptr);
+        ptr += 2 + if overflowed { 8 } else { 0 } + size;
+    }
+    let room = if entries < CONTENTPAGE_MAX_ENTRIES || tombstone {
+        PAGE_SIZE.saturating_sub(ptr)
+    } else {
+        0
+    };
+    1 + (room / FREE_MAP_GRANULE).min(254) as u8
+}
+
+/**
+ * Instrumentation callbacks for embedding applications.
+ *
+ * Every method has an empty default, so an implementation only overrides what it exports. Each
+ * one is invoked where the matching `PageStats` counter is updated. A table is identified by the
+ * page count of its root B-tree node, see `Table::root_page`.
+ */
+pub trait Hooks {
+    /** A page was loaded from the device */
+    fn on_page_read(&mut self, _count: u64) {}
+    /** A dirty page was written to the device */
+    fn on_page_write(&mut self, _count: u64) {}
+    /** A page was allocated */
+    fn on_alloc(&mut self, _count: u64) {}
+    /** A query of a table returned `rows` records */
+    fn on_query(&mut self, _table: u64, _rows: u64, _duration: Duration) {}
+    /** A record of `bytes` bytes of values was inserted into a table */
+    fn on_insert(&mut self, _table: u64, _bytes: u64) {}
+}
+
+/** Counts of the work of a page manager since it was created, from `PageManage::stats` */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PageStats {
+    /** Pages loaded from the device */
+    pub reads: u64,
+    /** Dirty pages written to the device */
+    pub writes: u64,
+    pub allocs: u64,
+    /** Changes to pages through `modify` and `with_page_mut`, a page changed twice counts twice */
+    pub modifies: u64,
+    /** Queries reported by tables, and the records they returned */
+    pub queries: u64,
+    pub rows: u64,
+    /** Inserts reported by tables, and the bytes of the values they stored */
+    pub inserts: u64,
+    pub bytes: u64,
+}
+
+/** A device whose length can be cut, for giving free pages at its end back */
+pub trait Truncate {
+    /** Cut or extend the device to `pages` pages */
+    fn set_len_pages(&mut self, pages: u64) -> IOResult<()>;
+}
+
+impl Truncate for File {
+    fn set_len_pages(&mut self, pages: u64) -> IOResult<()> {
+        self.set_len(pages * PAGE_SIZE as u64)
+    }
+}
+
+impl Truncate for Cursor<Vec<u8>> {
+    fn set_len_pages(&mut self, pages: u64) -> IOResult<()> {
+        self.get_mut().resize(pages as usize * PAGE_SIZE, 0);
+        Ok(())
+    }
+}
+
 pub struct PageManage {
     pages: BTreeMap<u64, Rc<RefCell<Page>>>,
+    /** Most pages kept in memory, 0 for no limit */
     pub cache_size: usize,
+    /** Dirty pages at which writes through the manager first flush the oldest ones, 0 for no
+     * limit */
+    pub dirty_limit: usize,
+    /** Cut the free pages at the end of the device off on every `checkpoint` */
+    pub autovacuum: bool,
+    /** Refuse every change and write, for salvaging a file that must not be modified */
+    pub read_only: bool,
+    /** Like `read_only` for a frozen file, set when its catalog is opened */
+    pub immutable: bool,
+    /** Directory page of the free-space map, set when the catalog is opened
+     *
+     * Without one, content pages with room are found by loading them in turn. */
+    pub free_map: Option<u64>,
     cache_pages: Vec<u64>,
+    hooks: Option<Box<dyn Hooks>>,
+    stats: PageStats,
+    /** Whether `get_data` reads like `get_data_bypass`, set by `bypassing` */
+    bypass: bool,
+}
+
+impl Default for PageManage {
+    /** Page manager keeping up to DEFAULT_CACHE_SIZE pages in memory */
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
 }
 
 impl PageManage {
-    /** Find ot allocate an unused page */
-    fn find_unused_page<D>(&mut self, device: &mut D) -> IOResult<u64>
+    pub fn new(cache_size: usize) -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            cache_size,
+            dirty_limit: 0,
+            autovacuum: false,
+            read_only: false,
+            immutable: false,
+            free_map: None,
+            cache_pages: Vec::new(),
+            hooks: None,
+            stats: PageStats::default(),
+            bypass: false,
+        }
+    }
+    fn check_writable(&self) -> IOResult<()> {
+        if self.immutable {
+            return Err(PageError::Immutable.into());
+        }
+        if self.read_only {
+            return Err(PageError::ReadOnly.into());
+        }
+        Ok(())
+    }
+    /** Install instrumentation hooks, replacing the previous ones */
+    pub fn set_hooks(&mut self, hooks: Box<dyn Hooks>) {
+        self.hooks = Some(hooks);
+    }
+    /** Counts of the work done so far */
+    pub fn stats(&self) -> PageStats {
+        self.stats
+    }
+    /** Report a query of the table rooted at `table` that returned `rows` records */
+    pub fn report_query(&mut self, table: u64, rows: u64, duration: Duration) {
+        self.stats.queries += 1;
+        self.stats.rows += rows;
+        Self::notify(&mut self.hooks, |h| h.on_query(table, rows, duration));
+    }
+    /** Report an insert of a record of `bytes` bytes of values into the table rooted at `table` */
+    pub fn report_insert(&mut self, table: u64, bytes: u64) {
+        self.stats.inserts += 1;
+        self.stats.bytes += bytes;
+        Self::notify(&mut self.hooks, |h| h.on_insert(table, bytes));
+    }
+    /** Invoke a hook
+     *
+     * A panicking hook is disabled instead of unwinding through a half-finished operation. */
+    fn notify<F>(hooks: &mut Option<Box<dyn Hooks>>, f: F)
+    where
+        F: FnOnce(&mut dyn Hooks),
+    {
+        if let Some(h) = hooks {
+            if panic::catch_unwind(AssertUnwindSafe(|| f(h.as_mut()))).is_err() {
+                *hooks = None;
+            }
+        }
+    }
+    /** Find and mark a run of `len` consecutive unused pages, return the first count
+     *
+     * Bitmap pages are allocated as needed and updated in place. */
+    fn find_unused_pages<D>(&mut self, device: &mut D, len: usize) -> IOResult<u64>
     where
         D: Write + Read + Seek,
     {
+        /* bit 0 of every bitmap is the bitmap page itself */
+        if len == 0 || len >= BITMAP_MANAGED_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("cannot allocate {} consecutive pages", len),
+            ));
+        }
         let mut bitmap_count = 0;
         loop {
-            let mut bitmap_page = BitmapPage::new(bitmap_count);
-            if let Ok(page) = self.get(device, bitmap_count) {
-                bitmap_page.page = *page.borrow();
-            } else {
-                bitmap_page.page = *self
-                    .alloc_with_count(device, bitmap_count, PageType::BitmapPage)
-                    .borrow();
-            }
-            bitmap_page.set_used(0); // set bitmap page as used
-            if let Some(count) = bitmap_page.find_unused() {
-                bitmap_page.set_used(count);
-                self.modify(device, bitmap_count, &bitmap_page.page.data)?;
-                let count = count + bitmap_count;
-                return Ok(count);
+            /* only a bitmap page past the end is new, one that fails to load may still mark pages */
+            match self.get(device, bitmap_count) {
+                Ok(_) => {}
+                Err(err) if matches!(PageError::of(&err), Some(PageError::OutOfRange(_))) => {
+                    self.alloc_with_count(device, bitmap_count, PageType::BitmapPage);
+                }
+                Err(err) => return Err(err),
+            }
+            let start = self.with_page_mut(device, bitmap_count, |data| {
+                bitmap_set(data, 0, true); // set bitmap page as used
+                let start = bitmap_find_run(data, len)?;
+                for index in start..start + len {
+                    bitmap_set(data, index, true);
+                }
+                Some(start)
+            })?;
+            if let Some(start) = start {
+                return Ok(bitmap_count + start as u64);
             }
             bitmap_count += BITMAP_MANAGED_SIZE as u64 + 1;
         }
@@ -361,16 +879,30 @@ impl PageManage {
     where
         D: Write + Read + Seek,
     {
-        self.limit_cache(device);
-        let count = self.find_unused_page(device)?;
-        let page = Page::new(count, page_type);
-        let count = page.count;
-        self.cache_pages.push(count);
-
-        self.pages.insert(page.count, Rc::new(RefCell::new(page)));
-
+        let count = self.alloc_contiguous(device, 1, page_type)?;
         Ok(Rc::clone(self.pages.get(&count).unwrap()))
     }
+    /** Allocate `len` pages with consecutive counts, return the first count
+     *
+     * All bits are claimed in a single bitmap update, which makes it the fast path for bulk
+     * allocation. */
+    pub fn alloc_contiguous<D>(
+        &mut self,
+        device: &mut D,
+        len: usize,
+        page_type: PageType,
+    ) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        self.check_writable()?;
+        self.limit_dirty(device)?;
+        let start = self.find_unused_pages(device, len)?;
+        for count in start..start + len as u64 {
+            self.alloc_with_count(device, count, page_type);
+        }
+        Ok(start)
+    }
     /** Allocate a new page with specified count */
     pub fn alloc_with_count<D>(
         &mut self,
@@ -381,12 +913,17 @@ impl PageManage {
     where
         D: Write + Read + Seek,
     {
-        self.limit_cache(device);
-        let page = Page::new(count, page_type);
-        let count = page.count;
-        self.cache_pages.push(count);
-
-        self.pages.insert(page.count, Rc::new(RefCell::new(page)));
+        /* a released page may still be cached */
+        if let Some(page) = self.pages.get(&count) {
+            *page.borrow_mut() = Page::new(count, page_type);
+        } else {
+            self.limit_cache(device);
+            self.cache_pages.push(count);
+            self.pages
+                .insert(count, Rc::new(RefCell::new(Page::new(count, page_type))));
+        }
+        self.stats.allocs += 1;
+        Self::notify(&mut self.hooks, |h| h.on_alloc(count));
 
         Rc::clone(self.pages.get(&count).unwrap())
     }
@@ -400,37 +937,221 @@ impl PageManage {
         }
         /* page does not loaded into memory */
         self.limit_cache(device);
-        let page_res = Page::load(device, page_count);
-        if let Ok(page) = page_res {
-            self.cache_pages.push(page_count);
-            self.pages.insert(page_count, Rc::new(RefCell::new(page)));
-        } else {
-            return Err(Error::new(ErrorKind::Other, ""));
+        match Page::load(device, page_count) {
+            Ok(page) => {
+                self.cache_pages.push(page_count);
+                self.pages.insert(page_count, Rc::new(RefCell::new(page)));
+                self.stats.reads += 1;
+                Self::notify(&mut self.hooks, |h| h.on_page_read(page_count));
+            }
+            Err(err) => return Err(Self::load_error(device, page_count, err)),
         }
         self.get(device, page_count)
     }
-    /** Sync all pages to disk */
+    /** Check the file for a partially written trailing page
+     *
+     * A torn page that the bitmap never marked as used is cut off, otherwise `PageError::TornTail`
+     * is returned. */
+    pub fn check_tail(&mut self, file: &mut File) -> IOResult<()> {
+        let len = file.metadata()?.len();
+        if len % PAGE_SIZE as u64 == 0 {
+            return Ok(());
+        }
+        let torn_count = len / PAGE_SIZE as u64;
+        /* the bitmap page itself is torn, nothing proves the page unused */
+        if bitmap_count_of(torn_count) == torn_count || self.is_used(file, torn_count)? {
+            return Err(PageError::TornTail(torn_count).into());
+        }
+        if self.read_only || self.immutable {
+            /* unused, so nothing reads it, a writable open cuts it off */
+            return Ok(());
+        }
+        file.set_len(torn_count * PAGE_SIZE as u64)
+    }
+    /** Check whether a page is marked as used in the allocation bitmap
+     *
+     * Pages managed by a bitmap page that does not exist yet are unused. */
+    pub fn is_used<D>(&mut self, device: &mut D, page_count: u64) -> IOResult<bool>
+    where
+        D: Write + Read + Seek,
+    {
+        let bitmap_count = bitmap_count_of(page_count);
+        /* the last page of a group has no bit */
+        if page_count - bitmap_count >= BITMAP_MANAGED_SIZE as u64 {
+            return Ok(false);
+        }
+        let page = match self.get(device, bitmap_count) {
+            Ok(page) => page,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let bitmap = BitmapPage::from_page(*page.borrow());
+        Ok(bitmap.get_used(page_count - bitmap_count))
+    }
+    /** Sync all pages to disk
+     *
+     * A page is marked clean only once it is written. The first page that cannot be written stops
+     * the sync with `PageError::SyncFailed`, and it stays dirty with every page not reached, so
+     * calling again once the device recovers writes the remainder. */
     pub fn sync_all<W>(&mut self, writer: &mut W) -> IOResult<()>
     where
         W: Write + Seek,
     {
-        for (_, i) in self.pages.iter() {
-            i.borrow_mut().sync(writer)?;
+        if self.dirty_count() != 0 {
+            self.check_writable()?;
+        }
+        let mut written = 0;
+        for (count, i) in self.pages.iter() {
+            if !i.borrow().is_dirty() {
+                continue;
+            }
+            i.borrow_mut()
+                .sync(writer)
+                .map_err(|source| PageError::SyncFailed {
+                    page: *count,
+                    written,
+                    source,
+                })?;
+            self.stats.writes += 1;
+            Self::notify(&mut self.hooks, |h| h.on_page_write(*count));
+            written += 1;
+        }
+        Ok(())
+    }
+    /** Sync up to `count` dirty pages to disk, the longest cached first
+     *
+     * Return the number of pages written. A failed write stops the sync like `sync_all` does. */
+    pub fn sync_some<W>(&mut self, writer: &mut W, count: usize) -> IOResult<usize>
+    where
+        W: Write + Seek,
+    {
+        if self.dirty_count() != 0 {
+            self.check_writable()?;
+        }
+        let mut written = 0;
+        for page_count in &self.cache_pages {
+            if written == count {
+                break;
+            }
+            let page = &self.pages[page_count];
+            if page.borrow().is_dirty() {
+                page.borrow_mut()
+                    .sync(writer)
+                    .map_err(|source| PageError::SyncFailed {
+                        page: *page_count,
+                        written,
+                        source,
+                    })?;
+                self.stats.writes += 1;
+                Self::notify(&mut self.hooks, |h| h.on_page_write(*page_count));
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+    /** Sync all pages to disk, then cut the free pages at the end off with self.autovacuum
+     *
+     * Return the number of pages cut off. `Database::checkpoint` also records the count of pages
+     * in the catalog. */
+    pub fn checkpoint<D>(&mut self, device: &mut D) -> IOResult<u64>
+    where
+        D: Write + Read + Seek + Truncate,
+    {
+        self.sync_all(device)?;
+        if !self.autovacuum {
+            return Ok(0);
+        }
+        self.shrink(device)
+    }
+    /** Cut the device after its last used page, return the number of pages cut off
+     *
+     * The device is cut to `used_len`, and every page past it is free, so it is dropped from the
+     * cache even when dirty. The bitmaps are read as cached, so without a sync first a crash can
+     * leave a bitmap on the device marking a page that no longer exists. */
+    pub fn shrink<D>(&mut self, device: &mut D) -> IOResult<u64>
+    where
+        D: Write + Read + Seek + Truncate,
+    {
+        self.check_writable()?;
+        let device_pages = device.seek(SeekFrom::End(0))? / PAGE_SIZE as u64;
+        let len = self.used_len(device)?;
+        self.pages.retain(|count, _| *count < len);
+        self.cache_pages.retain(|count| *count < len);
+        if len >= device_pages {
+            return Ok(0);
+        }
+        device.set_len_pages(len)?;
+        Ok(device_pages - len)
+    }
+    /** Count of pages up to and including the last page marked used in the bitmaps
+     *
+     * A bitmap page managing no used page is not counted, allocation past the end creates it
+     * again. The first bitmap page always is. */
+    pub fn used_len<D>(&mut self, device: &mut D) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let device_pages = device.seek(SeekFrom::End(0))? / PAGE_SIZE as u64;
+        /* pages allocated since the last sync are only in the cache */
+        let pages = self
+            .pages
+            .keys()
+            .next_back()
+            .map_or(device_pages, |count| device_pages.max(count + 1));
+        let mut len = 1;
+        let mut bitmap_count = 0;
+        while bitmap_count < pages {
+            let data = match self.get_data(device, bitmap_count) {
+                Ok(data) => data,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            /* bit 0 is the bitmap page itself */
+            match bitmap_last_used(&data) {
+                Some(used) if used > 0 => len = bitmap_count + used as u64 + 1,
+                _ => {}
+            }
+            bitmap_count += BITMAP_MANAGED_SIZE as u64 + 1;
+        }
+        Ok(len)
+    }
+    /** Number of cached pages with changes not synced to disk */
+    pub fn dirty_count(&self) -> usize {
+        self.pages
+            .values()
+            .filter(|page| page.borrow().is_dirty())
+            .count()
+    }
+    /** Keep the dirty pages below self.dirty_limit
+     *
+     * Once the limit is reached, half of it is flushed at once so that a run of writes does not
+     * sync on every call. */
+    fn limit_dirty<W>(&mut self, writer: &mut W) -> IOResult<()>
+    where
+        W: Write + Seek,
+    {
+        if self.dirty_limit == 0 {
+            return Ok(());
+        }
+        let dirty = self.dirty_count();
+        if dirty >= self.dirty_limit {
+            self.sync_some(writer, dirty - self.dirty_limit / 2)?;
         }
         Ok(())
     }
-    /** Release ununsed page */
-    pub fn release<D>(&mut self, device: &mut D, page_count: u64)
+    /** Release an unused page
+     *
+     * The page is zeroed so it no longer matches any page type, and marked unused in its bitmap so
+     * it can be allocated again. */
+    pub fn release<D>(&mut self, device: &mut D, page_count: u64) -> IOResult<()>
     where
         D: Write + Read + Seek,
     {
-        self.pages.remove(&page_count);
-        let bitmap_count =
-            (page_count as usize / (BITMAP_MANAGED_SIZE + 1)) * (BITMAP_MANAGED_SIZE + 1);
-        let bitmap_page = self.get(device, bitmap_count as u64);
-        let mut bitmap = BitmapPage::new(bitmap_count as u64);
-        bitmap.set_unused(page_count % (BITMAP_MANAGED_SIZE + 1) as u64);
-        bitmap_page.unwrap().borrow_mut().modify(&bitmap.page.data);
+        self.modify(device, page_count, &[0; PAGE_SIZE])?;
+        let bitmap_count = bitmap_count_of(page_count);
+        self.with_page_mut(device, bitmap_count, |data| {
+            bitmap_set(data, (page_count - bitmap_count) as usize, false)
+        })
     }
     /** Find or allocate a page by type */
     pub fn find_page_by_type<D>(
@@ -445,22 +1166,188 @@ impl PageManage {
         let mut page_count = start;
         loop {
             /* is a bitmap page */
-            if page_count % BITMAP_MANAGED_SIZE as u64 + 1 == 0 {
+            if bitmap_count_of(page_count) == page_count {
                 page_count += 1;
                 continue;
             }
-            if let Ok(page) = self.get(device, page_count) {
-                if page.borrow().data[0] == page_type {
-                    return Ok(page_count);
+            match self.get(device, page_count) {
+                Ok(page) => {
+                    if page.borrow().data()[0] == page_type {
+                        return Ok(page_count);
+                    }
+                }
+                /* no such page yet, the allocated one may come before the end of the device */
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    let page = self.alloc(device, PageType::General)?;
+                    page.borrow_mut().data_mut()[0] = page_type;
+                    let count = page.borrow().count;
+                    return Ok(count);
                 }
-            } else {
-                self.alloc(device, PageType::General)?;
-                self.get(device, page_count).unwrap().borrow_mut().data[0] = page_type;
-                return Ok(page_count);
+                Err(err) => return Err(err),
             }
             page_count += 1;
         }
     }
+    /** Allocate an empty free-space map and use it, return its directory page
+     *
+     * Map pages are allocated as content pages are recorded, see `free_space_byte`.
+     *
+     * # Data structure:
+     *
+     * ## Directory page
+     *
+     * |Start|End |Description|
+     * |-----|----|-----------|
+     * |0    |1   |Page type  |
+     * |8    |4096|Map page of each run of `FREE_MAP_MANAGED_SIZE` pages, 0 if none yet|
+     *
+     * ## Map page
+     *
+     * |Start|End |Description|
+     * |-----|----|-----------|
+     * |0    |1   |Page type  |
+     * |8    |4096|Free-space map byte of each page|
+     */
+    pub fn create_free_map<D>(&mut self, device: &mut D) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let page = self.alloc(device, PageType::General)?;
+        page.borrow_mut().data_mut()[0] = PAGE_TYPEID_FREE_MAP_DIR;
+        let count = page.borrow().count;
+        self.free_map = Some(count);
+        Ok(count)
+    }
+    /** Map page recording `page_count`, 0 if there is none yet and `None` past the last one */
+    fn free_map_page<D>(&mut self, device: &mut D, page_count: u64) -> IOResult<Option<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        let Some(dir) = self.free_map else {
+            return Ok(None);
+        };
+        let slot = (page_count / FREE_MAP_MANAGED_SIZE as u64) as usize;
+        if slot >= FREE_MAP_DIR_SLOTS {
+            return Ok(None);
+        }
+        Ok(Some(read_u64(
+            self.get(device, dir)?.borrow().data(),
+            8 + 8 * slot,
+        )))
+    }
+    /** Record the free-space map byte of a page, allocating its map page as needed */
+    pub fn set_free_space<D>(&mut self, device: &mut D, page_count: u64, byte: u8) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let Some(mut map) = self.free_map_page(device, page_count)? else {
+            return Ok(());
+        };
+        if map == 0 {
+            if byte == 0 {
+                return Ok(());
+            }
+            let page = self.alloc(device, PageType::General)?;
+            page.borrow_mut().data_mut()[0] = PAGE_TYPEID_FREE_MAP;
+            map = page.borrow().count;
+            let slot = (page_count / FREE_MAP_MANAGED_SIZE as u64) as usize;
+            let dir = self.free_map.unwrap();
+            self.with_page_mut(device, dir, |data| write_u64(data, 8 + 8 * slot, map))?;
+        }
+        let index = 8 + (page_count % FREE_MAP_MANAGED_SIZE as u64) as usize;
+        /* most changes leave the byte as it was, the map page is then not made dirty */
+        if self.get(device, map)?.borrow().data()[index] != byte {
+            self.with_page_mut(device, map, |data| data[index] = byte)?;
+        }
+        Ok(())
+    }
+    /** Find the first content page from `start` recorded with room for an entry of `size`
+     * bytes, see `ContentEntry::total_size`
+     *
+     * Only the map is read. It is advisory, the page found still has to be checked. `None` when
+     * no mapped page is recorded with enough room, or there is no free-space map. */
+    pub fn find_free_space<D>(
+        &mut self,
+        device: &mut D,
+        start: u64,
+        size: usize,
+    ) -> IOResult<Option<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        let Some(dir) = self.free_map else {
+            return Ok(None);
+        };
+        let needed = 1 + size.div_ceil(FREE_MAP_GRANULE);
+        if needed > u8::MAX as usize {
+            return Ok(None);
+        }
+        let dir = self.get_data(device, dir)?;
+        let first = (start / FREE_MAP_MANAGED_SIZE as u64) as usize;
+        for slot in first..FREE_MAP_DIR_SLOTS {
+            let map = read_u64(&dir, 8 + 8 * slot);
+            if map == 0 {
+                continue;
+            }
+            let base = (slot * FREE_MAP_MANAGED_SIZE) as u64;
+            let from = start.saturating_sub(base).min(FREE_MAP_MANAGED_SIZE as u64) as usize;
+            let data = self.get_data(device, map)?;
+            if let Some(index) = data[8 + from..]
+                .iter()
+                .position(|&byte| byte as usize >= needed)
+            {
+                return Ok(Some(base + (from + index) as u64));
+            }
+        }
+        Ok(None)
+    }
+    /** Record the free space of every used page again, from scratch
+     *
+     * Needed after the map fell out of step with the pages, such as when they were written
+     * around the page manager. */
+    pub fn rebuild_free_map<D>(&mut self, device: &mut D) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let Some(dir) = self.free_map else {
+            return Ok(());
+        };
+        let dir = self.get_data(device, dir)?;
+        for slot in 0..FREE_MAP_DIR_SLOTS {
+            let map = read_u64(&dir, 8 + 8 * slot);
+            if map != 0 {
+                self.with_page_mut(device, map, |data| data[8..].fill(0))?;
+            }
+        }
+        let device_pages = device.seek(SeekFrom::End(0))? / PAGE_SIZE as u64;
+        /* pages allocated since the last sync are only in the cache */
+        let pages = self
+            .pages
+            .keys()
+            .next_back()
+            .map_or(device_pages, |count| device_pages.max(count + 1));
+        for page_count in 0..pages {
+            if bitmap_count_of(page_count) == page_count || !self.is_used(device, page_count)? {
+                continue;
+            }
+            let byte = free_space_byte(&self.get_data(device, page_count)?);
+            self.set_free_space(device, page_count, byte)?;
+        }
+        Ok(())
+    }
+    /** Mutate page data in place and mark the page dirty */
+    pub fn with_page_mut<D, F, R>(&mut self, device: &mut D, page_count: u64, f: F) -> IOResult<R>
+    where
+        D: Write + Read + Seek,
+        F: FnOnce(&mut [u8; PAGE_SIZE]) -> R,
+    {
+        self.check_writable()?;
+        self.limit_dirty(device)?;
+        let page = self.get(device, page_count)?;
+        self.stats.modifies += 1;
+        let mut page = page.borrow_mut();
+        Ok(f(page.data_mut()))
+    }
     /** Modify a apge */
     pub fn modify<D>(
         &mut self,
@@ -471,28 +1358,593 @@ impl PageManage {
     where
         D: Write + Read + Seek,
     {
-        self.get(device, page_count)?.borrow_mut().modify(data);
+        self.check_writable()?;
+        self.limit_dirty(device)?;
+        let page = self.get(device, page_count)?;
+        let was_content = page.borrow().data()[0] == PAGE_TYPEID_CONTENT;
+        page.borrow_mut().modify(data);
+        self.stats.modifies += 1;
+        if was_content || data[0] == PAGE_TYPEID_CONTENT {
+            self.set_free_space(device, page_count, free_space_byte(data))?;
+        }
         Ok(())
     }
-    /** Get page data */
+    /** Get page data, without caching it inside `bypassing` */
     pub fn get_data<D>(&mut self, device: &mut D, page_count: u64) -> IOResult<[u8; PAGE_SIZE]>
     where
         D: Write + Read + Seek,
     {
+        if self.bypass {
+            return self.get_data_bypass(device, page_count);
+        }
         Ok(self.get(device, page_count)?.borrow().data)
     }
-    /** Limit the cache size to self.cache_size */
-    fn limit_cache<D>(&mut self, device: &mut D)
+    /** Run `f` with every `get_data` reading like `get_data_bypass`
+     *
+     * Pages fetched as handles with `get`, such as for changes, are cached as usual. */
+    pub fn bypassing<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let bypass = std::mem::replace(&mut self.bypass, true);
+        let result = f(self);
+        self.bypass = bypass;
+        result
+    }
+    /** Get page data without caching it
+     *
+     * Cached pages are still returned from memory so dirty contents are seen, but a page loaded
+     * from the device is neither cached nor evicts anything. Meant for one-shot bulk reads. */
+    pub fn get_data_bypass<D>(
+        &mut self,
+        device: &mut D,
+        page_count: u64,
+    ) -> IOResult<[u8; PAGE_SIZE]>
+    where
+        D: Write + Read + Seek,
+    {
+        if let Some(page) = self.pages.get(&page_count) {
+            return Ok(page.borrow().data);
+        }
+        match Page::load(device, page_count) {
+            Ok(page) => {
+                self.stats.reads += 1;
+                Self::notify(&mut self.hooks, |h| h.on_page_read(page_count));
+                Ok(page.data)
+            }
+            Err(err) => Err(Self::load_error(device, page_count, err)),
+        }
+    }
+    /** Error of a page that failed to load, `OutOfRange` past the end of the device and
+     * `TornTail` for a page the device ends in the middle of */
+    fn load_error<D>(device: &mut D, page_count: u64, err: Error) -> Error
+    where
+        D: Seek,
+    {
+        if err.kind() != ErrorKind::UnexpectedEof {
+            return err;
+        }
+        match device.seek(SeekFrom::End(0)) {
+            Ok(len) if len > page_count * PAGE_SIZE as u64 => {
+                PageError::TornTail(page_count).into()
+            }
+            Ok(_) => PageError::OutOfRange(page_count).into(),
+            Err(err) => err,
+        }
+    }
+    /** Read the raw bytes of an allocated page, for external tooling
+     *
+     * Goes through the cache, so pages modified but not yet synced are returned as modified. */
+    #[cfg(feature = "low-level")]
+    pub fn raw_read_page<D>(&mut self, device: &mut D, page_count: u64) -> IOResult<[u8; PAGE_SIZE]>
     where
         D: Write + Read + Seek,
     {
-        if self.cache_pages.len() >= self.cache_size {
-            self.pages[&self.cache_pages[0]]
+        if !self.is_used(device, page_count)? {
+            return Err(PageError::Unallocated(page_count).into());
+        }
+        self.get_data(device, page_count)
+    }
+    /** Overwrite the raw bytes of a page, for external tooling
+     *
+     * The page is modified in the cache and written on the next sync. Writing to a page the
+     * bitmap does not mark as used is refused unless `force` is set.
+     *
+     * Nothing here checks that `data` is a valid page: a wrong page type, broken entry lengths or
+     * a change to a bitmap page can corrupt the whole file. The free-space map is left as it was,
+     * `rebuild_free_map` brings it up to date after content pages were written this way. */
+    #[cfg(feature = "low-level")]
+    pub fn raw_write_page<D>(
+        &mut self,
+        device: &mut D,
+        page_count: u64,
+        data: &[u8; PAGE_SIZE],
+        force: bool,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        self.check_writable()?;
+        if !force && !self.is_used(device, page_count)? {
+            return Err(PageError::Unallocated(page_count).into());
+        }
+        match self.get(device, page_count) {
+            Ok(page) => page.borrow_mut().modify(data),
+            /* forced write past the end of the device */
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => self
+                .alloc_with_count(device, page_count, PageType::General)
                 .borrow_mut()
-                .sync(device)
+                .modify(data),
+            Err(err) => return Err(err),
+        }
+        self.stats.modifies += 1;
+        Ok(())
+    }
+    /** Counts of the pages currently in cache, in ascending order */
+    pub fn cached_pages(&self) -> Vec<u64> {
+        self.pages.keys().copied().collect()
+    }
+    /** Make room for one more page within self.cache_size
+     *
+     * Pages still referenced outside the cache belong to an operation in progress and are never
+     * evicted, since writes through those handles would be lost. When every page is referenced the
+     * cache grows past its limit instead. */
+    fn limit_cache<D>(&mut self, device: &mut D)
+    where
+        D: Write + Read + Seek,
+    {
+        if self.cache_size == 0 {
+            return;
+        }
+        let mut i = 0;
+        while self.cache_pages.len() >= self.cache_size && i < self.cache_pages.len() {
+            let count = self.cache_pages[i];
+            let dirty = self.pages[&count].borrow().is_dirty();
+            /* a change that slipped past a read-only manager stays in memory */
+            if Rc::strong_count(&self.pages[&count]) > 1 || dirty && self.check_writable().is_err()
+            {
+                i += 1;
+                continue;
+            }
+            /* a page that cannot be written stays for the next sync to report */
+            if self.pages[&count].borrow_mut().sync(device).is_err() {
+                i += 1;
+                continue;
+            }
+            if dirty {
+                self.stats.writes += 1;
+                Self::notify(&mut self.hooks, |h| h.on_page_write(count));
+            }
+            self.pages.remove(&count);
+            self.cache_pages.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Device refusing every write once `budget` writes went through, and every read while
+     * `fail_reads` is set */
+    struct FailingDevice {
+        device: Cursor<Vec<u8>>,
+        budget: Option<usize>,
+        fail_reads: bool,
+    }
+
+    impl Write for FailingDevice {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            match &mut self.budget {
+                Some(0) => Err(Error::from(ErrorKind::StorageFull)),
+                Some(budget) => {
+                    *budget -= 1;
+                    self.device.write(buf)
+                }
+                None => self.device.write(buf),
+            }
+        }
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for FailingDevice {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            if self.fail_reads {
+                return Err(Error::other("read failed"));
+            }
+            self.device.read(buf)
+        }
+    }
+
+    impl Seek for FailingDevice {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.device.seek(pos)
+        }
+    }
+
+    #[test]
+    fn sync_resumes_after_a_failed_write() {
+        let mut device = FailingDevice {
+            device: Cursor::new(Vec::new()),
+            budget: None,
+            fail_reads: false,
+        };
+        let mut mgr = PageManage::new(0);
+        let mut expected = BTreeMap::new();
+        for i in 0..10u8 {
+            let count = mgr
+                .alloc(&mut device, PageType::General)
+                .unwrap()
+                .borrow()
+                .count;
+            mgr.modify(&mut device, count, &[i + 1; PAGE_SIZE]).unwrap();
+            expected.insert(count, [i + 1; PAGE_SIZE]);
+        }
+        /* the bitmap page is dirty too */
+        let dirty = mgr.dirty_count();
+        assert_eq!(dirty, 11);
+
+        device.budget = Some(4);
+        let err = mgr.sync_all(&mut device).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StorageFull);
+        let err = err.into_inner().unwrap().downcast::<PageError>().unwrap();
+        assert!(matches!(
+            *err,
+            PageError::SyncFailed {
+                page: 4,
+                written: 4,
+                ..
+            }
+        ));
+        assert_eq!(mgr.dirty_count(), dirty - 4);
+
+        device.budget = None;
+        mgr.sync_all(&mut device).unwrap();
+        assert_eq!(mgr.dirty_count(), 0);
+        let mut file = device.device;
+        assert_eq!(file.get_ref().len(), 11 * PAGE_SIZE);
+        let mut reopened = PageManage::new(0);
+        for (&count, data) in &expected {
+            assert_eq!(&reopened.get_data(&mut file, count).unwrap(), data);
+            assert!(reopened.is_used(&mut file, count).unwrap());
+        }
+    }
+
+    #[test]
+    fn overflow_chain_fills_scattered_free_pages() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let counts: Vec<u64> = (0..8)
+            .map(|_| Ok(mgr.alloc(&mut device, PageType::General)?.borrow().count))
+            .collect::<IOResult<_>>()
+            .unwrap();
+        for &count in counts.iter().step_by(2) {
+            mgr.release(&mut device, count).unwrap();
+        }
+        mgr.sync_all(&mut device).unwrap();
+        let len = device.get_ref().len();
+
+        /* three overflow pages, no two of the free ones are adjacent */
+        let data: Vec<u8> = (0..PAGE_SIZE * 3).map(|i| i as u8).collect();
+        let entry = ContentEntry::from_bytes(&mut device, &mut mgr, &data).unwrap();
+        let mut chain = Vec::new();
+        let mut next = entry.overflow_page;
+        while let Some(count) = next {
+            chain.push(count);
+            next = OverflowPage::read(&mut device, &mut mgr, count)
+                .unwrap()
+                .next;
+        }
+        assert_eq!(chain, vec![counts[0], counts[2], counts[4]]);
+        assert_eq!(entry.read_data(&mut device, &mut mgr).unwrap(), data);
+        mgr.sync_all(&mut device).unwrap();
+        assert_eq!(device.get_ref().len(), len);
+    }
+
+    #[test]
+    fn corrupted_overflow_pages_report_where() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let data = vec![7; PAGE_SIZE * 2];
+        let entry = ContentEntry::from_bytes(&mut device, &mut mgr, &data).unwrap();
+        let first = entry.overflow_page.unwrap();
+        let second = OverflowPage::read(&mut device, &mut mgr, first)
+            .unwrap()
+            .next
+            .unwrap();
+
+        let mut page = mgr.get_data(&mut device, second).unwrap();
+        page[0] = PAGE_TYPEID_CONTENT;
+        mgr.modify(&mut device, second, &page).unwrap();
+        let err = entry.read_data(&mut device, &mut mgr).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let page_err = PageError::of(&err).unwrap();
+        assert!(matches!(
+            page_err,
+            PageError::Corrupted {
+                page,
+                page_type: PAGE_TYPEID_CONTENT,
+                structure: "page type",
+                offset: 0,
+            } if *page == second
+        ));
+        assert_eq!(page_err.page(), Some(second));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "page {}: bad page type at offset 0 of a page of type 3",
+                second
+            )
+        );
+
+        /* a length running past the end of the page */
+        let mut page = mgr.get_data(&mut device, first).unwrap();
+        write_len15(&mut page, 1, true, PAGE_SIZE - 10);
+        mgr.modify(&mut device, first, &page).unwrap();
+        let err = entry.read_data(&mut device, &mut mgr).unwrap_err();
+        assert!(matches!(
+            PageError::of(&err),
+            Some(PageError::Corrupted {
+                page,
+                page_type: PAGE_TYPEID_OVERFLOW,
+                structure: "overflow length",
+                offset: 1,
+            }) if *page == first
+        ));
+        assert_eq!(PageError::ReadOnly.page(), None);
+    }
+
+    /** File holding a bitmap page and `pages` used pages, removed when the test ends */
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, pages: usize) -> (Self, File) {
+            let path =
+                std::env::temp_dir().join(format!("31database-{}-{}", name, std::process::id()));
+            let mut file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
                 .unwrap();
-            self.pages.remove(&self.cache_pages[0]);
-            self.cache_pages.remove(0);
+            let mut mgr = PageManage::default();
+            for _ in 0..pages {
+                mgr.alloc(&mut file, PageType::General).unwrap();
+            }
+            mgr.sync_all(&mut file).unwrap();
+            (Self(path), file)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn ragged_tails_are_cut_or_refused() {
+        /* a page never marked as used is cut off, unless the manager may not write */
+        let (_path, mut file) = TempFile::new("unused-tail", 2);
+        let len = 3 * PAGE_SIZE as u64;
+        assert_eq!(file.metadata().unwrap().len(), len);
+        file.set_len(len + 100).unwrap();
+        let mut mgr = PageManage::new(DEFAULT_CACHE_SIZE);
+        mgr.read_only = true;
+        mgr.check_tail(&mut file).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), len + 100);
+        let mut mgr = PageManage::default();
+        mgr.check_tail(&mut file).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), len);
+        let err = mgr.get_data(&mut file, 3).unwrap_err();
+        assert!(matches!(
+            PageError::of(&err),
+            Some(PageError::OutOfRange(3))
+        ));
+
+        /* a used page cut short is refused and left alone for recovery */
+        let (_path, mut file) = TempFile::new("used-tail", 3);
+        file.set_len(3 * PAGE_SIZE as u64 + 100).unwrap();
+        let mut mgr = PageManage::default();
+        let err = mgr.check_tail(&mut file).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(matches!(PageError::of(&err), Some(PageError::TornTail(3))));
+        assert_eq!(file.metadata().unwrap().len(), 3 * PAGE_SIZE as u64 + 100);
+    }
+
+    #[test]
+    fn failed_bitmap_read_leaves_the_bitmap_alone() {
+        let mut device = FailingDevice {
+            device: Cursor::new(Vec::new()),
+            budget: None,
+            fail_reads: false,
+        };
+        let mut mgr = PageManage::default();
+        let counts: Vec<u64> = (0..5)
+            .map(|_| Ok(mgr.alloc(&mut device, PageType::General)?.borrow().count))
+            .collect::<IOResult<_>>()
+            .unwrap();
+        mgr.sync_all(&mut device).unwrap();
+        let bitmap = device.device.get_ref()[..PAGE_SIZE].to_vec();
+
+        let mut mgr = PageManage::default();
+        device.fail_reads = true;
+        let err = mgr.alloc(&mut device, PageType::General).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(mgr.dirty_count(), 0);
+        assert!(mgr.cached_pages().is_empty());
+        mgr.sync_all(&mut device).unwrap();
+        device.fail_reads = false;
+        assert_eq!(&device.device.get_ref()[..PAGE_SIZE], &bitmap[..]);
+        for count in counts {
+            assert!(mgr.is_used(&mut device, count).unwrap());
+        }
+
+        /* a bitmap page cut short is not taken for a missing one either */
+        device.device.get_mut().truncate(PAGE_SIZE / 2);
+        let mut mgr = PageManage::default();
+        let err = mgr.alloc(&mut device, PageType::General).err().unwrap();
+        assert!(matches!(PageError::of(&err), Some(PageError::TornTail(0))));
+        assert_eq!(mgr.dirty_count(), 0);
+    }
+
+    #[test]
+    fn content_page_refuses_an_unaddressable_slot() {
+        let mut content_page = ContentPage::default();
+        for slot in 0..CONTENTPAGE_MAX_ENTRIES {
+            assert_eq!(content_page.push(ContentEntry::default()), Ok(slot));
+        }
+        /* plenty of bytes left, but no slot a location can address */
+        assert!(content_page.total_size() < PAGE_SIZE / 2);
+        assert_eq!(content_page.push(ContentEntry::default()), Err(()));
+        let page_data = content_page.dump();
+        assert_eq!(free_space_byte(&page_data), 1);
+
+        content_page.remove(100);
+        assert!(free_space_byte(&content_page.dump()) > 1);
+        let entry = ContentEntry {
+            data: b"reused".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(content_page.push(entry), Ok(100));
+        assert_eq!(content_page.push(ContentEntry::default()), Err(()));
+        let loaded = ContentPage::load(0, &content_page.dump()).unwrap();
+        assert_eq!(loaded.entries.len(), CONTENTPAGE_MAX_ENTRIES);
+        assert_eq!(loaded.entries[100].data, b"reused");
+    }
+
+    #[test]
+    fn corrupted_content_pages_report_where() {
+        /* a page whose entries end `len` bytes into the page */
+        let page_ending_at = |len: usize| {
+            let mut content_page = ContentPage::default();
+            let entry = ContentEntry {
+                data: vec![1; len - 2 - 2],
+                ..Default::default()
+            };
+            content_page.push(entry).unwrap();
+            content_page.dump()
+        };
+        let offset_of = |data: &[u8; PAGE_SIZE]| match ContentPage::load(9, data) {
+            Err(PageError::Corrupted {
+                page: 9,
+                structure,
+                offset,
+                ..
+            }) => (structure, offset),
+            _ => panic!("not corrupted"),
+        };
+
+        let mut data = page_ending_at(100);
+        assert_eq!(
+            ContentPage::load(9, &data).unwrap().entries[0].data.len(),
+            96
+        );
+        data[0] = PAGE_TYPEID_OVERFLOW;
+        assert_eq!(offset_of(&data), ("page type", 0));
+        data[0] = PAGE_TYPEID_CONTENT;
+        write_len15(&mut data, 2, false, PAGE_SIZE - 3);
+        assert_eq!(offset_of(&data), ("entry length", 2));
+
+        /* a second entry whose length is cut by the end of the page */
+        let mut data = page_ending_at(PAGE_SIZE - 1);
+        data[1] = 2;
+        assert_eq!(offset_of(&data), ("entry length", PAGE_SIZE - 1));
+        /* a second entry whose overflow page is */
+        let mut data = page_ending_at(PAGE_SIZE - 3);
+        data[1] = 2;
+        write_len15(&mut data, PAGE_SIZE - 3, true, 0);
+        assert_eq!(offset_of(&data), ("overflow page", PAGE_SIZE - 1));
+        let err = Error::from(ContentPage::load(9, &data).err().unwrap());
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "page 9: bad overflow page at offset {} of a page of type 3",
+                PAGE_SIZE - 1
+            )
+        );
+    }
+
+    #[test]
+    fn free_space_map_finds_room_in_few_page_loads() {
+        let mut device = Cursor::new(Vec::new());
+        let mut mgr = PageManage::new(0);
+        let dir = mgr.create_free_map(&mut device).unwrap();
+        /* only page 150 has room for 3200 bytes */
+        let room = |i: usize| if i == 150 { 3500 } else { i * 53 % 3000 };
+        let fits = |i: &usize| room(*i) / FREE_MAP_GRANULE >= 2000_usize.div_ceil(FREE_MAP_GRANULE);
+        let mut pages = Vec::new();
+        for i in 0..200 {
+            let count = mgr
+                .alloc(&mut device, PageType::ContentPage)
+                .unwrap()
+                .borrow()
+                .count;
+            let mut content_page = ContentPage::default();
+            let entry = ContentEntry {
+                data: vec![0xAA; PAGE_SIZE - 4 - room(i)],
+                ..Default::default()
+            };
+            content_page.push(entry).unwrap();
+            mgr.modify(&mut device, count, &content_page.dump())
+                .unwrap();
+            pages.push(count);
+        }
+        mgr.sync_all(&mut device).unwrap();
+
+        let mut mgr = PageManage::new(0);
+        mgr.free_map = Some(dir);
+        let reads = mgr.stats().reads;
+        let found = mgr.find_free_space(&mut device, 0, 3200).unwrap();
+        assert_eq!(found, Some(pages[150]));
+        let mut content_page =
+            ContentPage::load(pages[150], &mgr.get_data(&mut device, pages[150]).unwrap()).unwrap();
+        let entry = ContentEntry {
+            data: vec![0xBB; 3198],
+            ..Default::default()
         };
+        assert_eq!(content_page.push(entry), Ok(1));
+        /* the directory, one map page and the page itself */
+        assert_eq!(mgr.stats().reads - reads, 3);
+        mgr.modify(&mut device, pages[150], &content_page.dump())
+            .unwrap();
+        assert_eq!(mgr.find_free_space(&mut device, 0, 3200).unwrap(), None);
+        /* a smaller entry fits on an earlier page, a start skips the pages before it */
+        assert_eq!(
+            mgr.find_free_space(&mut device, 0, 2000).unwrap(),
+            Some(pages[(0..200).find(fits).unwrap()])
+        );
+        let last = (0..200).rev().find(fits).unwrap();
+        assert_eq!(
+            mgr.find_free_space(&mut device, pages[last], 2000).unwrap(),
+            Some(pages[last])
+        );
+        assert_eq!(
+            mgr.find_free_space(&mut device, pages[last] + 1, 2000)
+                .unwrap(),
+            None
+        );
+
+        /* a released page has no room, a map out of step is rebuilt */
+        mgr.release(&mut device, pages[last]).unwrap();
+        assert_ne!(
+            mgr.find_free_space(&mut device, 0, 2000).unwrap(),
+            Some(pages[last])
+        );
+        mgr.set_free_space(&mut device, pages[0], u8::MAX).unwrap();
+        assert_eq!(
+            mgr.find_free_space(&mut device, 0, 3200).unwrap(),
+            Some(pages[0])
+        );
+        mgr.rebuild_free_map(&mut device).unwrap();
+        assert_eq!(mgr.find_free_space(&mut device, 0, 3200).unwrap(), None);
+        assert_eq!(
+            mgr.find_free_space(&mut device, 0, 2000).unwrap(),
+            Some(pages[(0..200).find(fits).unwrap()])
+        );
     }
 }
@@ -1,6 +1,14 @@
 use crate::btree::*;
+use crate::catalog::*;
+use crate::codec::*;
 use crate::page::*;
+use crate::timestamp::*;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::io::{Result as IOResult, *};
+use std::ops::{Range, RangeInclusive};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub fn location_to_u64(content_page_count: u64, offset: u8) -> u64 {
     content_page_count << 8 | offset as u64
@@ -10,10 +18,158 @@ pub fn location_from_u64(u64_val: u64) -> (u64, u8) {
     (u64_val >> 8, (u64_val & 255) as u8)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
+pub enum TableError {
+    /** No record has this rowid */
+    RowNotFound(u64),
+    /** A record already has this rowid */
+    RowExists(u64),
+    /** A record does not have as many values as the table has columns */
+    ColumnCount { expected: usize, found: usize },
+    /** The stored record ends before the column the schema expects */
+    BrokenChain { rowid: u64, column: usize },
+    /** A record built by column name has a column the table does not have */
+    UnknownColumn(String),
+    /** A record built by column name has no value for this column */
+    MissingColumn(String),
+    /** Two columns have the same name */
+    DuplicateColumn(String),
+    /** A table needs at least one column */
+    NoColumns,
+    /** The column has no index */
+    NoIndex(usize),
+    /** An index key only holds the low 32 bits of a rowid */
+    IndexedRowid(u64),
+    /** The value of a unique column is already held by another record */
+    UniqueViolation {
+        column: usize,
+        rowid_of_existing: u64,
+    },
+    /** A value does not have the type of its column or is not a valid value of the type */
+    InvalidValue {
+        column: usize,
+        value_type: ValueType,
+    },
+    /** The values of a stored record do not match its checksum */
+    Corrupted { rowid: u64 },
+    /** A record has no value at this position */
+    NoValue(usize),
+    /** A typed getter of a record was used on a value of another type */
+    WrongType {
+        column: usize,
+        expected: ValueType,
+        found: ValueType,
+    },
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RowNotFound(rowid) => write!(f, "no record with rowid {}", rowid),
+            Self::RowExists(rowid) => write!(f, "a record with rowid {} already exists", rowid),
+            Self::ColumnCount { expected, found } => {
+                write!(f, "expected {} values, found {}", expected, found)
+            }
+            Self::BrokenChain { rowid, column } => write!(
+                f,
+                "record {} is corrupted: no stored value for column {}",
+                rowid, column
+            ),
+            Self::UnknownColumn(name) => write!(f, "no column named {}", name),
+            Self::MissingColumn(name) => write!(f, "no value for column {}", name),
+            Self::DuplicateColumn(name) => write!(f, "duplicate column name {}", name),
+            Self::NoColumns => write!(f, "a table needs at least one column"),
+            Self::NoIndex(column) => write!(f, "column {} has no index", column),
+            Self::IndexedRowid(rowid) => {
+                write!(f, "rowid {} is too large for a table with indexes", rowid)
+            }
+            Self::UniqueViolation {
+                column,
+                rowid_of_existing,
+            } => write!(
+                f,
+                "value of unique column {} is already held by record {}",
+                column, rowid_of_existing
+            ),
+            Self::Corrupted { rowid } => {
+                write!(f, "record with rowid {} does not match its checksum", rowid)
+            }
+            Self::NoValue(column) => write!(f, "the record has no value {}", column),
+            Self::WrongType {
+                column,
+                expected,
+                found,
+            } => write!(f, "value {} is a {:?}, not a {:?}", column, found, expected),
+            Self::InvalidValue { column, value_type } => {
+                write!(
+                    f,
+                    "value of column {} is not a valid {:?}",
+                    column, value_type
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TableError {}
+
+impl From<TableError> for Error {
+    fn from(err: TableError) -> Self {
+        let kind = match err {
+            TableError::RowNotFound(_) => ErrorKind::NotFound,
+            TableError::ColumnCount { .. } => ErrorKind::InvalidInput,
+            TableError::BrokenChain { .. } | TableError::Corrupted { .. } => ErrorKind::InvalidData,
+            TableError::UnknownColumn(_)
+            | TableError::MissingColumn(_)
+            | TableError::DuplicateColumn(_)
+            | TableError::NoColumns
+            | TableError::NoIndex(_)
+            | TableError::IndexedRowid(_)
+            | TableError::InvalidValue { .. }
+            | TableError::NoValue(_)
+            | TableError::WrongType { .. } => ErrorKind::InvalidInput,
+            TableError::RowExists(_) | TableError::UniqueViolation { .. } => {
+                ErrorKind::AlreadyExists
+            }
+        };
+        Error::new(kind, err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ValueType {
+    /** 8-byte big-endian u64 */
     Number,
     Bytes,
+    /** 8-byte big-endian i64 of milliseconds since the Unix epoch with its sign bit flipped, so
+     * that the bytes sort like the times and like the bytes of a `Number` */
+    Timestamp,
+}
+
+impl ValueType {
+    /** Byte identifying the type in the catalog */
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Number => 1,
+            Self::Bytes => 2,
+            Self::Timestamp => 3,
+        }
+    }
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Number),
+            2 => Some(Self::Bytes),
+            3 => Some(Self::Timestamp),
+            _ => None,
+        }
+    }
+    /** Size of every value of the type, `None` for variable-length types */
+    pub fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Self::Number | Self::Timestamp => Some(8),
+            Self::Bytes => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,133 +185,3248 @@ impl Value {
             data: data.to_vec(),
         }
     }
+    pub fn from_u64(number: u64) -> Self {
+        Self::new(ValueType::Number, &number.to_be_bytes())
+    }
+    pub fn from_bytes(data: &[u8]) -> Self {
+        Self::new(ValueType::Bytes, data)
+    }
+    /** Build bytes holding UTF-8 text */
+    pub fn from_text(text: &str) -> Self {
+        Self::new(ValueType::Bytes, text.as_bytes())
+    }
+    /** Decode a number */
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value_type {
+            ValueType::Number => Some(u64::from_be_bytes(self.data[..].try_into().ok()?)),
+            _ => None,
+        }
+    }
+    /** Build a timestamp of milliseconds since the Unix epoch */
+    pub fn from_timestamp_millis(millis: i64) -> Self {
+        Self::new(ValueType::Timestamp, &(millis ^ i64::MIN).to_be_bytes())
+    }
+    /** Build a timestamp, truncated to milliseconds */
+    pub fn from_system_time(time: SystemTime) -> Self {
+        let millis = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_millis() as i64,
+            Err(before) => -(before.duration().as_millis() as i64),
+        };
+        Self::from_timestamp_millis(millis)
+    }
+    /** Milliseconds since the Unix epoch of a timestamp */
+    pub fn as_timestamp_millis(&self) -> Option<i64> {
+        match self.value_type {
+            ValueType::Timestamp => {
+                Some(i64::from_be_bytes(self.data[..].try_into().ok()?) ^ i64::MIN)
+            }
+            _ => None,
+        }
+    }
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let millis = self.as_timestamp_millis()?;
+        if millis >= 0 {
+            UNIX_EPOCH.checked_add(Duration::from_millis(millis as u64))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_millis(millis.unsigned_abs()))
+        }
+    }
+    /** Parse a timestamp from RFC 3339 text */
+    pub fn from_rfc3339(text: &str) -> Option<Self> {
+        Some(Self::from_timestamp_millis(parse_rfc3339(text)?))
+    }
+    /** Render a timestamp as RFC 3339 text in UTC */
+    pub fn to_rfc3339(&self) -> Option<String> {
+        Some(format_rfc3339(self.as_timestamp_millis()?))
+    }
 }
 
+/** Values of a record, either in schema order or built by column name
+ *
+ * A queried record carries the column names of its table. A record to insert is built by
+ * column name with `set`, or positionally by pushing to `values` with `columns` left empty. */
 #[derive(Clone, Default, Debug)]
 pub struct Record {
     pub rowid: u64,
     pub values: Vec<Value>,
+    /** Name of each value, empty for a positional record */
+    pub columns: Vec<String>,
+    /** Content page and slot of each value, filled in by `query` and `insert` */
     pub location: Vec<(u64, u8)>,
 }
 
+impl Record {
+    /** Start a positional record, one value per call in schema order */
+    pub fn builder() -> RecordBuilder {
+        RecordBuilder::default()
+    }
+    /** Value at `column`, checked to have type `expected` */
+    fn typed(&self, column: usize, expected: ValueType) -> std::result::Result<&Value, TableError> {
+        let value = self.values.get(column).ok_or(TableError::NoValue(column))?;
+        if value.value_type != expected {
+            return Err(TableError::WrongType {
+                column,
+                expected,
+                found: value.value_type.clone(),
+            });
+        }
+        if expected
+            .fixed_size()
+            .is_some_and(|size| size != value.data.len())
+        {
+            return Err(TableError::InvalidValue {
+                column,
+                value_type: expected,
+            });
+        }
+        Ok(value)
+    }
+    /** Number at `column` */
+    pub fn u64(&self, column: usize) -> std::result::Result<u64, TableError> {
+        Ok(self.typed(column, ValueType::Number)?.as_u64().unwrap())
+    }
+    /** Bytes at `column` */
+    pub fn bytes(&self, column: usize) -> std::result::Result<&[u8], TableError> {
+        Ok(&self.typed(column, ValueType::Bytes)?.data)
+    }
+    /** Bytes at `column`, which must be UTF-8 text */
+    pub fn text(&self, column: usize) -> std::result::Result<&str, TableError> {
+        std::str::from_utf8(self.bytes(column)?).map_err(|_| TableError::InvalidValue {
+            column,
+            value_type: ValueType::Bytes,
+        })
+    }
+    /** Timestamp at `column` */
+    pub fn timestamp(&self, column: usize) -> std::result::Result<SystemTime, TableError> {
+        self.typed(column, ValueType::Timestamp)?
+            .to_system_time()
+            .ok_or(TableError::InvalidValue {
+                column,
+                value_type: ValueType::Timestamp,
+            })
+    }
+    /** Value of the column named `name` */
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        let index = self.columns.iter().position(|column| column == name)?;
+        self.values.get(index)
+    }
+    /** Set the value of the column named `name`, adding the column if the record has none */
+    pub fn set(&mut self, name: &str, value: Value) {
+        match self.columns.iter().position(|column| column == name) {
+            Some(index) => self.values[index] = value,
+            None => {
+                self.columns.push(name.to_owned());
+                self.values.push(value);
+            }
+        }
+    }
+}
+
+/** Builder of a positional record, from `Record::builder` */
 #[derive(Default)]
+pub struct RecordBuilder {
+    values: Vec<Value>,
+}
+
+impl RecordBuilder {
+    pub fn value(mut self, value: Value) -> Self {
+        self.values.push(value);
+        self
+    }
+    pub fn number(self, number: u64) -> Self {
+        self.value(Value::from_u64(number))
+    }
+    pub fn bytes(self, data: &[u8]) -> Self {
+        self.value(Value::from_bytes(data))
+    }
+    pub fn text(self, text: &str) -> Self {
+        self.value(Value::from_text(text))
+    }
+    pub fn timestamp(self, time: SystemTime) -> Self {
+        self.value(Value::from_system_time(time))
+    }
+    pub fn build(self) -> Record {
+        Record {
+            values: self.values,
+            ..Default::default()
+        }
+    }
+}
+
+/** 32-bit FNV-1a hash */
+fn hash32(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/** CRC-32 of the values of a record, each prefixed by its 4-byte length */
+fn record_checksum(values: &[Value]) -> u32 {
+    values.iter().fold(0, |crc, value| {
+        let crc = crc32(crc, &(value.data.len() as u32).to_be_bytes());
+        crc32(crc, &value.data)
+    })
+}
+
+/** Secondary index over the values of one column
+ *
+ * A B-tree whose ids hold the hash of the value in the high 32 bits and the rowid in the low 32
+ * bits, and whose pointers are rowids. Values sharing a hash are told apart by reading the
+ * record, and rowids of an indexed table must stay below `u32::MAX`. */
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub column: usize,
+    /** No two records may hold the same value in the column */
+    pub unique: bool,
+    /** Page count of the root B-tree node, kept on the same page like the root of a table */
+    pub root_page: u64,
+}
+
+impl Index {
+    fn key(data: &[u8], rowid: u64) -> u64 {
+        (hash32(data) as u64) << 32 | rowid
+    }
+    pub fn root_node<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<BtreeNode>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(BtreeNode::new(
+            self.root_page,
+            &mgr.get_data(device, self.root_page)?,
+        ))
+    }
+    fn add<D>(&self, device: &mut D, mgr: &mut PageManage, data: &[u8], rowid: u64) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        self.root_node(device, mgr)?
+            .insert_id(device, mgr, Self::key(data, rowid), rowid)
+    }
+    fn remove<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        data: &[u8],
+        rowid: u64,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        self.root_node(device, mgr)?
+            .remove_id(device, mgr, Self::key(data, rowid))
+    }
+    /** Rowids of the records whose value may be `data`, in ascending order */
+    fn candidates<D>(&self, device: &mut D, mgr: &mut PageManage, data: &[u8]) -> IOResult<Vec<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut found = Vec::new();
+        let start = Self::key(data, 0);
+        self.root_node(device, mgr)?.find_range(
+            device,
+            mgr,
+            &(start..start | u32::MAX as u64),
+            &mut found,
+        )?;
+        Ok(found.into_iter().map(|(_, rowid)| rowid).collect())
+    }
+}
+
+/** Cursor over the records of a table in ascending rowid order
+ *
+ * Only the next rowid to visit is kept between steps, so the table may be modified while
+ * scanning: the scan continues at the first rowid after the last one returned. */
+pub struct TableScan<'a> {
+    table: &'a Table,
+    next: Option<u64>,
+    /** Read content and overflow pages without caching them */
+    bypass: bool,
+}
+
+impl TableScan<'_> {
+    /** Read content and overflow pages through `PageManage::get_data_bypass`
+     *
+     * A one-shot scan or export then leaves the cache as it found it, apart from B-tree nodes. */
+    pub fn bypass_cache(mut self) -> Self {
+        self.bypass = true;
+        self
+    }
+    /** Read the next record, `None` once every record has been visited or after an error */
+    pub fn next<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> Option<IOResult<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let from = self.next?;
+        let found = self
+            .table
+            .root_node(device, mgr)
+            .and_then(|root_node| root_node.find_from(device, mgr, from));
+        let result = match found {
+            Ok(Some((rowid, node_val))) => {
+                self.next = rowid.checked_add(1);
+                if self.bypass {
+                    mgr.bypassing(|mgr| self.table.record_at(device, mgr, rowid, node_val))
+                } else {
+                    self.table.record_at(device, mgr, rowid, node_val)
+                }
+            }
+            Ok(None) => {
+                self.next = None;
+                return None;
+            }
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            self.next = None;
+        }
+        Some(result)
+    }
+    /** Read the next record whose value of `column` is `value`, reading only that value of the
+     * records skipped */
+    fn next_matching<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        column: usize,
+        value: &[u8],
+    ) -> Option<IOResult<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let result = self.step_matching(device, mgr, column, value).transpose()?;
+        if result.is_err() {
+            self.next = None;
+        }
+        Some(result)
+    }
+    fn step_matching<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        column: usize,
+        value: &[u8],
+    ) -> IOResult<Option<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let table = self.table;
+        let root_node = table.root_node(device, mgr)?;
+        while let Some(from) = self.next {
+            let Some((rowid, node_val)) = root_node.find_from(device, mgr, from)? else {
+                self.next = None;
+                break;
+            };
+            self.next = rowid.checked_add(1);
+            let entries = table.chain(device, mgr, rowid, node_val)?;
+            if table.entry_value(device, mgr, rowid, &entries, column)? == value {
+                return table
+                    .record_of_chain(device, mgr, rowid, &entries)
+                    .map(Some);
+            }
+        }
+        Ok(None)
+    }
+}
+
+/** Cursor over the records of a table whose value of a column is a value, from `Table::find`
+ *
+ * Without an index it steps like `TableScan`. Through an index the matching rowids are looked up
+ * when the cursor is made and each record is read as it is reached. The query is reported to the
+ * hooks once the last record has been returned. */
+pub struct TableFind<'a> {
+    scan: TableScan<'a>,
+    column: usize,
+    value: Vec<u8>,
+    /** Matching rowids left to read when the column has an index */
+    rowids: Option<std::vec::IntoIter<u64>>,
+    /** When the query started, `None` once it is over */
+    start: Option<Instant>,
+    rows: u64,
+}
+
+impl TableFind<'_> {
+    /** Read the next matching record, `None` once every record has been visited or after an
+     * error */
+    pub fn next<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> Option<IOResult<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let start = self.start?;
+        let table = self.scan.table;
+        let result = match &mut self.rowids {
+            Some(rowids) => rowids
+                .next()
+                .map(|rowid| table.record_of_rowid(device, mgr, rowid)),
+            None => self
+                .scan
+                .next_matching(device, mgr, self.column, &self.value),
+        };
+        match &result {
+            Some(Ok(_)) => self.rows += 1,
+            Some(Err(_)) => self.start = None,
+            None => {
+                self.start = None;
+                mgr.report_query(table.root_page, self.rows, start.elapsed());
+            }
+        }
+        result
+    }
+    /** Read every remaining matching record */
+    pub fn collect<D>(mut self, device: &mut D, mgr: &mut PageManage) -> IOResult<Vec<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut records = Vec::new();
+        while let Some(record) = self.next(device, mgr) {
+            records.push(record?);
+        }
+        Ok(records)
+    }
+}
+
+/** Cursor over the records of a table in descending rowid order
+ *
+ * The nodes from the root down to the current leaf are kept between steps, so each B-tree page
+ * is read once, and the table must not be modified while scanning. */
+pub struct TableScanRev<'a> {
+    table: &'a Table,
+    /** Each node with the count of its ids left to visit, `None` before the first step */
+    path: Option<Vec<(BtreeNode, usize)>>,
+}
+
+impl TableScanRev<'_> {
+    /** Read the previous record, `None` once every record has been visited or after an error */
+    pub fn next<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> Option<IOResult<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let result = self.step(device, mgr).transpose()?;
+        if result.is_err() {
+            self.path = Some(Vec::new());
+        }
+        Some(result)
+    }
+    fn step<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> IOResult<Option<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        if self.path.is_none() {
+            let root_node = self.table.root_node(device, mgr)?;
+            let len = root_node.len();
+            self.path = Some(vec![(root_node, len)]);
+        }
+        let path = self.path.as_mut().unwrap();
+        while let Some((node, left)) = path.last_mut() {
+            if *left == 0 {
+                path.pop();
+                continue;
+            }
+            *left -= 1;
+            let (id, ptr) = (node.ids[*left], node.ptrs[*left]);
+            if node.is_leaf() {
+                return self.table.record_at(device, mgr, id, ptr).map(Some);
+            }
+            let child = BtreeNode::new(ptr, &mgr.get_data(device, ptr)?);
+            let len = child.len();
+            path.push((child, len));
+        }
+        Ok(None)
+    }
+}
+
+/** Difference between an index and the records of its table, found by `Table::verify_index` */
+#[derive(Debug, PartialEq)]
+pub enum IndexMismatch {
+    /** The record has no entry */
+    Missing { rowid: u64 },
+    /** The entry matches no record */
+    Extra { key: u64, rowid: u64 },
+    /** The entry of the record points at another rowid */
+    WrongRowid { rowid: u64, found: u64 },
+}
+
+/** How `Table::insert` picks the rowid of a new record */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RowidMode {
+    /** The rowid after every rowid used before, the rowid of a deleted record is never reused */
+    #[default]
+    Increasing,
+    /** The first gap between the rowids in use, found by walking the B-tree */
+    ReuseGaps,
+}
+
+/** How the values of a record are stored in content entries */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RecordLayout {
+    /** One entry per value, each but the last prefixed by the location of the next one */
+    #[default]
+    Chained,
+    /** Every value in a single entry, read in one go
+     *
+     * |Start|End   |Description|
+     * |-----|------|-----------|
+     * |0    |2     |Count of values (c)|
+     * |2    |2+5c  |Value type and 4-byte length of each value|
+     * |2+5c |...   |Data of each value|
+     */
+    Packed,
+}
+
+impl RecordLayout {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Chained => 0,
+            Self::Packed => 1,
+        }
+    }
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Chained),
+            1 => Some(Self::Packed),
+            _ => None,
+        }
+    }
+}
+
+impl RowidMode {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Increasing => 0,
+            Self::ReuseGaps => 1,
+        }
+    }
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Increasing),
+            1 => Some(Self::ReuseGaps),
+            _ => None,
+        }
+    }
+}
+
+/** What `Table::upsert` did */
+#[derive(Debug, PartialEq)]
+pub enum UpsertOutcome {
+    Inserted,
+    Updated,
+}
+
+/** Storage taken by the records of a table, from `Table::stats`
+ *
+ * Only the B-tree of the table and the pages its records reach are counted, the B-trees of
+ * secondary indexes are left out. */
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TableStats {
+    pub records: u64,
+    /** Count of B-tree pages of each level, starting with the root */
+    pub btree_pages: Vec<u64>,
+    /** Count of content pages holding an entry of a record */
+    pub content_pages: u64,
+    /** Bytes of every value, without the locations chaining them or the header of a packed
+     * record */
+    pub payload_bytes: u64,
+    /** Count of content entries continued on overflow pages */
+    pub overflow_chains: u64,
+    /** Count of overflow pages over every chain */
+    pub overflow_pages: u64,
+    /** Average share of a content page used by its entries, shared pages count whole */
+    pub fill_factor: f64,
+}
+
 pub struct Table {
-    pub root_node: BtreeNode,
+    /** Page count of the root B-tree node
+     *
+     * A root split keeps the root on the same page, so the node itself is re-read from this page
+     * at the start of every operation instead of being held in memory, where it could go stale. */
+    pub root_page: u64,
     pub value_types: Vec<ValueType>,
+    /** Column names in schema order, empty when the columns are unnamed */
+    pub columns: Vec<String>,
+    /** Secondary indexes, kept up to date by every change to the table */
+    pub indexes: Vec<Index>,
+    /** `save_schema` persists a change */
+    pub rowid_mode: RowidMode,
+    /** Changed with `set_layout`, which rewrites every record */
+    pub layout: RecordLayout,
+    /** Default value of each column, set with `set_default` */
+    pub defaults: Vec<Option<Value>>,
+    /** Changed with `set_checksum`, which rewrites every record
+     *
+     * The first entry of each record then starts with the CRC-32 of its values, checked whenever
+     * the whole record is read. Reading only some values, like `query_slice` does, skips it. */
+    pub checksum: bool,
 }
 
 impl Table {
-    /** Query a record by rowid */
-    pub fn query<D>(&self, device: &mut D, mgr: &mut PageManage, rowid: u64) -> IOResult<Record>
+    /** Create an empty table of at least one column with a new root B-tree page */
+    pub fn create<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        value_types: Vec<ValueType>,
+    ) -> IOResult<Self>
     where
         D: Write + Read + Seek,
     {
-        let node_val = self.root_node.find_id(device, mgr, rowid).unwrap();
-        let (mut content_page_count, mut offset) = location_from_u64(node_val);
-        let mut rec = Record::default();
-
-        for i in 0..self.value_types.len() {
-            let content_page = ContentPage::load(&mgr.get_data(device, content_page_count)?);
+        if value_types.is_empty() {
+            return Err(TableError::NoColumns.into());
+        }
+        let root_page = mgr.alloc(device, PageType::BtreePage)?;
+        let mut root_node = BtreeNode::new_node(PAGE_TYPEID_BTREE_LEAF);
+        root_node.page_count = root_page.borrow().count;
+        root_page.borrow_mut().modify(&root_node.dump());
 
-            /* not the last value */
-            if i != self.value_types.len() - 1 {
-                let mut data = Vec::new();
-                data.extend(&content_page.entries[offset as usize].data);
+        Ok(Self {
+            root_page: root_node.page_count,
+            defaults: vec![None; value_types.len()],
+            checksum: false,
+            value_types,
+            columns: Vec::new(),
+            indexes: Vec::new(),
+            rowid_mode: RowidMode::default(),
+            layout: RecordLayout::default(),
+        })
+    }
+    /** Open a table whose schema was saved under `name` */
+    pub fn open<D>(device: &mut D, mgr: &mut PageManage, name: &str) -> IOResult<Self>
+    where
+        D: Write + Read + Seek,
+    {
+        let catalog = Catalog::open(device, mgr)?;
+        let entry = catalog
+            .find(name)
+            .ok_or_else(|| CatalogError::TableNotFound(name.to_owned()))?;
+        Ok(Self {
+            root_page: entry.root_page,
+            value_types: entry.value_types.clone(),
+            columns: entry.columns.clone(),
+            indexes: entry.indexes.clone(),
+            rowid_mode: entry.rowid_mode,
+            layout: entry.layout,
+            defaults: entry.defaults.clone(),
+            checksum: entry.checksum,
+        })
+    }
+    /** Save the schema of the table in the catalog under `name`, replacing any previous one */
+    pub fn save_schema<D>(&self, device: &mut D, mgr: &mut PageManage, name: &str) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut catalog = Catalog::open(device, mgr)?;
+        catalog.set(CatalogEntry {
+            name: name.to_owned(),
+            root_page: self.root_page,
+            value_types: self.value_types.clone(),
+            columns: self.columns.clone(),
+            indexes: self.indexes.clone(),
+            rowid_mode: self.rowid_mode,
+            layout: self.layout,
+            defaults: self.defaults.clone(),
+            checksum: self.checksum,
+        });
+        catalog.save(device, mgr)
+    }
+    /** Name the columns in schema order, `save_schema` persists them */
+    pub fn set_columns(&mut self, columns: Vec<String>) -> IOResult<()> {
+        Self::check_columns(&columns, self.value_types.len())?;
+        self.columns = columns;
+        Ok(())
+    }
+    /** Set the value given to `column` by an insert without one, `save_schema` persists it
+     *
+     * `None` removes the default. */
+    pub fn set_default(&mut self, column: usize, default: Option<Value>) -> IOResult<()> {
+        if column >= self.value_types.len() {
+            return Err(TableError::ColumnCount {
+                expected: column + 1,
+                found: self.value_types.len(),
+            }
+            .into());
+        }
+        if let Some(value) = &default {
+            self.check_value(column, value)?;
+        }
+        self.defaults[column] = default;
+        Ok(())
+    }
+    /** Append a column of type `value_type` with `default` to the table named `table_name` and
+     * save the new schema
+     *
+     * `name` names the column of a table with named columns and is `None` for unnamed columns.
+     * Records already stored read `default` for the new column, so it needs one unless the table
+     * is empty. A packed record keeps its count of values and is left as it is, so it reads
+     * `default` lazily. A chained record has no count: its last value is the one without a link,
+     * which the new schema would read as a link, so every chained record is rewritten with
+     * `default` appended. Those records only read right under the new schema, so it is saved here
+     * rather than left to the caller. */
+    pub fn add_column<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        table_name: &str,
+        name: Option<String>,
+        value_type: ValueType,
+        default: Option<Value>,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let column = self.value_types.len();
+        let mut columns = self.columns.clone();
+        columns.extend(name);
+        if !columns.is_empty() {
+            Self::check_columns(&columns, column + 1)?;
+        }
+        if default.is_none() && self.len(device, mgr)? != 0 {
+            let name = columns.get(column).cloned();
+            return Err(TableError::MissingColumn(name.unwrap_or(column.to_string())).into());
+        }
+        /* read with the schema they were written with */
+        let mut chains = Vec::new();
+        if self.layout == RecordLayout::Chained {
+            for (rowid, node_val) in self.rows(device, mgr)? {
+                let entries = self.chain(device, mgr, rowid, node_val)?;
+                let values = self.record_of_chain(device, mgr, rowid, &entries)?.values;
+                chains.push((rowid, entries, values));
+            }
+        }
+        self.value_types.push(value_type);
+        self.defaults.push(None);
+        if let Err(err) = self.set_default(column, default.clone()) {
+            self.value_types.pop();
+            self.defaults.pop();
+            return Err(err);
+        }
+        self.columns = columns;
 
-                if let Some(overflow_page) = content_page.entries[offset as usize].overflow_page {
-                    let mut page = OverflowPage::load(&mgr.get_data(device, overflow_page)?);
-                    data.extend(page.data);
-                    while let Some(next) = page.next {
-                        page = OverflowPage::load(&mgr.get_data(device, next)?);
-                        data.extend(page.data);
-                    }
-                }
-                rec.values.push(Value::new(ValueType::Bytes, &data[8..]));
-                (content_page_count, offset) = location_from_u64(u64::from_be_bytes(
-                    content_page.entries[offset as usize].data[0..8]
-                        .try_into()
-                        .unwrap(),
-                ));
-            } else {
-                let mut data = Vec::new();
-                data.extend(&content_page.entries[offset as usize].data);
-
-                if let Some(overflow_page) = content_page.entries[offset as usize].overflow_page {
-                    let mut page = OverflowPage::load(&mgr.get_data(device, overflow_page)?);
-                    data.extend(page.data);
-                    while let Some(next) = page.next {
-                        page = OverflowPage::load(&mgr.get_data(device, next)?);
-                        data.extend(page.data);
-                    }
+        let mut root_node = self.root_node(device, mgr)?;
+        let mut page_count = 0;
+        for (rowid, entries, mut values) in chains {
+            values.extend(default.clone());
+            Self::remove_entries(device, mgr, entries)?;
+            let location = Self::write_values(
+                device,
+                mgr,
+                self.layout,
+                self.checksum,
+                &values,
+                &mut page_count,
+            )?[0];
+            root_node.update_id(device, mgr, rowid, location_to_u64(location.0, location.1))?;
+        }
+        self.save_schema(device, mgr, table_name)
+    }
+    /** Check that `columns` names `count` columns, at least one, each with a different name */
+    pub fn check_columns(columns: &[String], count: usize) -> IOResult<()> {
+        if count == 0 {
+            return Err(TableError::NoColumns.into());
+        }
+        if columns.len() != count {
+            return Err(TableError::ColumnCount {
+                expected: count,
+                found: columns.len(),
+            }
+            .into());
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].contains(column) {
+                return Err(TableError::DuplicateColumn(column.clone()).into());
+            }
+        }
+        Ok(())
+    }
+    /** Index of the column named `name` */
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+    /** Values of a record in schema order
+     *
+     * A record built by column name is reordered, it must have a value for every column without
+     * a default and no other column. A positional record may leave out trailing columns with a
+     * default. Every value must have the type of its column. */
+    fn arrange(&self, record: Record) -> IOResult<Vec<Value>> {
+        let values = if record.columns.is_empty() {
+            let mut values = record.values;
+            for default in self.defaults.iter().skip(values.len()) {
+                match default {
+                    Some(default) => values.push(default.clone()),
+                    None => break,
                 }
-                rec.values.push(Value::new(ValueType::Bytes, &data));
             }
+            values
+        } else {
+            if let Some(unknown) = record
+                .columns
+                .iter()
+                .find(|column| self.column_index(column).is_none())
+            {
+                return Err(TableError::UnknownColumn(unknown.clone()).into());
+            }
+            self.columns
+                .iter()
+                .zip(&self.defaults)
+                .map(|(column, default)| {
+                    record
+                        .get(column)
+                        .or(default.as_ref())
+                        .cloned()
+                        .ok_or_else(|| TableError::MissingColumn(column.clone()))
+                })
+                .collect::<std::result::Result<_, _>>()?
+        };
+        if values.len() != self.value_types.len() {
+            return Err(TableError::ColumnCount {
+                expected: self.value_types.len(),
+                found: values.len(),
+            }
+            .into());
         }
-
-        Ok(rec)
+        for (column, value) in values.iter().enumerate() {
+            self.check_value(column, value)?;
+        }
+        Ok(values)
     }
-    /** Insert a record */
-    pub fn insert<D>(
+    /** Fail unless `value` is a valid value of the type of `column` */
+    fn check_value(&self, column: usize, value: &Value) -> IOResult<()> {
+        let value_type = &self.value_types[column];
+        let size_ok = value_type
+            .fixed_size()
+            .is_none_or(|size| value.data.len() == size);
+        if value.value_type != *value_type || !size_ok {
+            return Err(TableError::InvalidValue {
+                column,
+                value_type: value_type.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+    /** Index the values of `column`, `save_schema` persists the index
+     *
+     * Every existing record is added to the new index. A unique index fails if two records
+     * already hold the same value, an existing index of the column is made unique the same way.
+     * An index is never made non-unique again. */
+    pub fn create_index<D>(
         &mut self,
         device: &mut D,
         mgr: &mut PageManage,
-        record: Record,
-    ) -> IOResult<u64>
+        column: usize,
+        unique: bool,
+    ) -> IOResult<()>
     where
         D: Write + Read + Seek,
     {
-        let rowid = self.root_node.find_unused(device, mgr);
+        if column >= self.value_types.len() {
+            return Err(TableError::ColumnCount {
+                expected: column + 1,
+                found: self.value_types.len(),
+            }
+            .into());
+        }
+        let existing = self.indexes.iter().position(|index| index.column == column);
+        let rows = self.rows(device, mgr)?;
 
-        let mut page_count = mgr.find_page_by_type(device, 0, PAGE_TYPEID_CONTENT)?;
-        let mut last_location: Option<u64> = None;
-        for (count, val) in record.values.iter().enumerate() {
-            let mut entry = ContentEntry::from_bytes(device, mgr, &val.data)?;
-
-            /* not the last value */
-            if count != record.values.len() - 1 {
-                entry.data = {
-                    let mut data = vec![0; 8];
-                    data.extend(entry.data);
-                    data
+        let mut index = match existing {
+            Some(i) => self.indexes[i].clone(),
+            None => {
+                if let Some(&(rowid, _)) = rows.last() {
+                    if rowid >= u32::MAX as u64 {
+                        return Err(TableError::IndexedRowid(rowid).into());
+                    }
+                }
+                let root_page = mgr.alloc(device, PageType::BtreePage)?;
+                let mut root_node = BtreeNode::new_node(PAGE_TYPEID_BTREE_LEAF);
+                root_node.page_count = root_page.borrow().count;
+                root_page.borrow_mut().modify(&root_node.dump());
+                let index = Index {
+                    column,
+                    unique: false,
+                    root_page: root_node.page_count,
                 };
+                self.fill_index(device, mgr, &index, &rows)?;
+                index
             }
-
-            /* write to content page */
-            let mut content_page = ContentPage::load(&mgr.get_data(device, page_count)?);
-            loop {
-                if content_page.push(entry.clone()).is_ok() {
-                    mgr.modify(device, page_count, &content_page.dump())?;
-                    /* the first value */
-                    if count == 0 {
-                        let id = self.root_node.find_unused(device, mgr);
-                        /* set this location to btree node */
-                        self.root_node.insert_id(
-                            device,
-                            mgr,
-                            id,
-                            location_to_u64(page_count, content_page.entries.len() as u8 - 1),
-                        )?;
-                    } else {
-                        let (last_page_count, offset) = location_from_u64(last_location.unwrap());
-                        let mut last_content_page =
-                            ContentPage::load(&mgr.get_data(device, last_page_count)?);
-                        last_content_page.entries[offset as usize].data[0..8].copy_from_slice(
-                            &location_to_u64(page_count, content_page.entries.len() as u8 - 1)
-                                .to_be_bytes(),
-                        );
-
-                        mgr.modify(device, last_page_count, &last_content_page.dump())?;
+        };
+        if unique && !index.unique {
+            for &(rowid, _) in &rows {
+                let data = self.query_slice(device, mgr, rowid, column, 0, usize::MAX)?;
+                let matching = self.matching_rowids(device, mgr, &index, &data)?;
+                if let Some(&other) = matching.iter().find(|&&other| other != rowid) {
+                    if existing.is_none() {
+                        index.root_node(device, mgr)?.release(device, mgr)?;
+                    }
+                    return Err(TableError::UniqueViolation {
+                        column,
+                        rowid_of_existing: other,
                     }
-                    last_location = Some(location_to_u64(
-                        page_count,
-                        content_page.entries.len() as u8 - 1,
-                    ));
-                    break;
+                    .into());
                 }
-                page_count = mgr.find_page_by_type(device, page_count + 1, PAGE_TYPEID_CONTENT)?;
-                content_page = ContentPage::load(&mgr.get_data(device, page_count)?);
             }
+            index.unique = true;
+        }
+        match existing {
+            Some(i) => self.indexes[i] = index,
+            None => self.indexes.push(index),
+        }
+        Ok(())
+    }
+    /** Add every record of `rows` to `index` */
+    fn fill_index<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        index: &Index,
+        rows: &[(u64, u64)],
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        for &(rowid, node_val) in rows {
+            let entries = self.chain(device, mgr, rowid, node_val)?;
+            let data = self.entry_value(device, mgr, rowid, &entries, index.column)?;
+            index.add(device, mgr, &data, rowid)?;
+        }
+        Ok(())
+    }
+    /** Compare the index of `column` with the records of the table
+     *
+     * The key of every record is computed from its value and looked up in the index, which
+     * must hold nothing else. Every record and every index entry is read. */
+    pub fn verify_index<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        column: usize,
+    ) -> IOResult<Vec<IndexMismatch>>
+    where
+        D: Write + Read + Seek,
+    {
+        let index = self.index_of(column).ok_or(TableError::NoIndex(column))?;
+        let mut mismatches = Vec::new();
+        let mut expected = BTreeMap::new();
+        for (rowid, node_val) in self.rows(device, mgr)? {
+            /* no key holds such a rowid, see `Index::key` */
+            if rowid >= u32::MAX as u64 {
+                mismatches.push(IndexMismatch::Missing { rowid });
+                continue;
+            }
+            let entries = self.chain(device, mgr, rowid, node_val)?;
+            let data = self.entry_value(device, mgr, rowid, &entries, column)?;
+            expected.insert(Index::key(&data, rowid), rowid);
+        }
+        for (key, found_rowid) in index.root_node(device, mgr)?.find_all(device, mgr)? {
+            match expected.remove(&key) {
+                Some(rowid) if rowid == found_rowid => {}
+                Some(rowid) => mismatches.push(IndexMismatch::WrongRowid {
+                    rowid,
+                    found: found_rowid,
+                }),
+                None => mismatches.push(IndexMismatch::Extra {
+                    key,
+                    rowid: found_rowid,
+                }),
+            }
+        }
+        mismatches.extend(
+            expected
+                .into_values()
+                .map(|rowid| IndexMismatch::Missing { rowid }),
+        );
+        Ok(mismatches)
+    }
+    /** Rebuild the index of `column` from the records of the table
+     *
+     * The root stays on its page, so the schema doesn't change. A unique index is not checked
+     * again. A table holding a rowid no key can hold is refused, see `TableError::IndexedRowid`. */
+    pub fn reindex<D>(&self, device: &mut D, mgr: &mut PageManage, column: usize) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let index = self.index_of(column).ok_or(TableError::NoIndex(column))?;
+        let rows = self.rows(device, mgr)?;
+        if let Some(&(rowid, _)) = rows.last() {
+            if rowid >= u32::MAX as u64 {
+                return Err(TableError::IndexedRowid(rowid).into());
+            }
+        }
+        index
+            .root_node(device, mgr)?
+            .release_children(device, mgr)?;
+        self.fill_index(device, mgr, index, &rows)
+    }
+    /** Rowids of the records whose value of the indexed column is `data`, in ascending order */
+    fn matching_rowids<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        index: &Index,
+        data: &[u8],
+    ) -> IOResult<Vec<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut rowids = Vec::new();
+        for rowid in index.candidates(device, mgr, data)? {
+            /* another value with the same hash */
+            if self.query_slice(device, mgr, rowid, index.column, 0, usize::MAX)? == data {
+                rowids.push(rowid);
+            }
+        }
+        Ok(rowids)
+    }
+    /** Fail if a record other than `rowid` holds one of `values` in a unique column */
+    fn check_unique<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        values: &[Value],
+        rowid: Option<u64>,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            let matching = self.matching_rowids(device, mgr, index, &values[index.column].data)?;
+            if let Some(&other) = matching.iter().find(|&&other| Some(other) != rowid) {
+                return Err(TableError::UniqueViolation {
+                    column: index.column,
+                    rowid_of_existing: other,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+    fn index_of(&self, column: usize) -> Option<&Index> {
+        self.indexes.iter().find(|index| index.column == column)
+    }
+    /** Query every record whose value of `column` is `value` through the index of the column */
+    pub fn query_by_index<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        column: usize,
+        value: &Value,
+    ) -> IOResult<Vec<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let start = Instant::now();
+        let index = self.index_of(column).ok_or(TableError::NoIndex(column))?;
+        let records = self
+            .matching_rowids(device, mgr, index, &value.data)?
+            .into_iter()
+            .map(|rowid| self.record_of_rowid(device, mgr, rowid))
+            .collect::<IOResult<Vec<_>>>()?;
+        mgr.report_query(self.root_page, records.len() as u64, start.elapsed());
+        Ok(records)
+    }
+    /** Count of records, kept in the root B-tree node */
+    pub fn len<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(self.root_node(device, mgr)?.record_count)
+    }
+    /** Whether the table has no record, from the count kept in the root B-tree node */
+    pub fn is_empty<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<bool>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(self.len(device, mgr)? == 0)
+    }
+    /** Smallest rowid, reading one B-tree node per level and no content page */
+    pub fn min_rowid<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Option<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(self
+            .root_node(device, mgr)?
+            .min_id(device, mgr)?
+            .map(|(rowid, _)| rowid))
+    }
+    /** Largest rowid, reading one B-tree node per level and no content page */
+    pub fn max_rowid<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Option<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(self
+            .root_node(device, mgr)?
+            .max_id(device, mgr)?
+            .map(|(rowid, _)| rowid))
+    }
+    /** Count records by walking the B-tree, to verify `len` */
+    pub fn count_scan<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        self.root_node(device, mgr)?.count_ids(device, mgr)
+    }
+    /** Measure the storage of the table with one walk of its B-tree
+     *
+     * Every record is loaded as `query` would, so a broken record fails the walk. */
+    pub fn stats<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<TableStats>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut stats = TableStats::default();
+        let mut content_pages = BTreeSet::new();
+        let mut level = vec![self.root_node(device, mgr)?];
+        while !level.is_empty() {
+            stats.btree_pages.push(level.len() as u64);
+            let mut below = Vec::new();
+            for node in level {
+                if node.is_internal() {
+                    for &ptr in &node.ptrs {
+                        below.push(BtreeNode::new(ptr, &mgr.get_data(device, ptr)?));
+                    }
+                    continue;
+                }
+                for (&rowid, &node_val) in node.ids.iter().zip(&node.ptrs) {
+                    stats.records += 1;
+                    let entries = self.chain(device, mgr, rowid, node_val)?;
+                    for (i, ((content_page_count, _), entry)) in entries.into_iter().enumerate() {
+                        content_pages.insert(content_page_count);
+                        let mut len = entry.data.len();
+                        let mut next = entry.overflow_page;
+                        if next.is_some() {
+                            stats.overflow_chains += 1;
+                        }
+                        while let Some(count) = next {
+                            let page = OverflowPage::read(device, mgr, count)?;
+                            stats.overflow_pages += 1;
+                            len += page.data.len();
+                            next = page.next;
+                        }
+                        let overhead = match self.layout {
+                            RecordLayout::Packed => {
+                                let header = self.checksum_len();
+                                header + 2 + 5 * read_u16(&entry.data, header) as usize
+                            }
+                            RecordLayout::Chained => self.value_offset(i),
+                        };
+                        stats.payload_bytes += (len - overhead) as u64;
+                    }
+                }
+            }
+            level = below;
+        }
+        stats.content_pages = content_pages.len() as u64;
+        let mut used = 0;
+        for &count in &content_pages {
+            used += ContentPage::load(count, &mgr.get_data(device, count)?)?.total_size();
+        }
+        if !content_pages.is_empty() {
+            stats.fill_factor = used as f64 / (content_pages.len() * PAGE_SIZE) as f64;
+        }
+        Ok(stats)
+    }
+    /** Add `delta` to the count of records */
+    fn add_record_count<D>(&self, device: &mut D, mgr: &mut PageManage, delta: i64) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut root_node = self.root_node(device, mgr)?;
+        root_node.record_count = root_node.record_count.wrapping_add_signed(delta);
+        mgr.modify(device, self.root_page, &root_node.dump())
+    }
+    /** Load the root B-tree node */
+    pub fn root_node<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<BtreeNode>
+    where
+        D: Write + Read + Seek,
+    {
+        let page = mgr.get(device, self.root_page)?;
+        let node = BtreeNode::new(self.root_page, page.borrow().data());
+        Ok(node)
+    }
+    /** Load the content entry holding column `column` of a record
+     *
+     * Every location after the first one comes from the previous entry, so anything but a live
+     * entry on a content page means the stored record is shorter than the schema. A page is
+     * decoded once and kept in `pages`, `None` when it is not a used content page. */
+    fn load_entry<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        pages: &mut BTreeMap<u64, Option<ContentPage>>,
+        rowid: u64,
+        column: usize,
+        (content_page_count, offset): (u64, u8),
+    ) -> IOResult<ContentEntry>
+    where
+        D: Write + Read + Seek,
+    {
+        let page = match pages.entry(content_page_count) {
+            Entry::Occupied(loaded) => loaded.into_mut(),
+            Entry::Vacant(vacant) => {
+                let mut page = None;
+                if mgr.is_used(device, content_page_count)? {
+                    let data = mgr.get_data(device, content_page_count)?;
+                    if data[0] == PAGE_TYPEID_CONTENT {
+                        page = Some(ContentPage::load(content_page_count, &data)?);
+                    }
+                }
+                vacant.insert(page)
+            }
+        };
+        match page
+            .as_ref()
+            .and_then(|page| page.entries.get(offset as usize))
+        {
+            Some(entry) if !entry.deleted => Ok(entry.clone()),
+            _ => Err(TableError::BrokenChain { rowid, column }.into()),
+        }
+    }
+    /** Load every content entry of a record, starting at the location of its first value
+     *
+     * The whole chain is validated before anything is returned. A packed record is a chain of one
+     * entry. */
+    fn chain<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        node_val: u64,
+    ) -> IOResult<Vec<((u64, u8), ContentEntry)>>
+    where
+        D: Write + Read + Seek,
+    {
+        let count = self.value_types.len();
+        self.chain_prefix(device, mgr, &mut BTreeMap::new(), rowid, node_val, count)
+    }
+    /** Load the content entries of the first `count` values of a record, like `chain`
+     *
+     * Content pages are decoded through `pages`, see `load_entry`. */
+    #[allow(clippy::too_many_arguments)]
+    fn chain_prefix<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        pages: &mut BTreeMap<u64, Option<ContentPage>>,
+        rowid: u64,
+        node_val: u64,
+        count: usize,
+    ) -> IOResult<Vec<((u64, u8), ContentEntry)>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut location = location_from_u64(node_val);
+        if self.layout == RecordLayout::Packed {
+            let entry = Self::load_entry(device, mgr, pages, rowid, 0, location)?;
+            self.packed_lengths(device, mgr, rowid, &entry)?;
+            return Ok(vec![(location, entry)]);
+        }
+        let last = self.value_types.len() - 1;
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let entry = Self::load_entry(device, mgr, pages, rowid, i, location)?;
+            if entry.data.len() < self.value_offset(i) {
+                /* a missing link loses the next value */
+                let column = (i + 1).min(last);
+                return Err(TableError::BrokenChain { rowid, column }.into());
+            }
+            /* not the last value, the link ends the prefix */
+            let next = (i != last).then(|| read_u64(&entry.data, self.value_offset(i) - 8));
+            entries.push((location, entry));
+            if let Some(next) = next {
+                location = location_from_u64(next);
+            }
+        }
+        Ok(entries)
+    }
+    /** Store a content entry on the first content page from `page_count` on with room for it
+     *
+     * With a free-space map, only the pages it records with room are loaded, and a new content
+     * page is allocated when there is none. `page_count` is left at the page used, so consecutive
+     * entries search from there. */
+    fn place_entry<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        page_count: &mut u64,
+        entry: ContentEntry,
+    ) -> IOResult<(u64, u8)>
+    where
+        D: Write + Read + Seek,
+    {
+        if mgr.free_map.is_some() {
+            let size = entry.total_size();
+            let mut start = *page_count;
+            while let Some(candidate) = mgr.find_free_space(device, start, size)? {
+                let data = mgr.get_data(device, candidate)?;
+                if data[0] == PAGE_TYPEID_CONTENT {
+                    let mut content_page = ContentPage::load(candidate, &data)?;
+                    if let Ok(slot) = content_page.push(entry.clone()) {
+                        mgr.modify(device, candidate, &content_page.dump())?;
+                        *page_count = candidate;
+                        return Ok((candidate, slot as u8));
+                    }
+                }
+                /* the map is out of date for this page */
+                mgr.set_free_space(device, candidate, free_space_byte(&data))?;
+                start = candidate + 1;
+            }
+            let mut content_page = ContentPage::default();
+            let slot = content_page.push(entry).map_err(|()| {
+                Error::new(ErrorKind::InvalidInput, "content entry larger than a page")
+            })?;
+            *page_count = mgr.alloc(device, PageType::ContentPage)?.borrow().count;
+            mgr.modify(device, *page_count, &content_page.dump())?;
+            return Ok((*page_count, slot as u8));
+        }
+        *page_count = mgr.find_page_by_type(device, *page_count, PAGE_TYPEID_CONTENT)?;
+        loop {
+            let mut content_page =
+                ContentPage::load(*page_count, &mgr.get_data(device, *page_count)?)?;
+            if let Ok(slot) = content_page.push(entry.clone()) {
+                mgr.modify(device, *page_count, &content_page.dump())?;
+                return Ok((*page_count, slot as u8));
+            }
+            *page_count = mgr.find_page_by_type(device, *page_count + 1, PAGE_TYPEID_CONTENT)?;
+        }
+    }
+    /** Length of each value of a packed record, checked against the schema
+     *
+     * A record written before trailing columns were added stores fewer values, the missing ones
+     * must have a default. */
+    fn packed_lengths<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        entry: &ContentEntry,
+    ) -> IOResult<Vec<usize>>
+    where
+        D: Write + Read + Seek,
+    {
+        let count = self.value_types.len();
+        let header = entry.read_range(device, mgr, self.checksum_len(), 2 + 5 * count)?;
+        if header.len() < 2 {
+            return Err(TableError::BrokenChain { rowid, column: 0 }.into());
+        }
+        let stored = read_u16(&header, 0) as usize;
+        if stored > count || self.defaults[stored..].iter().any(Option::is_none) {
+            let column = stored.min(count);
+            return Err(TableError::BrokenChain { rowid, column }.into());
+        }
+        let mut lengths = Vec::with_capacity(stored);
+        for (column, value_type) in self.value_types[..stored].iter().enumerate() {
+            let ptr = 2 + 5 * column;
+            if header.len() < ptr + 5 || header[ptr] != value_type.id() {
+                return Err(TableError::BrokenChain { rowid, column }.into());
+            }
+            lengths.push(read_u32(&header, ptr + 1) as usize);
+        }
+        Ok(lengths)
+    }
+    /** Build the single content entry of a packed record, starting with `checksum` if any */
+    fn packed_entry<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        checksum: Option<u32>,
+        values: &[Value],
+    ) -> IOResult<ContentEntry>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut data: Vec<_> = checksum.iter().flat_map(|crc| crc.to_be_bytes()).collect();
+        data.extend((values.len() as u16).to_be_bytes());
+        for value in values {
+            data.push(value.value_type.id());
+            data.extend((value.data.len() as u32).to_be_bytes());
+        }
+        for value in values {
+            data.extend(&value.data);
+        }
+        ContentEntry::from_bytes(device, mgr, &data)
+    }
+    /** Build the content entry of a value, prefixed by the checksum of the record and the
+     * location of the next value if any */
+    fn value_entry<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        checksum: Option<u32>,
+        next: Option<u64>,
+        value: &Value,
+    ) -> IOResult<ContentEntry>
+    where
+        D: Write + Read + Seek,
+    {
+        /* the prefix counts towards the size limit of the entry like the value itself */
+        let mut data = Vec::new();
+        if let Some(checksum) = checksum {
+            data.extend(checksum.to_be_bytes());
+        }
+        if let Some(next) = next {
+            data.extend(next.to_be_bytes());
+        }
+        data.extend(&value.data);
+        ContentEntry::from_bytes(device, mgr, &data)
+    }
+    /** Query a record by rowid */
+    pub fn query<D>(&self, device: &mut D, mgr: &mut PageManage, rowid: u64) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        let start = Instant::now();
+        let rec = self.record_of_rowid(device, mgr, rowid)?;
+        mgr.report_query(self.root_page, 1, start.elapsed());
+        Ok(rec)
+    }
+    /** Record of `rowid`, without reporting a query */
+    fn record_of_rowid<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        let node_val = self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .ok_or(TableError::RowNotFound(rowid))?;
+        self.record_at(device, mgr, rowid, node_val)
+    }
+    /** Whether a record has `rowid`, found in the B-tree without reading any content page */
+    pub fn exists<D>(&self, device: &mut D, mgr: &mut PageManage, rowid: u64) -> IOResult<bool>
+    where
+        D: Write + Read + Seek,
+    {
+        Ok(self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .is_some())
+    }
+    /** Query the values of columns `columns` of a record, in the order they are given
+     *
+     * The chain is followed only up to the last requested column, and only the overflow pages of
+     * the requested values are read. The values of a packed record are back to back, so reaching
+     * one stored after a large value still reads the overflow pages before it. */
+    pub fn query_columns<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        columns: &[usize],
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        if let Some(&column) = columns
+            .iter()
+            .find(|&&column| column >= self.value_types.len())
+        {
+            return Err(TableError::ColumnCount {
+                expected: column + 1,
+                found: self.value_types.len(),
+            }
+            .into());
+        }
+        let start = Instant::now();
+        let node_val = self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .ok_or(TableError::RowNotFound(rowid))?;
+        let count = columns.iter().max().map_or(0, |last| last + 1);
+        let entries =
+            self.chain_prefix(device, mgr, &mut BTreeMap::new(), rowid, node_val, count)?;
+        let mut rec = Record {
+            rowid,
+            ..Default::default()
+        };
+        for &column in columns {
+            let data = self.entry_value(device, mgr, rowid, &entries, column)?;
+            rec.values
+                .push(Value::new(self.value_types[column].clone(), &data));
+            if let Some(name) = self.columns.get(column) {
+                rec.columns.push(name.clone());
+            }
+            let entry = match self.layout {
+                RecordLayout::Chained => column,
+                RecordLayout::Packed => 0,
+            };
+            rec.location.push(entries[entry].0);
+        }
+        mgr.report_query(self.root_page, 1, start.elapsed());
+        Ok(rec)
+    }
+    /** Query the records of `rowids`, in the same order, `None` for a rowid without a record
+     *
+     * Every rowid is looked up in the B-tree first. The records are then assembled in the order
+     * of the content page holding their first value, and each content page is decoded once for
+     * the whole call. */
+    pub fn query_many<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowids: &[u64],
+    ) -> IOResult<Vec<Option<Record>>>
+    where
+        D: Write + Read + Seek,
+    {
+        let start = Instant::now();
+        let root_node = self.root_node(device, mgr)?;
+        let mut found = Vec::new();
+        for (position, &rowid) in rowids.iter().enumerate() {
+            if let Some(node_val) = root_node.find_id(device, mgr, rowid)? {
+                found.push((node_val, position));
+            }
+        }
+        /* a location sorts by its content page first */
+        found.sort_unstable();
+        let mut pages = BTreeMap::new();
+        let mut records = vec![None; rowids.len()];
+        for (node_val, position) in found {
+            let rowid = rowids[position];
+            let count = self.value_types.len();
+            let entries = self.chain_prefix(device, mgr, &mut pages, rowid, node_val, count)?;
+            records[position] = Some(self.record_of_chain(device, mgr, rowid, &entries)?);
+        }
+        let rows = records.iter().flatten().count() as u64;
+        mgr.report_query(self.root_page, rows, start.elapsed());
+        Ok(records)
+    }
+    /** Query every record with a rowid in `range`, in ascending rowid order */
+    pub fn query_range<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        range: Range<u64>,
+    ) -> IOResult<Vec<Record>>
+    where
+        D: Write + Read + Seek,
+    {
+        let start = Instant::now();
+        let mut found = Vec::new();
+        if !range.is_empty() {
+            self.root_node(device, mgr)?
+                .find_range(device, mgr, &range, &mut found)?;
+        }
+        let records = found
+            .into_iter()
+            .map(|(rowid, node_val)| self.record_at(device, mgr, rowid, node_val))
+            .collect::<IOResult<Vec<_>>>()?;
+        mgr.report_query(self.root_page, records.len() as u64, start.elapsed());
+        Ok(records)
+    }
+    /** Data of the value of column `column` of a record held by `entries` */
+    fn entry_value<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        entries: &[((u64, u8), ContentEntry)],
+        column: usize,
+    ) -> IOResult<Vec<u8>>
+    where
+        D: Write + Read + Seek,
+    {
+        self.value_range(device, mgr, rowid, entries, column, 0, usize::MAX)
+    }
+    /** Read `len` bytes from `start` of the value of column `column` of a record held by
+     * `entries`, cut short at the end of the value */
+    #[allow(clippy::too_many_arguments)]
+    fn value_range<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        entries: &[((u64, u8), ContentEntry)],
+        column: usize,
+        start: usize,
+        len: usize,
+    ) -> IOResult<Vec<u8>>
+    where
+        D: Write + Read + Seek,
+    {
+        match self.layout {
+            RecordLayout::Chained => {
+                let skip = self.value_offset(column);
+                entries[column]
+                    .1
+                    .read_range(device, mgr, start.saturating_add(skip), len)
+            }
+            RecordLayout::Packed => {
+                let entry = &entries[0].1;
+                let lengths = self.packed_lengths(device, mgr, rowid, entry)?;
+                if column >= lengths.len() {
+                    let data = &self.defaults[column].as_ref().unwrap().data;
+                    let start = start.min(data.len());
+                    return Ok(data[start..start + len.min(data.len() - start)].to_vec());
+                }
+                let offset = self.checksum_len()
+                    + 2
+                    + 5 * lengths.len()
+                    + lengths[..column].iter().sum::<usize>();
+                let start = start.min(lengths[column]);
+                let len = len.min(lengths[column] - start);
+                entry.read_range(device, mgr, offset + start, len)
+            }
+        }
+    }
+    /** Materialize the record whose first value is at `node_val` */
+    fn record_at<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        node_val: u64,
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        let entries = self.chain(device, mgr, rowid, node_val)?;
+        self.record_of_chain(device, mgr, rowid, &entries)
+    }
+    /** Build the record whose content entries are `entries` */
+    fn record_of_chain<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        entries: &[((u64, u8), ContentEntry)],
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut rec = Record {
+            rowid,
+            columns: self.columns.clone(),
+            ..Default::default()
+        };
+
+        if self.layout == RecordLayout::Packed {
+            let (location, entry) = &entries[0];
+            let lengths = self.packed_lengths(device, mgr, rowid, entry)?;
+            let data = entry.read_data(device, mgr)?;
+            let mut ptr = self.checksum_len() + 2 + 5 * lengths.len();
+            for (i, &len) in lengths.iter().enumerate() {
+                let value = data
+                    .get(ptr..ptr + len)
+                    .ok_or(TableError::BrokenChain { rowid, column: i })?;
+                rec.values
+                    .push(Value::new(self.value_types[i].clone(), value));
+                rec.location.push(*location);
+                ptr += len;
+            }
+            self.verify_checksum(rowid, entries, &rec.values)?;
+            /* checked by `packed_lengths` to have a default */
+            for default in &self.defaults[lengths.len()..] {
+                rec.values.push(default.clone().unwrap());
+                rec.location.push(*location);
+            }
+            return Ok(rec);
+        }
+        for (i, (location, _)) in entries.iter().enumerate() {
+            let data = self.entry_value(device, mgr, rowid, entries, i)?;
+            rec.values
+                .push(Value::new(self.value_types[i].clone(), &data));
+            rec.location.push(*location);
+        }
+        self.verify_checksum(rowid, entries, &rec.values)?;
+        Ok(rec)
+    }
+    /** Fail unless `values`, the values stored by a record, match the checksum in `entries` */
+    fn verify_checksum(
+        &self,
+        rowid: u64,
+        entries: &[((u64, u8), ContentEntry)],
+        values: &[Value],
+    ) -> IOResult<()> {
+        if self.checksum && read_u32(&entries[0].1.data, 0) != record_checksum(values) {
+            return Err(TableError::Corrupted { rowid }.into());
+        }
+        Ok(())
+    }
+    /** Bytes of the checksum starting the first entry of a record, 0 without checksums */
+    fn checksum_len(&self) -> usize {
+        if self.checksum {
+            4
+        } else {
+            0
+        }
+    }
+    /** Offset of the data of the value of `column` in its entry of a chained record
+     *
+     * The first entry starts with the checksum, and every entry but the last one continues with
+     * the location of the next value. */
+    fn value_offset(&self, column: usize) -> usize {
+        let checksum = if column == 0 { self.checksum_len() } else { 0 };
+        let link = if column != self.value_types.len() - 1 {
+            8
+        } else {
+            0
+        };
+        checksum + link
+    }
+    /** Find every record whose value of `column` is `value`, in ascending rowid order
+     *
+     * Goes through the index of the column when it has one. Otherwise the returned cursor visits
+     * every record, reading only its value of `column` until it matches, so only one record is in
+     * memory at a time. `Number` and `Timestamp` values are equal exactly when their bytes are. */
+    pub fn find<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        column: usize,
+        value: &Value,
+    ) -> IOResult<TableFind<'_>>
+    where
+        D: Write + Read + Seek,
+    {
+        match self.value_types.get(column) {
+            None => {
+                return Err(TableError::ColumnCount {
+                    expected: column + 1,
+                    found: self.value_types.len(),
+                }
+                .into())
+            }
+            Some(value_type) if *value_type != value.value_type => {
+                return Err(TableError::InvalidValue {
+                    column,
+                    value_type: value_type.clone(),
+                }
+                .into())
+            }
+            Some(_) => {}
+        }
+        let start = Instant::now();
+        let rowids = match self.index_of(column) {
+            Some(index) => Some(
+                self.matching_rowids(device, mgr, index, &value.data)?
+                    .into_iter(),
+            ),
+            None => None,
+        };
+        Ok(TableFind {
+            scan: self.scan(),
+            column,
+            value: value.data.clone(),
+            rowids,
+            start: Some(start),
+            rows: 0,
+        })
+    }
+    /** Scan every record in ascending rowid order */
+    pub fn scan(&self) -> TableScan<'_> {
+        TableScan {
+            table: self,
+            next: Some(0),
+            bypass: false,
+        }
+    }
+    /** Scan every record in descending rowid order */
+    pub fn scan_rev(&self) -> TableScanRev<'_> {
+        TableScanRev {
+            table: self,
+            path: None,
+        }
+    }
+    /** Read `len` bytes from `start` of one value of a record
+     *
+     * Only the overflow pages covering the range are read, so a prefix of a large value is cheap.
+     * A range past the end of the value is cut short. */
+    pub fn query_slice<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        column: usize,
+        start: usize,
+        len: usize,
+    ) -> IOResult<Vec<u8>>
+    where
+        D: Write + Read + Seek,
+    {
+        let node_val = self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .ok_or(TableError::RowNotFound(rowid))?;
+        if column >= self.value_types.len() {
+            return Err(TableError::ColumnCount {
+                expected: column + 1,
+                found: self.value_types.len(),
+            }
+            .into());
+        }
+        let entries = self.chain(device, mgr, rowid, node_val)?;
+        self.value_range(device, mgr, rowid, &entries, column, start, len)
+    }
+    /** Rowid and B-tree value of every record, in ascending rowid order */
+    fn rows<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<Vec<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        self.root_node(device, mgr)?.find_all(device, mgr)
+    }
+    /** Rewrite every record with `layout`, `save_schema` persists the change
+     *
+     * Every record is read before anything is rewritten, so a broken record leaves the table
+     * untouched. Rowids and indexes stay as they are. */
+    pub fn set_layout<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        layout: RecordLayout,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if layout == self.layout {
+            return Ok(());
+        }
+        self.rewrite(device, mgr, layout, self.checksum)
+    }
+    /** Add a checksum to every record or drop it, `save_schema` persists the change
+     *
+     * Every record is rewritten like `set_layout` does. */
+    pub fn set_checksum<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        checksum: bool,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        if checksum == self.checksum {
+            return Ok(());
+        }
+        self.rewrite(device, mgr, self.layout, checksum)
+    }
+    /** Rewrite every record with `layout` and `checksum`, after reading all of them */
+    fn rewrite<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        layout: RecordLayout,
+        checksum: bool,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut records = Vec::new();
+        for (rowid, node_val) in self.rows(device, mgr)? {
+            let entries = self.chain(device, mgr, rowid, node_val)?;
+            let values = self.record_of_chain(device, mgr, rowid, &entries)?.values;
+            records.push((rowid, entries, values));
+        }
+        let mut root_node = self.root_node(device, mgr)?;
+        let mut page_count = 0;
+        for (rowid, entries, values) in records {
+            Self::remove_entries(device, mgr, entries)?;
+            let location =
+                Self::write_values(device, mgr, layout, checksum, &values, &mut page_count)?[0];
+            root_node.update_id(device, mgr, rowid, location_to_u64(location.0, location.1))?;
+        }
+        self.layout = layout;
+        self.checksum = checksum;
+        Ok(())
+    }
+    /** Renumber rowids contiguously from 0, keeping their order
+     *
+     * Only B-tree ids change, content entries stay where they are. New records follow the
+     * renumbered ones. Return every (old rowid, new rowid) pair so callers can fix references to
+     * the table. */
+    pub fn renumber<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> IOResult<Vec<(u64, u64)>>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut mapping = Vec::new();
+        let mut next = 0;
+        self.root_node(device, mgr)?
+            .renumber(device, mgr, &mut next, &mut mapping)?;
+        let mut root_node = self.root_node(device, mgr)?;
+        root_node.next_rowid = next;
+        mgr.modify(device, self.root_page, &root_node.dump())?;
+
+        /* every old key goes before any new one is added, a new key may equal an old one */
+        let mut moved = Vec::new();
+        for &(old, new) in mapping.iter().filter(|(old, new)| old != new) {
+            for index in &self.indexes {
+                let data = self.query_slice(device, mgr, new, index.column, 0, usize::MAX)?;
+                index.remove(device, mgr, &data, old)?;
+                moved.push((index, data, new));
+            }
+        }
+        for (index, data, new) in moved {
+            index.add(device, mgr, &data, new)?;
+        }
+        Ok(mapping)
+    }
+    /** Delete a record by rowid
+     *
+     * Content entries become tombstones whose slots later inserts reuse, content pages left
+     * without live entries and overflow pages are released. */
+    pub fn delete<D>(&mut self, device: &mut D, mgr: &mut PageManage, rowid: u64) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut root_node = self.root_node(device, mgr)?;
+        let node_val = root_node
+            .find_id(device, mgr, rowid)?
+            .ok_or(TableError::RowNotFound(rowid))?;
+        /* walk the whole chain first, so a broken record is left untouched */
+        let entries = self.chain(device, mgr, rowid, node_val)?;
+        self.remove_content(device, mgr, rowid, entries)?;
+        root_node.remove_id(device, mgr, rowid)?;
+        self.add_record_count(device, mgr, -1)
+    }
+    /** Delete every record whose rowid is in `range`, return how many were deleted
+     *
+     * Every record is read before anything is removed, so a broken record leaves the table
+     * untouched. The B-tree ids go in a single `BtreeNode::remove_range`. */
+    pub fn delete_range<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        range: &Range<u64>,
+    ) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut root_node = self.root_node(device, mgr)?;
+        let mut rows = Vec::new();
+        root_node.find_range(device, mgr, range, &mut rows)?;
+        let chains = rows
+            .into_iter()
+            .map(|(rowid, node_val)| Ok((rowid, self.chain(device, mgr, rowid, node_val)?)))
+            .collect::<IOResult<Vec<_>>>()?;
+        for (rowid, entries) in chains {
+            self.remove_content(device, mgr, rowid, entries)?;
+        }
+
+        let removed = root_node.remove_range(device, mgr, range)?;
+        self.add_record_count(device, mgr, -(removed as i64))?;
+        Ok(removed)
+    }
+    /** Delete every record, return how many were deleted
+     *
+     * Like `delete_range` over every rowid, but the B-trees of the table and of its indexes are
+     * released whole instead of id by id, only their roots stay as empty leaves. Rowids of the
+     * deleted records are not reused, as after `delete`. */
+    pub fn truncate<D>(&mut self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let mut root_node = self.root_node(device, mgr)?;
+        let rows = self.rows(device, mgr)?;
+        let chains = rows
+            .iter()
+            .map(|&(rowid, node_val)| self.chain(device, mgr, rowid, node_val))
+            .collect::<IOResult<Vec<_>>>()?;
+        for entries in chains {
+            Self::remove_entries(device, mgr, entries)?;
+        }
+
+        root_node.record_count = 0;
+        root_node.release_children(device, mgr)?;
+        for index in &self.indexes {
+            index
+                .root_node(device, mgr)?
+                .release_children(device, mgr)?;
+        }
+        Ok(rows.len() as u64)
+    }
+    /** Remove the index keys and content entries of a record, but not its B-tree id */
+    fn remove_content<D>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        entries: Vec<((u64, u8), ContentEntry)>,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        for index in &self.indexes {
+            let data = self.entry_value(device, mgr, rowid, &entries, index.column)?;
+            index.remove(device, mgr, &data, rowid)?;
+        }
+        Self::remove_entries(device, mgr, entries)
+    }
+    /** Remove content entries, releasing their overflow chains and the pages left empty */
+    fn remove_entries<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        entries: Vec<((u64, u8), ContentEntry)>,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        for ((content_page_count, offset), entry) in entries {
+            let mut content_page = ContentPage::load(
+                content_page_count,
+                &mgr.get_data(device, content_page_count)?,
+            )?;
+            content_page.remove(offset as usize);
+            if content_page.is_empty() {
+                mgr.release(device, content_page_count)?;
+            } else {
+                mgr.modify(device, content_page_count, &content_page.dump())?;
+            }
+            entry.release_overflow(device, mgr)?;
+        }
+        Ok(())
+    }
+    /** Replace the values of a record
+     *
+     * Each content entry is rewritten in its slot when it fits there, otherwise it moves to another
+     * content page and the location pointing to it is updated. Old overflow chains are released. */
+    pub fn update<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        record: Record,
+    ) -> IOResult<()>
+    where
+        D: Write + Read + Seek,
+    {
+        let values = self.arrange(record)?;
+        let mut root_node = self.root_node(device, mgr)?;
+        let node_val = root_node
+            .find_id(device, mgr, rowid)?
+            .ok_or(TableError::RowNotFound(rowid))?;
+        let entries = self.chain(device, mgr, rowid, node_val)?;
+        /* before anything is written, a conflict leaves the record as it was */
+        self.check_unique(device, mgr, &values, Some(rowid))?;
+        for index in &self.indexes {
+            let data = self.entry_value(device, mgr, rowid, &entries, index.column)?;
+            if data != values[index.column].data {
+                index.remove(device, mgr, &data, rowid)?;
+                index.add(device, mgr, &values[index.column].data, rowid)?;
+            }
+        }
+
+        /* from the last value, so the location of the next value is final when writing a link */
+        let checksum = self.checksum.then(|| record_checksum(&values));
+        let mut page_count = 0;
+        let mut next = None;
+        for (i, ((content_page_count, offset), old_entry)) in entries.into_iter().enumerate().rev()
+        {
+            old_entry.release_overflow(device, mgr)?;
+            let entry = match self.layout {
+                RecordLayout::Chained => {
+                    let checksum = checksum.filter(|_| i == 0);
+                    Self::value_entry(device, mgr, checksum, next, &values[i])?
+                }
+                RecordLayout::Packed => Self::packed_entry(device, mgr, checksum, &values)?,
+            };
+
+            let mut content_page = ContentPage::load(
+                content_page_count,
+                &mgr.get_data(device, content_page_count)?,
+            )?;
+            content_page.entries[offset as usize] = entry.clone();
+            let location = if content_page.total_size() <= PAGE_SIZE {
+                mgr.modify(device, content_page_count, &content_page.dump())?;
+                (content_page_count, offset)
+            } else {
+                /* does not fit in place any more */
+                content_page.remove(offset as usize);
+                if content_page.is_empty() {
+                    mgr.release(device, content_page_count)?;
+                } else {
+                    mgr.modify(device, content_page_count, &content_page.dump())?;
+                }
+                Self::place_entry(device, mgr, &mut page_count, entry)?
+            };
+            next = Some(location_to_u64(location.0, location.1));
+        }
+
+        if next != Some(node_val) {
+            root_node.update_id(device, mgr, rowid, next.unwrap())?;
+        }
+        Ok(())
+    }
+    /** Insert a record, positional or built by column name, under an unused rowid
+     *
+     * Return the record as stored: in schema order, with its rowid and the location of each value,
+     * like `query` gives it. */
+    pub fn insert<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        record: Record,
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        let rowid = self.new_rowid(device, mgr)?;
+        self.insert_with_rowid(device, mgr, rowid, record)
+    }
+    /** Insert a record under `rowid`, which no record may have, return it as `insert` does */
+    pub fn insert_with_rowid<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        record: Record,
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        if self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .is_some()
+        {
+            return Err(TableError::RowExists(rowid).into());
+        }
+        let values = self.arrange(record)?;
+        self.insert_values(device, mgr, rowid, values, &mut 0)
+    }
+    /** Insert a record under `rowid`, or replace the values of the record already there */
+    pub fn upsert<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        record: Record,
+    ) -> IOResult<UpsertOutcome>
+    where
+        D: Write + Read + Seek,
+    {
+        if self
+            .root_node(device, mgr)?
+            .find_id(device, mgr, rowid)?
+            .is_some()
+        {
+            self.update(device, mgr, rowid, record)?;
+            return Ok(UpsertOutcome::Updated);
+        }
+        let values = self.arrange(record)?;
+        self.insert_values(device, mgr, rowid, values, &mut 0)?;
+        Ok(UpsertOutcome::Inserted)
+    }
+    /** Insert records in order, return their rowids
+     *
+     * Every record is checked against the schema before anything is written. The search for a
+     * content page with room resumes where the previous record was placed instead of at the
+     * first page, and rowids following the previous one are taken without searching the B-tree
+     * for a gap. An error on a record, such as a unique conflict, leaves the records before it
+     * inserted.
+     *
+     * Into an empty table, the values of every record are written first and the B-trees are then
+     * built with `BtreeNode::bulk_load`, so each leaf is written once instead of once per record.
+     * A unique conflict within the records is then found before anything is written. */
+    pub fn insert_many<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        records: Vec<Record>,
+    ) -> IOResult<Vec<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        let values = records
+            .into_iter()
+            .map(|record| self.arrange(record))
+            .collect::<IOResult<Vec<_>>>()?;
+        let mut rowid = self.new_rowid(device, mgr)?;
+        let last = (values.len() as u64)
+            .checked_sub(1)
+            .and_then(|len| rowid.checked_add(len));
+        if let Some(last) = last {
+            if self.root_node(device, mgr)?.is_empty() {
+                return self.bulk_insert(device, mgr, rowid..=last, values);
+            }
+        }
+        let mut rowids = Vec::with_capacity(values.len());
+        let mut page_count = 0;
+        for values in values {
+            self.insert_values(device, mgr, rowid, values, &mut page_count)?;
+            rowids.push(rowid);
+            let root_node = self.root_node(device, mgr)?;
+            rowid = match rowid.checked_add(1) {
+                Some(next) if root_node.find_id(device, mgr, next)?.is_none() => next,
+                /* the rowid filled a gap */
+                _ => self.new_rowid(device, mgr)?,
+            };
+        }
+        Ok(rowids)
+    }
+    /** Insert the records of `values` under `rowids` into this empty table, see `insert_many` */
+    fn bulk_insert<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowids: RangeInclusive<u64>,
+        values: Vec<Vec<Value>>,
+    ) -> IOResult<Vec<u64>>
+    where
+        D: Write + Read + Seek,
+    {
+        if !self.indexes.is_empty() && *rowids.end() >= u32::MAX as u64 {
+            return Err(TableError::IndexedRowid(*rowids.end()).into());
+        }
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            let mut seen = BTreeMap::new();
+            for (rowid, values) in rowids.clone().zip(&values) {
+                if let Some(&other) = seen.get(&values[index.column].data) {
+                    return Err(TableError::UniqueViolation {
+                        column: index.column,
+                        rowid_of_existing: other,
+                    }
+                    .into());
+                }
+                seen.insert(&values[index.column].data, rowid);
+            }
+        }
+        let mut rows = Vec::with_capacity(values.len());
+        let mut keys = vec![Vec::new(); self.indexes.len()];
+        let mut page_count = 0;
+        for (rowid, values) in rowids.clone().zip(values) {
+            let location = Self::write_values(
+                device,
+                mgr,
+                self.layout,
+                self.checksum,
+                &values,
+                &mut page_count,
+            )?[0];
+            rows.push((rowid, location_to_u64(location.0, location.1)));
+            for (index, keys) in self.indexes.iter().zip(&mut keys) {
+                keys.push((Index::key(&values[index.column].data, rowid), rowid));
+            }
+            let bytes = values.iter().map(|value| value.data.len() as u64).sum();
+            mgr.report_insert(self.root_page, bytes);
+        }
+
+        let mut root_node = self.root_node(device, mgr)?;
+        root_node.record_count = rows.len() as u64;
+        root_node.next_rowid = root_node.next_rowid.max(rowids.end().saturating_add(1));
+        root_node.bulk_load(device, mgr, &rows)?;
+        /* the index of an empty table is empty, anything left in it is kept */
+        for (index, mut keys) in self.indexes.iter().zip(keys) {
+            let mut index_root = index.root_node(device, mgr)?;
+            if index_root.is_empty() {
+                keys.sort_unstable();
+                index_root.bulk_load(device, mgr, &keys)?;
+            } else {
+                for (key, rowid) in keys {
+                    index
+                        .root_node(device, mgr)?
+                        .insert_id(device, mgr, key, rowid)?;
+                }
+            }
+        }
+        Ok(rowids.collect())
+    }
+    /** Write the values of a new record under `rowid`, return the record as stored
+     *
+     * The search for a content page with room starts at `page_count`, which is left at the last
+     * page used. */
+    fn insert_values<D>(
+        &mut self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        rowid: u64,
+        values: Vec<Value>,
+        page_count: &mut u64,
+    ) -> IOResult<Record>
+    where
+        D: Write + Read + Seek,
+    {
+        /* before anything is written, a conflict leaves no partial record behind */
+        self.check_unique(device, mgr, &values, None)?;
+        if !self.indexes.is_empty() && rowid >= u32::MAX as u64 {
+            return Err(TableError::IndexedRowid(rowid).into());
+        }
+        let location =
+            Self::write_values(device, mgr, self.layout, self.checksum, &values, page_count)?;
+        self.root_node(device, mgr)?.insert_id(
+            device,
+            mgr,
+            rowid,
+            location_to_u64(location[0].0, location[0].1),
+        )?;
+        for index in &self.indexes {
+            index.add(device, mgr, &values[index.column].data, rowid)?;
+        }
+        let mut root_node = self.root_node(device, mgr)?;
+        root_node.record_count += 1;
+        /* once the largest rowid is used, inserting without a rowid fails */
+        root_node.next_rowid = root_node.next_rowid.max(rowid.saturating_add(1));
+        mgr.modify(device, self.root_page, &root_node.dump())?;
+        let bytes = values.iter().map(|value| value.data.len() as u64).sum();
+        mgr.report_insert(self.root_page, bytes);
+        Ok(Record {
+            rowid,
+            values,
+            columns: self.columns.clone(),
+            location,
+        })
+    }
+    /** Write the content entries of `values`, with a checksum if `checksum`, return the location
+     * of each one
+     *
+     * The search for a content page with room starts at `page_count`, which is left at the last
+     * page used. */
+    fn write_values<D>(
+        device: &mut D,
+        mgr: &mut PageManage,
+        layout: RecordLayout,
+        checksum: bool,
+        values: &[Value],
+        page_count: &mut u64,
+    ) -> IOResult<Vec<(u64, u8)>>
+    where
+        D: Write + Read + Seek,
+    {
+        let checksum = checksum.then(|| record_checksum(values));
+        if layout == RecordLayout::Packed {
+            let entry = Self::packed_entry(device, mgr, checksum, values)?;
+            let location = Self::place_entry(device, mgr, page_count, entry)?;
+            return Ok(vec![location; values.len()]);
+        }
+        let mut locations = Vec::with_capacity(values.len());
+        let mut last_location: Option<u64> = None;
+        for (count, val) in values.iter().enumerate() {
+            /* not the last value, the location of the next one is filled in once it is placed */
+            let next = (count != values.len() - 1).then_some(0);
+            let entry_checksum = checksum.filter(|_| count == 0);
+            let entry = Self::value_entry(device, mgr, entry_checksum, next, val)?;
+
+            /* write to content page */
+            let (content_page_count, slot) = Self::place_entry(device, mgr, page_count, entry)?;
+            let location = location_to_u64(content_page_count, slot);
+            locations.push((content_page_count, slot));
+            /* not the first value */
+            if count != 0 {
+                let (last_page_count, offset) = location_from_u64(last_location.unwrap());
+                let mut last_content_page =
+                    ContentPage::load(last_page_count, &mgr.get_data(device, last_page_count)?)?;
+                /* the link follows the checksum in the first entry */
+                let link = if count == 1 && checksum.is_some() {
+                    4
+                } else {
+                    0
+                };
+                write_u64(
+                    &mut last_content_page.entries[offset as usize].data,
+                    link,
+                    location,
+                );
+
+                mgr.modify(device, last_page_count, &last_content_page.dump())?;
+            }
+            last_location = Some(location);
+        }
+        Ok(locations)
+    }
+    /** Copy every record into a new table of another file, with the same schema and rowids
+     *
+     * Records are written in rowid order one after another, and the B-trees of the table and of
+     * its indexes are bulk-loaded once every record is written, so no page is left half empty. */
+    pub fn copy_packed<D, E>(
+        &self,
+        device: &mut D,
+        mgr: &mut PageManage,
+        target: &mut E,
+        target_mgr: &mut PageManage,
+    ) -> IOResult<Table>
+    where
+        D: Write + Read + Seek,
+        E: Write + Read + Seek,
+    {
+        let mut copy = Table::create(target, target_mgr, self.value_types.clone())?;
+        copy.columns = self.columns.clone();
+        copy.rowid_mode = self.rowid_mode;
+        copy.layout = self.layout;
+        copy.defaults = self.defaults.clone();
+        copy.checksum = self.checksum;
+        let mut rows = Vec::new();
+        let mut keys = vec![Vec::new(); self.indexes.len()];
+        let mut page_count = 0;
+
+        let root_node = self.root_node(device, mgr)?;
+        let mut next = Some(0);
+        while let Some(from) = next {
+            let Some((rowid, node_val)) = root_node.find_from(device, mgr, from)? else {
+                break;
+            };
+            next = rowid.checked_add(1);
+            let values = self.record_at(device, mgr, rowid, node_val)?.values;
+            let location = Self::write_values(
+                target,
+                target_mgr,
+                copy.layout,
+                copy.checksum,
+                &values,
+                &mut page_count,
+            )?[0];
+            rows.push((rowid, location_to_u64(location.0, location.1)));
+            for (index, keys) in self.indexes.iter().zip(&mut keys) {
+                keys.push((Index::key(&values[index.column].data, rowid), rowid));
+            }
+        }
+
+        let mut copy_root = copy.root_node(target, target_mgr)?;
+        copy_root.record_count = rows.len() as u64;
+        copy_root.next_rowid = root_node.next_rowid;
+        copy_root.bulk_load(target, target_mgr, &rows)?;
+        for (index, mut keys) in self.indexes.iter().zip(keys) {
+            let root_page = target_mgr.alloc(target, PageType::BtreePage)?;
+            let mut index_root = BtreeNode::new_node(PAGE_TYPEID_BTREE_LEAF);
+            index_root.page_count = root_page.borrow().count;
+            keys.sort_unstable();
+            index_root.bulk_load(target, target_mgr, &keys)?;
+            copy.indexes.push(Index {
+                root_page: index_root.page_count,
+                ..index.clone()
+            });
+        }
+        Ok(copy)
+    }
+    /** Rowid for a new record, picked as `rowid_mode` says */
+    fn new_rowid<D>(&self, device: &mut D, mgr: &mut PageManage) -> IOResult<u64>
+    where
+        D: Write + Read + Seek,
+    {
+        let root_node = self.root_node(device, mgr)?;
+        Ok(match self.rowid_mode {
+            RowidMode::Increasing => root_node.next_rowid,
+            RowidMode::ReuseGaps => root_node.find_unused(device, mgr)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    fn memory_db() -> Database<Cursor<Vec<u8>>> {
+        Database::open(Cursor::new(Vec::new()), PageManage::default()).unwrap()
+    }
+
+    /** Largest page count the bitmap marks as used, after syncing */
+    fn high_water(db: &mut Database<Cursor<Vec<u8>>>) -> u64 {
+        db.mgr.sync_all(&mut db.device).unwrap();
+        let pages = db.device.get_ref().len() as u64 / PAGE_SIZE as u64;
+        (0..pages)
+            .filter(|&count| db.mgr.is_used(&mut db.device, count).unwrap())
+            .max()
+            .unwrap()
+    }
+
+    fn fill(db: &mut Database<Cursor<Vec<u8>>>, table: &mut Table) {
+        for i in 0..600u64 {
+            /* every tenth value needs overflow pages */
+            let len = if i % 10 == 0 { 9000 } else { 40 };
+            let record = Record::builder()
+                .bytes(&vec![i as u8; len])
+                .number(i % 50)
+                .build();
+            table.insert(&mut db.device, &mut db.mgr, record).unwrap();
+        }
+    }
+
+    #[test]
+    fn scan_sees_a_root_split_it_crosses() {
+        let mut db = memory_db();
+        let mut writer = db
+            .create_table("t", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        let record = |i: u64| {
+            Record::builder()
+                .number(i)
+                .bytes(&i.to_be_bytes().repeat(4))
+                .build()
+        };
+        for i in 0..200 {
+            writer
+                .insert(&mut db.device, &mut db.mgr, record(i))
+                .unwrap();
+        }
+        assert!(writer
+            .root_node(&mut db.device, &mut db.mgr)
+            .unwrap()
+            .is_leaf());
+
+        /* a second handle on the same table, the scan borrows it while the writer splits the root */
+        let reader = db.table("t").unwrap();
+        let mut scan = reader.scan();
+        let mut seen = Vec::new();
+        while let Some(rec) = scan.next(&mut db.device, &mut db.mgr) {
+            let rec = rec.unwrap();
+            assert_eq!(rec.u64(0).unwrap(), rec.rowid);
+            assert_eq!(rec.bytes(1).unwrap(), rec.rowid.to_be_bytes().repeat(4));
+            seen.push(rec.rowid);
+            if rec.rowid == 99 {
+                for i in 200..600 {
+                    writer
+                        .insert(&mut db.device, &mut db.mgr, record(i))
+                        .unwrap();
+                }
+                assert!(writer
+                    .root_node(&mut db.device, &mut db.mgr)
+                    .unwrap()
+                    .is_internal());
+                /* behind the scan, then ahead of it */
+                writer.delete(&mut db.device, &mut db.mgr, 50).unwrap();
+                writer.delete(&mut db.device, &mut db.mgr, 150).unwrap();
+            }
+        }
+        let expected: Vec<u64> = (0..600).filter(|&rowid| rowid != 150).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn find_streams_the_matching_records() {
+        let mut db = memory_db();
+        let mut table = db
+            .create_table("t", vec![ValueType::Bytes, ValueType::Number])
+            .unwrap();
+        let value = Value::from_bytes(&[0; 5000]);
+        let mut found = table.find(&mut db.device, &mut db.mgr, 0, &value).unwrap();
+        assert!(found.next(&mut db.device, &mut db.mgr).is_none());
+
+        for i in 0..300u64 {
+            /* every tenth compared value lives mostly in overflow pages */
+            let bytes = if i % 10 == 0 {
+                vec![0; 5000]
+            } else {
+                vec![0; 50]
+            };
+            let rec = Record::builder().bytes(&bytes).number(i % 7).build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        }
+        let (queries, rows) = (db.mgr.stats().queries, db.mgr.stats().rows);
+        /* a second handle, the cursor borrows it while the table is changed */
+        let reader = db.table("t").unwrap();
+        let mut found = reader.find(&mut db.device, &mut db.mgr, 0, &value).unwrap();
+        let mut seen = Vec::new();
+        while let Some(rec) = found.next(&mut db.device, &mut db.mgr) {
+            let rec = rec.unwrap();
+            assert_eq!(rec.bytes(0).unwrap(), [0; 5000]);
+            seen.push(rec.rowid);
+            /* reported once the cursor is done */
+            assert_eq!(db.mgr.stats().queries, queries);
+            if rec.rowid == 100 {
+                /* a record ahead of the cursor is not read until it is reached */
+                table.delete(&mut db.device, &mut db.mgr, 200).unwrap();
+            }
+        }
+        let expected: Vec<u64> = (0..300).step_by(10).filter(|&i| i != 200).collect();
+        assert_eq!(seen, expected);
+        assert_eq!(db.mgr.stats().queries, queries + 1);
+        assert!(found.next(&mut db.device, &mut db.mgr).is_none());
+        assert_eq!(db.mgr.stats().queries, queries + 1);
+
+        /* through an index, each matching record is read as it is reached */
+        table
+            .create_index(&mut db.device, &mut db.mgr, 1, false)
+            .unwrap();
+        let mut found = table
+            .find(&mut db.device, &mut db.mgr, 1, &Value::from_u64(3))
+            .unwrap();
+        let first = found.next(&mut db.device, &mut db.mgr).unwrap().unwrap();
+        assert_eq!(first.rowid, 3);
+        let rest = found.collect(&mut db.device, &mut db.mgr).unwrap();
+        let rowids: Vec<u64> = rest.iter().map(|rec| rec.rowid).collect();
+        let expected: Vec<u64> = (10..300).step_by(7).collect();
+        assert_eq!(rowids, expected);
+        assert_eq!(db.mgr.stats().queries, queries + 2);
+        assert_eq!(db.mgr.stats().rows - rows, 29 + 1 + expected.len() as u64);
+    }
+
+    #[test]
+    fn duplicate_rowid_is_refused() {
+        let mut db = memory_db();
+        let mut table = db.create_table("t", vec![ValueType::Bytes]).unwrap();
+        let rowids: Vec<u64> = (0..50u8)
+            .map(|i| {
+                let rec = Record::builder().bytes(&[i]).build();
+                table
+                    .insert(&mut db.device, &mut db.mgr, rec)
+                    .unwrap()
+                    .rowid
+            })
+            .collect();
+        for (i, &rowid) in rowids.iter().enumerate() {
+            let rec = table.query(&mut db.device, &mut db.mgr, rowid).unwrap();
+            assert_eq!(rec.bytes(0).unwrap(), [i as u8]);
+        }
+
+        let rec = Record::builder().bytes(b"again").build();
+        let err = table
+            .insert_with_rowid(&mut db.device, &mut db.mgr, rowids[7], rec)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+        assert!(matches!(*err, TableError::RowExists(rowid) if rowid == rowids[7]));
+        /* the record under it is left alone */
+        let rec = table.query(&mut db.device, &mut db.mgr, rowids[7]).unwrap();
+        assert_eq!(rec.bytes(0).unwrap(), [7]);
+        assert_eq!(table.len(&mut db.device, &mut db.mgr).unwrap(), 50);
+    }
+
+    #[test]
+    fn table_without_columns_is_refused() {
+        let mut db = memory_db();
+        let no_columns = |err: Error| {
+            let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+            matches!(*err, TableError::NoColumns)
+        };
+        assert!(no_columns(db.create_table("t", Vec::new()).err().unwrap()));
+        assert!(no_columns(
+            db.create_table_with_columns("t", Vec::new()).err().unwrap()
+        ));
+        assert!(db.table_names().unwrap().is_empty());
+        let pages = db.mgr.stats().allocs;
+        assert!(no_columns(
+            Table::create(&mut db.device, &mut db.mgr, Vec::new())
+                .err()
+                .unwrap()
+        ));
+        assert_eq!(db.mgr.stats().allocs, pages);
+    }
+
+    #[test]
+    fn renumber_makes_rowids_dense() {
+        let mut db = memory_db();
+        let mut table = db
+            .create_table("t", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 0, true)
+            .unwrap();
+        /* sparse rowids over several B-tree levels */
+        let inserted: Vec<u64> = (0..700).map(|i| i * 7 + 3).collect();
+        for &rowid in &inserted {
+            let rec = Record::builder()
+                .number(rowid + 1000)
+                .bytes(&rowid.to_be_bytes())
+                .build();
+            table
+                .insert_with_rowid(&mut db.device, &mut db.mgr, rowid, rec)
+                .unwrap();
+        }
+        for &rowid in inserted.iter().step_by(3) {
+            table.delete(&mut db.device, &mut db.mgr, rowid).unwrap();
+        }
+        let old: Vec<u64> = inserted
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .map(|(_, &rowid)| rowid)
+            .collect();
+
+        let mapping = table.renumber(&mut db.device, &mut db.mgr).unwrap();
+        let n = old.len() as u64;
+        let expected: Vec<(u64, u64)> = old.iter().copied().zip(0..n).collect();
+        assert_eq!(mapping, expected);
+        for &(old, new) in &mapping {
+            let rec = table.query(&mut db.device, &mut db.mgr, new).unwrap();
+            assert_eq!(rec.bytes(1).unwrap(), old.to_be_bytes());
+            let found = table
+                .find(&mut db.device, &mut db.mgr, 0, &Value::from_u64(old + 1000))
+                .unwrap()
+                .collect(&mut db.device, &mut db.mgr)
+                .unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].rowid, new);
+            if old >= n {
+                let err = table.query(&mut db.device, &mut db.mgr, old).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::NotFound);
+            }
+        }
+        assert!(table
+            .verify_index(&mut db.device, &mut db.mgr, 0)
+            .unwrap()
+            .is_empty());
+        assert_eq!(table.len(&mut db.device, &mut db.mgr).unwrap(), n);
+        let rec = Record::builder().number(1).bytes(b"new").build();
+        let rec = table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        assert_eq!(rec.rowid, n);
+    }
+
+    #[test]
+    fn record_after_the_last_slot_goes_to_a_new_page() {
+        let mut db = memory_db();
+        let mut table = db.create_table("t", vec![ValueType::Bytes]).unwrap();
+        let mut locations = Vec::new();
+        for i in 0..=CONTENTPAGE_MAX_ENTRIES as u64 {
+            let rec = Record::builder().bytes(&[i as u8]).build();
+            let rec = table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+            locations.push(rec.location[0]);
+        }
+        let page = locations[0].0;
+        for (slot, location) in locations[..CONTENTPAGE_MAX_ENTRIES].iter().enumerate() {
+            assert_eq!(*location, (page, slot as u8));
+        }
+        let extra = locations[CONTENTPAGE_MAX_ENTRIES];
+        assert_ne!(extra.0, page);
+        assert_eq!(extra.1, 0);
+        for rowid in 0..=CONTENTPAGE_MAX_ENTRIES as u64 {
+            let rec = table.query(&mut db.device, &mut db.mgr, rowid).unwrap();
+            assert_eq!(rec.bytes(0).unwrap(), [rowid as u8]);
+        }
+
+        /* a removed entry frees its slot for the next record */
+        table.delete(&mut db.device, &mut db.mgr, 100).unwrap();
+        let rec = Record::builder().bytes(b"again").build();
+        let rec = table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+        assert_eq!(rec.location[0], (page, 100));
+        let rec = table.query(&mut db.device, &mut db.mgr, rec.rowid).unwrap();
+        assert_eq!(rec.bytes(0).unwrap(), b"again");
+    }
+
+    #[test]
+    fn dirty_limit_bounds_a_large_insert() {
+        /* without a cache limit, nothing but the dirty limit flushes pages */
+        let mut mgr = PageManage::new(0);
+        mgr.dirty_limit = 32;
+        let mut db = Database::open(Cursor::new(Vec::new()), mgr).unwrap();
+        let mut table = db
+            .create_table("t", vec![ValueType::Number, ValueType::Bytes])
+            .unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 0, false)
+            .unwrap();
+        table.save_schema(&mut db.device, &mut db.mgr, "t").unwrap();
+        let mut most = 0;
+        for i in 0..5000u64 {
+            let rec = Record::builder()
+                .number(i * 7919 % 5000)
+                .bytes(&[i as u8; 120])
+                .build();
+            table.insert(&mut db.device, &mut db.mgr, rec).unwrap();
+            most = most.max(db.mgr.dirty_count());
+        }
+        /* one insert dirties a handful of pages past the check made before each write */
+        assert!(most <= 32 + 8, "{} dirty pages", most);
+        assert!(db.device.get_ref().len() > 100 * PAGE_SIZE);
+        db.mgr.sync_all(&mut db.device).unwrap();
+
+        let mut db = Database::open(db.device, PageManage::default()).unwrap();
+        let table = db.table("t").unwrap();
+        assert_eq!(table.len(&mut db.device, &mut db.mgr).unwrap(), 5000);
+        for i in (0..5000u64).step_by(97) {
+            let rec = table.query(&mut db.device, &mut db.mgr, i).unwrap();
+            assert_eq!(rec.u64(0).unwrap(), i * 7919 % 5000);
+            assert_eq!(rec.bytes(1).unwrap(), [i as u8; 120]);
+        }
+        assert!(table
+            .verify_index(&mut db.device, &mut db.mgr, 0)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn create_index_refuses_the_largest_rowid() {
+        let mut db = memory_db();
+        let mut table = db.create_table("t", vec![ValueType::Number]).unwrap();
+        for rowid in [0, u64::MAX] {
+            let rec = Record::builder().number(rowid).build();
+            table
+                .insert_with_rowid(&mut db.device, &mut db.mgr, rowid, rec)
+                .unwrap();
+        }
+        let pages = high_water(&mut db);
+        let err = table
+            .create_index(&mut db.device, &mut db.mgr, 0, false)
+            .unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+        assert!(matches!(*err, TableError::IndexedRowid(u64::MAX)));
+        assert!(table.indexes.is_empty());
+        assert_eq!(high_water(&mut db), pages);
+    }
+
+    #[test]
+    fn verify_index_sees_the_largest_ids() {
+        let mut db = memory_db();
+        let mut table = db.create_table("t", vec![ValueType::Number]).unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 0, false)
+            .unwrap();
+        let last = u32::MAX as u64 - 1;
+        for rowid in (0..300).chain([last]) {
+            let rec = Record::builder().number(rowid % 13).build();
+            table
+                .insert_with_rowid(&mut db.device, &mut db.mgr, rowid, rec)
+                .unwrap();
+        }
+        let verify = |db: &mut Database<Cursor<Vec<u8>>>, table: &Table| {
+            table.verify_index(&mut db.device, &mut db.mgr, 0).unwrap()
+        };
+        assert!(verify(&mut db, &table).is_empty());
+
+        /* an entry under the largest key */
+        let index = table.indexes[0].clone();
+        index
+            .root_node(&mut db.device, &mut db.mgr)
+            .unwrap()
+            .insert_id(&mut db.device, &mut db.mgr, u64::MAX, 7)
+            .unwrap();
+        assert_eq!(
+            verify(&mut db, &table),
+            [IndexMismatch::Extra {
+                key: u64::MAX,
+                rowid: 7
+            }]
+        );
+        table.reindex(&mut db.device, &mut db.mgr, 0).unwrap();
+        assert!(verify(&mut db, &table).is_empty());
+        let found = table
+            .find(&mut db.device, &mut db.mgr, 0, &Value::from_u64(last % 13))
+            .unwrap()
+            .collect(&mut db.device, &mut db.mgr)
+            .unwrap();
+        assert_eq!(found.last().unwrap().rowid, last);
+
+        /* a record under the largest rowid, which no key can hold */
+        let node_val = table
+            .root_node(&mut db.device, &mut db.mgr)
+            .unwrap()
+            .find_id(&mut db.device, &mut db.mgr, 0)
+            .unwrap()
+            .unwrap();
+        table
+            .root_node(&mut db.device, &mut db.mgr)
+            .unwrap()
+            .insert_id(&mut db.device, &mut db.mgr, u64::MAX, node_val)
+            .unwrap();
+        assert_eq!(
+            verify(&mut db, &table),
+            [IndexMismatch::Missing { rowid: u64::MAX }]
+        );
+        let err = table.reindex(&mut db.device, &mut db.mgr, 0).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+        assert!(matches!(*err, TableError::IndexedRowid(u64::MAX)));
+        assert_eq!(
+            verify(&mut db, &table),
+            [IndexMismatch::Missing { rowid: u64::MAX }]
+        );
+    }
+
+    #[test]
+    fn insert_many_bulk_loads_an_empty_table() {
+        let records: Vec<Record> = (0..3000u64)
+            .map(|i| {
+                Record::builder()
+                    .number(i * 31 % 1000)
+                    .bytes(&[i as u8; 50])
+                    .build()
+            })
+            .collect();
+        let mut stats = Vec::new();
+        let mut dbs = Vec::new();
+        for bulk in [true, false] {
+            let mut db = Database::open(Cursor::new(Vec::new()), PageManage::new(64)).unwrap();
+            let mut table = db
+                .create_table("t", vec![ValueType::Number, ValueType::Bytes])
+                .unwrap();
+            table
+                .create_index(&mut db.device, &mut db.mgr, 0, false)
+                .unwrap();
+            let before = db.mgr.stats();
+            let rowids = if bulk {
+                table
+                    .insert_many(&mut db.device, &mut db.mgr, records.clone())
+                    .unwrap()
+            } else {
+                records
+                    .iter()
+                    .map(|rec| {
+                        let rec = table.insert(&mut db.device, &mut db.mgr, rec.clone());
+                        rec.unwrap().rowid
+                    })
+                    .collect()
+            };
+            db.mgr.sync_all(&mut db.device).unwrap();
+            let after = db.mgr.stats();
+            assert_eq!(rowids, (0..3000).collect::<Vec<_>>());
+            stats.push((
+                after.modifies - before.modifies,
+                after.writes - before.writes,
+            ));
+            dbs.push((db, table));
+        }
+        /* record by record, a table leaf, an index leaf and the root change for every record */
+        let (bulk, each) = (stats[0], stats[1]);
+        assert!(each.0 - bulk.0 >= 3 * 3000, "{:?}", stats);
+        assert!(bulk.1 < each.1, "{:?}", stats);
+
+        /* both ways store the same records and index */
+        let [(mut a, mut table_a), (mut b, table_b)] = <[_; 2]>::try_from(dbs).ok().unwrap();
+        for rowid in (0..3000).step_by(7) {
+            let rec_a = table_a.query(&mut a.device, &mut a.mgr, rowid).unwrap();
+            let rec_b = table_b.query(&mut b.device, &mut b.mgr, rowid).unwrap();
+            assert_eq!(rec_a.u64(0).unwrap(), rec_b.u64(0).unwrap());
+            assert_eq!(rec_a.bytes(1).unwrap(), rec_b.bytes(1).unwrap());
+        }
+        for number in [0, 17, 999] {
+            let value = Value::from_u64(number);
+            let found_a = table_a.find(&mut a.device, &mut a.mgr, 0, &value).unwrap();
+            let found_a = found_a.collect(&mut a.device, &mut a.mgr).unwrap();
+            let found_b = table_b.find(&mut b.device, &mut b.mgr, 0, &value).unwrap();
+            let found_b = found_b.collect(&mut b.device, &mut b.mgr).unwrap();
+            let rowids = |found: Vec<Record>| found.iter().map(|rec| rec.rowid).collect::<Vec<_>>();
+            assert_eq!(rowids(found_a), rowids(found_b));
+        }
+        assert!(table_a
+            .verify_index(&mut a.device, &mut a.mgr, 0)
+            .unwrap()
+            .is_empty());
+        assert_eq!(table_a.len(&mut a.device, &mut a.mgr).unwrap(), 3000);
+        let rec = Record::builder().number(1).bytes(b"next").build();
+        let rec = table_a.insert(&mut a.device, &mut a.mgr, rec).unwrap();
+        assert_eq!(rec.rowid, 3000);
+
+        /* a conflict within the records is found before anything is written */
+        let mut table = a.create_table("unique", vec![ValueType::Number]).unwrap();
+        table
+            .create_index(&mut a.device, &mut a.mgr, 0, true)
+            .unwrap();
+        let records = [1, 2, 3, 2].map(|number| Record::builder().number(number).build());
+        let err = table
+            .insert_many(&mut a.device, &mut a.mgr, records.to_vec())
+            .unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+        assert!(matches!(
+            *err,
+            TableError::UniqueViolation {
+                column: 0,
+                rowid_of_existing: 1
+            }
+        ));
+        assert!(table.is_empty(&mut a.device, &mut a.mgr).unwrap());
+    }
+
+    #[test]
+    fn truncate_reuses_released_pages() {
+        let mut db = memory_db();
+        let mut table = db
+            .create_table("t", vec![ValueType::Bytes, ValueType::Number])
+            .unwrap();
+        table
+            .create_index(&mut db.device, &mut db.mgr, 1, false)
+            .unwrap();
+        table.save_schema(&mut db.device, &mut db.mgr, "t").unwrap();
+        fill(&mut db, &mut table);
+        let full = high_water(&mut db);
+        for _ in 0..3 {
+            assert_eq!(table.truncate(&mut db.device, &mut db.mgr).unwrap(), 600);
+            assert_eq!(table.count_scan(&mut db.device, &mut db.mgr).unwrap(), 0);
+            fill(&mut db, &mut table);
+            assert!(high_water(&mut db) <= full);
+        }
+        assert!(table
+            .verify_index(&mut db.device, &mut db.mgr, 1)
+            .unwrap()
+            .is_empty());
+
+        db.drop_table("t").unwrap();
+        let mut table = db
+            .create_table("u", vec![ValueType::Bytes, ValueType::Number])
+            .unwrap();
+        fill(&mut db, &mut table);
+        assert!(high_water(&mut db) <= full);
+    }
+
+    #[test]
+    fn tiny_caches_run_a_full_cycle() {
+        for cache_size in [1, 2] {
+            let mut db =
+                Database::open(Cursor::new(Vec::new()), PageManage::new(cache_size)).unwrap();
+            let mut table = db
+                .create_table("t", vec![ValueType::Bytes, ValueType::Number])
+                .unwrap();
+            table
+                .create_index(&mut db.device, &mut db.mgr, 1, false)
+                .unwrap();
+            table.save_schema(&mut db.device, &mut db.mgr, "t").unwrap();
+            fill(&mut db, &mut table);
+            assert!(db.mgr.cached_pages().len() <= cache_size);
+
+            for rowid in (0..600).step_by(7) {
+                table.delete(&mut db.device, &mut db.mgr, rowid).unwrap();
+            }
+            db.mgr.sync_all(&mut db.device).unwrap();
+            let mut db = Database::open(
+                Cursor::new(db.device.into_inner()),
+                PageManage::new(cache_size),
+            )
+            .unwrap();
+            let table = db.table("t").unwrap();
+            for i in 0..600u64 {
+                let record = table.query(&mut db.device, &mut db.mgr, i);
+                if i % 7 == 0 {
+                    assert_eq!(record.unwrap_err().kind(), ErrorKind::NotFound);
+                    continue;
+                }
+                let record = record.unwrap();
+                let len = if i % 10 == 0 { 9000 } else { 40 };
+                assert_eq!(record.bytes(0).unwrap(), vec![i as u8; len]);
+                assert_eq!(record.u64(1).unwrap(), i % 50);
+            }
+            let matching = table
+                .query_by_index(&mut db.device, &mut db.mgr, 1, &Value::from_u64(3))
+                .unwrap();
+            assert_eq!(matching.len(), 10);
+            assert!(table
+                .verify_index(&mut db.device, &mut db.mgr, 1)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn add_column_reads_old_and_new_rows() {
+        for layout in [RecordLayout::Chained, RecordLayout::Packed] {
+            let mut db = memory_db();
+            let mut table = db
+                .create_table_with_columns(
+                    "t",
+                    vec![
+                        ("name".to_owned(), ValueType::Bytes),
+                        ("size".to_owned(), ValueType::Number),
+                    ],
+                )
+                .unwrap();
+            table
+                .set_layout(&mut db.device, &mut db.mgr, layout)
+                .unwrap();
+            table.save_schema(&mut db.device, &mut db.mgr, "t").unwrap();
+            let old = Record::builder().text("old").number(1).build();
+            let old = table
+                .insert(&mut db.device, &mut db.mgr, old)
+                .unwrap()
+                .rowid;
+            let large = Record::builder().bytes(&[7; 9000]).number(2).build();
+            let large = table
+                .insert(&mut db.device, &mut db.mgr, large)
+                .unwrap()
+                .rowid;
+            /* every content page holding the records, a packed record lists its page once for
+             * every value of the schema */
+            let content = |db: &mut Database<Cursor<Vec<u8>>>, table: &Table| {
+                let mut pages = BTreeMap::new();
+                for rowid in [old, large] {
+                    let rec = table.query(&mut db.device, &mut db.mgr, rowid).unwrap();
+                    for (page, _) in rec.location {
+                        pages.insert(page, db.mgr.get_data(&mut db.device, page).unwrap());
+                    }
+                }
+                pages
+            };
+            let before = content(&mut db, &table);
+
+            table
+                .add_column(
+                    &mut db.device,
+                    &mut db.mgr,
+                    "t",
+                    Some("tag".to_owned()),
+                    ValueType::Bytes,
+                    Some(Value::from_text("none")),
+                )
+                .unwrap();
+            /* packed records are left as they are, chained ones gain the default */
+            let after = content(&mut db, &table);
+            if layout == RecordLayout::Packed {
+                assert!(before == after);
+            } else {
+                assert!(before != after);
+            }
+            let new = Record::builder().text("new").number(3).text("set").build();
+            let new = table
+                .insert(&mut db.device, &mut db.mgr, new)
+                .unwrap()
+                .rowid;
+
+            /* nothing but `add_column` saved the schema */
+            db.mgr.sync_all(&mut db.device).unwrap();
+            let mut db =
+                Database::open(Cursor::new(db.device.into_inner()), PageManage::default()).unwrap();
+            let table = db.table("t").unwrap();
+            assert_eq!(table.columns, ["name", "size", "tag"]);
+            let old = table.query(&mut db.device, &mut db.mgr, old).unwrap();
+            assert_eq!(
+                (
+                    old.text(0).unwrap(),
+                    old.u64(1).unwrap(),
+                    old.text(2).unwrap()
+                ),
+                ("old", 1, "none")
+            );
+            let large = table.query(&mut db.device, &mut db.mgr, large).unwrap();
+            assert_eq!(large.bytes(0).unwrap(), [7; 9000]);
+            assert_eq!(large.text(2).unwrap(), "none");
+            let new = table.query(&mut db.device, &mut db.mgr, new).unwrap();
+            assert_eq!(
+                (
+                    new.text(0).unwrap(),
+                    new.u64(1).unwrap(),
+                    new.text(2).unwrap()
+                ),
+                ("new", 3, "set")
+            );
+        }
+    }
+
+    #[test]
+    fn bypass_scan_leaves_content_pages_uncached() {
+        let mut db = memory_db();
+        let mut table = db
+            .create_table("t", vec![ValueType::Bytes, ValueType::Number])
+            .unwrap();
+        fill(&mut db, &mut table);
+        db.mgr.sync_all(&mut db.device).unwrap();
+        let data = db.device.into_inner();
+
+        let cached_types = |bypass: bool| {
+            let mut db = Database::open(Cursor::new(data.clone()), PageManage::new(0)).unwrap();
+            let table = db.table("t").unwrap();
+            let before = db.mgr.cached_pages();
+            let mut scan = table.scan();
+            if bypass {
+                scan = scan.bypass_cache();
+            }
+            let mut count = 0;
+            while let Some(record) = scan.next(&mut db.device, &mut db.mgr) {
+                let record = record.unwrap();
+                let len = if record.rowid % 10 == 0 { 9000 } else { 40 };
+                assert_eq!(record.bytes(0).unwrap(), vec![record.rowid as u8; len]);
+                count += 1;
+            }
+            assert_eq!(count, 600);
+            let mut types: Vec<_> = db
+                .mgr
+                .cached_pages()
+                .into_iter()
+                .filter(|count| !before.contains(count))
+                .map(|count| db.mgr.get_data(&mut db.device, count).unwrap()[0])
+                .collect();
+            types.sort_unstable();
+            types.dedup();
+            types
+        };
+        let cached = cached_types(false);
+        assert!(cached.contains(&PAGE_TYPEID_CONTENT));
+        assert!(cached.contains(&PAGE_TYPEID_OVERFLOW));
+        let bypassed = cached_types(true);
+        assert!(!bypassed.contains(&PAGE_TYPEID_CONTENT));
+        assert!(!bypassed.contains(&PAGE_TYPEID_OVERFLOW));
+    }
+
+    /** Hooks collecting the count of every page read from the device */
+    struct ReadLog(std::rc::Rc<std::cell::RefCell<Vec<u64>>>);
+
+    impl crate::page::Hooks for ReadLog {
+        fn on_page_read(&mut self, count: u64) {
+            self.0.borrow_mut().push(count);
+        }
+    }
+
+    #[test]
+    fn slice_of_a_large_value_reads_few_pages() {
+        let mut db = memory_db();
+        let mut table = db.create_table("t", vec![ValueType::Bytes]).unwrap();
+        let value: Vec<u8> = (0..1 << 20).map(|i: u32| (i % 251) as u8).collect();
+        let record = Record::builder().bytes(&value).build();
+        let rowid = table
+            .insert(&mut db.device, &mut db.mgr, record)
+            .unwrap()
+            .rowid;
+        db.mgr.sync_all(&mut db.device).unwrap();
+        let data = db.device.into_inner();
+
+        let mut db = Database::open(Cursor::new(data.clone()), PageManage::default()).unwrap();
+        let table = db.table("t").unwrap();
+        let reads = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        db.mgr.set_hooks(Box::new(ReadLog(reads.clone())));
+        let slice = table
+            .query_slice(&mut db.device, &mut db.mgr, rowid, 0, 0, 256)
+            .unwrap();
+        assert_eq!(slice, value[..256]);
+        let overflow_reads = reads
+            .borrow()
+            .iter()
+            .filter(|&&count| data[count as usize * PAGE_SIZE] == PAGE_TYPEID_OVERFLOW)
+            .count();
+        assert!(
+            overflow_reads <= 1,
+            "{} overflow pages read",
+            overflow_reads
+        );
+        assert!(reads.borrow().len() <= 4, "{:?} read", reads.borrow());
+    }
+
+    #[test]
+    fn timestamp_bytes_sort_like_times() {
+        let millis = [i64::MIN, -86_400_000, -1, 0, 1, 1_700_000_000_123, i64::MAX];
+        let values: Vec<_> = millis
+            .iter()
+            .map(|&millis| Value::from_timestamp_millis(millis))
+            .collect();
+        for (value, &millis) in values.iter().zip(&millis) {
+            assert_eq!(value.as_timestamp_millis(), Some(millis));
+        }
+        assert!(values.windows(2).all(|pair| pair[0].data < pair[1].data));
+
+        let before = Value::from_rfc3339("1969-12-31T23:59:59.999Z").unwrap();
+        let after = Value::from_rfc3339("1970-01-01T00:00:00Z").unwrap();
+        assert_eq!(before.as_timestamp_millis(), Some(-1));
+        assert!(before.data < after.data);
+        let time = UNIX_EPOCH - Duration::from_millis(1234);
+        let value = Value::from_system_time(time);
+        assert_eq!(value.to_system_time(), Some(time));
+        assert_eq!(value.to_rfc3339().unwrap(), "1969-12-31T23:59:58.766Z");
+    }
+
+    #[test]
+    fn checksum_catches_a_flipped_byte() {
+        for layout in [RecordLayout::Chained, RecordLayout::Packed] {
+            let mut db = memory_db();
+            let mut table = db
+                .create_table("t", vec![ValueType::Bytes, ValueType::Number])
+                .unwrap();
+            table
+                .set_layout(&mut db.device, &mut db.mgr, layout)
+                .unwrap();
+            table
+                .set_checksum(&mut db.device, &mut db.mgr, true)
+                .unwrap();
+            let record = Record::builder().text("checked text").number(7).build();
+            let rowid = table
+                .insert(&mut db.device, &mut db.mgr, record)
+                .unwrap()
+                .rowid;
+            let other = Record::builder().text("untouched").number(8).build();
+            let other = table
+                .insert(&mut db.device, &mut db.mgr, other)
+                .unwrap()
+                .rowid;
+
+            let (page, _) = table
+                .query(&mut db.device, &mut db.mgr, rowid)
+                .unwrap()
+                .location[0];
+            let mut data = db.mgr.get_data(&mut db.device, page).unwrap();
+            let at = data
+                .windows(12)
+                .position(|window| window == b"checked text")
+                .unwrap();
+            data[at] ^= 1;
+            db.mgr.modify(&mut db.device, page, &data).unwrap();
+
+            let err = table.query(&mut db.device, &mut db.mgr, rowid).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            let err = err.into_inner().unwrap().downcast::<TableError>().unwrap();
+            assert!(matches!(*err, TableError::Corrupted { rowid: found } if found == rowid));
+            assert_eq!(
+                table
+                    .query(&mut db.device, &mut db.mgr, other)
+                    .unwrap()
+                    .text(0)
+                    .unwrap(),
+                "untouched"
+            );
         }
-        Ok(rowid)
     }
 }
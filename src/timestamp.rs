@@ -0,0 +1,131 @@
+/* Minimal RFC 3339 support for timestamps stored as milliseconds since the Unix epoch */
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/** Days since 1970-01-01 of a proleptic Gregorian date */
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/** Proleptic Gregorian date of days since 1970-01-01
+ *
+ * Return:
+ * * year
+ * * month
+ * * day */
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_digits(s: &str, start: usize, len: usize) -> Option<i64> {
+    let digits = s.get(start..start + len)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/** Parse `YYYY-MM-DDTHH:MM:SS[.fff][Z|+HH:MM|-HH:MM]` into milliseconds since the Unix epoch */
+pub fn parse_rfc3339(s: &str) -> Option<i64> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let year = parse_digits(s, 0, 4)?;
+    let month = parse_digits(s, 5, 2)?;
+    let day = parse_digits(s, 8, 2)?;
+    let hour = parse_digits(s, 11, 2)?;
+    let minute = parse_digits(s, 14, 2)?;
+    let second = parse_digits(s, 17, 2)?;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut ptr = 19;
+    let mut millis = 0;
+    /* fraction of a second, only the first 3 digits are kept */
+    if bytes[ptr] == b'.' {
+        ptr += 1;
+        let start = ptr;
+        while ptr < bytes.len() && bytes[ptr].is_ascii_digit() {
+            if ptr - start < 3 {
+                millis = millis * 10 + (bytes[ptr] - b'0') as i64;
+            }
+            ptr += 1;
+        }
+        if ptr == start {
+            return None;
+        }
+        for _ in ptr - start..3 {
+            millis *= 10;
+        }
+    }
+
+    let offset_minutes = match bytes.get(ptr)? {
+        b'Z' | b'z' if ptr + 1 == bytes.len() => 0,
+        sign @ (b'+' | b'-') if ptr + 6 == bytes.len() && bytes[ptr + 3] == b':' => {
+            let offset = parse_digits(s, ptr + 1, 2)? * 60 + parse_digits(s, ptr + 4, 2)?;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return None,
+    };
+
+    let seconds = hour * 3600 + minute * 60 + second - offset_minutes * 60;
+    Some(days_from_civil(year, month, day) * MILLIS_PER_DAY + seconds * 1000 + millis)
+}
+
+/** Format milliseconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SS[.fff]Z` */
+pub fn format_rfc3339(millis: i64) -> String {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let in_day = millis.rem_euclid(MILLIS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (in_day / 3_600_000, in_day / 60_000 % 60, in_day / 1000 % 60);
+    let mut formatted = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    );
+    if in_day % 1000 != 0 {
+        formatted.push_str(&format!(".{:03}", in_day % 1000));
+    }
+    formatted.push('Z');
+    formatted
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/** Empty directory of its own for a test, removed first if a previous run left it */
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("31database-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run(dir: &Path, args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_31database"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn demo_writes_then_read_only_lists() {
+    let dir = temp_dir("demo");
+    let db = dir.join("with space").join("new.db");
+
+    /* the parent directory is missing until --create-dirs */
+    let output = run(&dir, &["--db".as_ref(), db.as_os_str()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!db.exists());
+
+    let output = run(
+        &dir,
+        &["--db".as_ref(), db.as_os_str(), "--create-dirs".as_ref()],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "sss sss\nno record with rowid 1\n");
+    let output = run(&dir, &["--db".as_ref(), db.as_os_str()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "sss sss\nno record with rowid 2\n");
+
+    let len = std::fs::metadata(&db).unwrap().len();
+    let output = run(
+        &dir,
+        &["--read-only".as_ref(), "--db".as_ref(), db.as_os_str()],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "sss sss\nsss sss\n");
+    assert_eq!(std::fs::metadata(&db).unwrap().len(), len);
+
+    /* nothing is created for a read-only open */
+    let missing = dir.join("missing.db");
+    let output = run(
+        &dir,
+        &["--read-only".as_ref(), "--db".as_ref(), missing.as_os_str()],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!missing.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn exit_status_of_diff_and_bad_arguments() {
+    let dir = temp_dir("status");
+    let a = dir.join("a.db");
+    let b = dir.join("b.db");
+    for db in [&a, &b] {
+        assert!(run(&dir, &["--db".as_ref(), db.as_os_str()])
+            .status
+            .success());
+    }
+    let output = run(&dir, &["diff".as_ref(), a.as_os_str(), b.as_os_str()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let empty = dir.join("empty.db");
+    std::fs::write(&empty, []).unwrap();
+    let output = run(&dir, &["diff".as_ref(), a.as_os_str(), empty.as_os_str()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(&dir, &["--verbose".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage:"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}